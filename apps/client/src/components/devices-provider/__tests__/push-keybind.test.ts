@@ -2,6 +2,7 @@ import { describe, expect, it } from 'bun:test';
 import {
   formatPushKeybindLabel,
   matchesPushKeybind,
+  mouseButtonCode,
   normalizePushKeybind,
   pushKeybindFromKeyState
 } from '../push-keybind';
@@ -60,5 +61,15 @@ describe('push-keybind', () => {
   it('formats keybind labels for UI', () => {
     expect(formatPushKeybindLabel('Control+Shift+KeyV')).toBe('Ctrl + Shift + V');
     expect(formatPushKeybindLabel(undefined)).toBe('Not set');
+    expect(formatPushKeybindLabel('Shift+Mouse4')).toBe('Shift + Mouse 4');
+  });
+
+  it('maps side and middle mouse buttons to keybind codes', () => {
+    expect(mouseButtonCode(0)).toBeUndefined();
+    expect(mouseButtonCode(1)).toBe('Mouse3');
+    expect(mouseButtonCode(2)).toBeUndefined();
+    expect(mouseButtonCode(3)).toBe('Mouse4');
+    expect(mouseButtonCode(4)).toBe('Mouse5');
+    expect(mouseButtonCode(5)).toBe('Mouse6');
   });
 });
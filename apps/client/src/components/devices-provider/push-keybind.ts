@@ -26,6 +26,22 @@ const MODIFIER_CODES = new Set([
   'MetaRight'
 ]);
 
+// `MouseEvent.button` numbers to sidecar keybind codes. Left and right click
+// stay unbindable so they keep working in the settings screen.
+const MOUSE_BUTTON_CODES: Record<number, string> = {
+  1: 'Mouse3',
+  3: 'Mouse4',
+  4: 'Mouse5'
+};
+
+const mouseButtonCode = (button: number): string | undefined => {
+  if (button in MOUSE_BUTTON_CODES) {
+    return MOUSE_BUTTON_CODES[button];
+  }
+
+  return button > 4 ? `Mouse${button + 1}` : undefined;
+};
+
 const createDefaultKeybindState = (): TKeybindState => ({
   code: '',
   ctrlKey: false,
@@ -184,6 +200,7 @@ export type { TKeybindState };
 export {
   formatPushKeybindLabel,
   matchesPushKeybind,
+  mouseButtonCode,
   normalizePushKeybind,
   pushKeybindFromKeyState
 };
@@ -1,6 +1,7 @@
 import { useDevices } from '@/components/devices-provider/hooks/use-devices';
 import {
   formatPushKeybindLabel,
+  mouseButtonCode,
   pushKeybindFromKeyState
 } from '@/components/devices-provider/push-keybind';
 import { Alert, AlertDescription } from '@/components/ui/alert';
@@ -90,17 +91,16 @@ const Devices = memo(() => {
       return;
     }
 
-    const onKeyDown = (event: KeyboardEvent) => {
-      event.preventDefault();
-      event.stopPropagation();
-
-      if (event.code === 'Escape') {
-        setCapturingKeybindField(undefined);
+    const captureKeybind = (
+      event: KeyboardEvent | MouseEvent,
+      code: string | undefined
+    ) => {
+      if (!code) {
         return;
       }
 
       const nextKeybind = pushKeybindFromKeyState({
-        code: event.code,
+        code,
         ctrlKey: event.ctrlKey,
         altKey: event.altKey,
         shiftKey: event.shiftKey,
@@ -125,10 +125,36 @@ const Devices = memo(() => {
       setCapturingKeybindField(undefined);
     };
 
+    const onKeyDown = (event: KeyboardEvent) => {
+      event.preventDefault();
+      event.stopPropagation();
+
+      if (event.code === 'Escape') {
+        setCapturingKeybindField(undefined);
+        return;
+      }
+
+      captureKeybind(event, event.code);
+    };
+
+    const onMouseDown = (event: MouseEvent) => {
+      const code = mouseButtonCode(event.button);
+
+      if (!code) {
+        return;
+      }
+
+      event.preventDefault();
+      event.stopPropagation();
+      captureKeybind(event, code);
+    };
+
     window.addEventListener('keydown', onKeyDown, true);
+    window.addEventListener('mousedown', onMouseDown, true);
 
     return () => {
       window.removeEventListener('keydown', onKeyDown, true);
+      window.removeEventListener('mousedown', onMouseDown, true);
     };
   }, [
     capturingKeybindField,
@@ -332,7 +358,7 @@ const Devices = memo(() => {
                     onClick={() => startPushKeybindCapture('pushToTalkKeybind')}
                   >
                     {capturingKeybindField === 'pushToTalkKeybind'
-                      ? 'Press keys...'
+                      ? 'Press keys or a mouse button...'
                       : formatPushKeybindLabel(values.pushToTalkKeybind)}
                   </Button>
                   <Button
@@ -360,7 +386,7 @@ const Devices = memo(() => {
                     onClick={() => startPushKeybindCapture('pushToMuteKeybind')}
                   >
                     {capturingKeybindField === 'pushToMuteKeybind'
-                      ? 'Press keys...'
+                      ? 'Press keys or a mouse button...'
                       : formatPushKeybindLabel(values.pushToMuteKeybind)}
                   </Button>
                   <Button
@@ -182,7 +182,13 @@ export type TStartVoiceFilterInput = {
   echoCancellation: boolean;
 };
 
-export type TMicDevice = { id: string; label: string };
+export type TMicDevice = {
+  id: string;
+  label: string;
+  isDefault: boolean;
+  channels?: number;
+  sampleRate?: number;
+};
 export type TMicDevicesResult = { devices: TMicDevice[] };
 export type TStartVoiceFilterWithCaptureInput = TStartVoiceFilterInput & {
   deviceId?: string;
@@ -0,0 +1,299 @@
+//! Comfort noise for the voice filter chain.
+//!
+//! A closed noise gate leaves digital silence, which listeners take for a
+//! dropped connection and which makes the gate opening stand out. This stage
+//! learns the residual noise reaching the gate while nobody speaks, as an
+//! all-pole (LPC) spectrum the way RFC 3389 comfort noise does, and fills in
+//! what the gate takes away with noise shaped by it.
+
+use crate::dsp::GateBlock;
+use crate::params::check_range;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+const DEFAULT_LEVEL_DB: f32 = 0.0;
+const LEVEL_RANGE_DB: RangeInclusive<f32> = -40.0..=12.0;
+/// Enough poles for the broad shape of a noise floor at 48 kHz.
+const LPC_ORDER: usize = 10;
+/// Time constant of the noise estimate, counted in speech-free hops only.
+const ESTIMATE_MS: f32 = 500.0;
+/// Raises the zero lag by -40 dB of white noise so the model stays stable on
+/// strongly coloured or near-constant input.
+const WHITE_NOISE_CORRECTION: f64 = 1e-4;
+/// Residual below -120 dBFS is silence; nothing is injected for it.
+const MIN_POWER: f64 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ComfortNoiseOptions {
+    /// Level of the injected noise relative to the residual noise measured
+    /// while nobody speaks, in dB.
+    #[serde(default = "default_level_db")]
+    level_db: f32,
+}
+
+fn default_level_db() -> f32 {
+    DEFAULT_LEVEL_DB
+}
+
+impl Default for ComfortNoiseOptions {
+    fn default() -> Self {
+        Self {
+            level_db: DEFAULT_LEVEL_DB,
+        }
+    }
+}
+
+impl ComfortNoiseOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range("Comfort noise level", self.level_db, LEVEL_RANGE_DB, " dB")
+    }
+}
+
+/// All-pole noise model: `1 / A(z)` driven by white noise of `excitation`
+/// RMS.
+struct NoiseModel {
+    /// `a[1..=LPC_ORDER]` of `A(z) = 1 + a[1] z^-1 + ...`.
+    coefficients: [f32; LPC_ORDER],
+    excitation: f32,
+}
+
+impl NoiseModel {
+    /// Levinson-Durbin recursion on the autocorrelation of the noise.
+    fn fit(autocorrelation: &[f64; LPC_ORDER + 1]) -> Option<Self> {
+        if autocorrelation[0] <= MIN_POWER {
+            return None;
+        }
+
+        let mut a = [0.0_f64; LPC_ORDER + 1];
+        a[0] = 1.0;
+        let mut error = autocorrelation[0] * (1.0 + WHITE_NOISE_CORRECTION);
+        for order in 1..=LPC_ORDER {
+            let correlation: f64 = (0..order)
+                .map(|lag| a[lag] * autocorrelation[order - lag])
+                .sum();
+            let reflection = -correlation / error;
+            let previous = a;
+            for lag in 1..order {
+                a[lag] = previous[lag] + reflection * previous[order - lag];
+            }
+            a[order] = reflection;
+            error *= 1.0 - reflection * reflection;
+            if error <= 0.0 {
+                return None;
+            }
+        }
+
+        Some(Self {
+            coefficients: std::array::from_fn(|lag| a[lag + 1] as f32),
+            excitation: error.sqrt() as f32,
+        })
+    }
+}
+
+pub(crate) struct ComfortNoise {
+    level: f32,
+    channels: usize,
+    estimate_frames: f32,
+    /// Running autocorrelation of the speech-free residual, per sample.
+    autocorrelation: Option<[f64; LPC_ORDER + 1]>,
+    model: Option<NoiseModel>,
+    /// The last `LPC_ORDER` outputs of every channel, newest first.
+    history: Vec<[f32; LPC_ORDER]>,
+    /// xorshift32 state.
+    seed: u32,
+}
+
+impl ComfortNoise {
+    pub(crate) fn new(options: ComfortNoiseOptions, sample_rate: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+
+        Self {
+            level: 10.0_f32.powf(options.level_db / 20.0),
+            channels,
+            estimate_frames: ESTIMATE_MS * sample_rate as f32 / 1_000.0,
+            autocorrelation: None,
+            model: None,
+            history: vec![[0.0; LPC_ORDER]; channels],
+            seed: 0x9E37_79B9,
+        }
+    }
+
+    /// Learns from the speech-free hops of `gate` and adds noise to
+    /// `samples` wherever the gate attenuated them, in proportion to how far
+    /// it was closed.
+    pub(crate) fn process(&mut self, samples: &mut [f32], gate: &GateBlock) {
+        let mut start = 0;
+        for gate_hop in &gate.hops {
+            let hop = start * self.channels..(start + gate_hop.frames) * self.channels;
+            start += gate_hop.frames;
+            if !gate_hop.speech {
+                self.learn(&gate.residual[hop.clone()]);
+            }
+
+            let gain = (1.0 - gate_hop.gain) * self.level;
+            if gain > 0.0 {
+                self.fill(&mut samples[hop], gain);
+            }
+        }
+    }
+
+    fn learn(&mut self, residual: &[f32]) {
+        let frames = residual.len() / self.channels;
+        if frames <= LPC_ORDER {
+            return;
+        }
+
+        let mut hop = [0.0_f64; LPC_ORDER + 1];
+        for channel in 0..self.channels {
+            let signal: Vec<f64> = residual
+                .iter()
+                .skip(channel)
+                .step_by(self.channels)
+                .map(|sample| f64::from(*sample))
+                .collect();
+            for (lag, value) in hop.iter_mut().enumerate() {
+                *value += signal[lag..]
+                    .iter()
+                    .zip(&signal)
+                    .map(|(current, past)| current * past)
+                    .sum::<f64>();
+            }
+        }
+        let samples = (frames * self.channels) as f64;
+        hop.iter_mut().for_each(|value| *value /= samples);
+
+        let autocorrelation = match &mut self.autocorrelation {
+            Some(autocorrelation) => {
+                let weight = 1.0 - f64::from((-(frames as f32) / self.estimate_frames).exp());
+                for (value, update) in autocorrelation.iter_mut().zip(hop) {
+                    *value += (update - *value) * weight;
+                }
+                autocorrelation
+            }
+            None => self.autocorrelation.insert(hop),
+        };
+        self.model = NoiseModel::fit(autocorrelation);
+    }
+
+    fn fill(&mut self, samples: &mut [f32], gain: f32) {
+        let Some(model) = &self.model else {
+            return;
+        };
+
+        for frame in samples.chunks_mut(self.channels) {
+            for (sample, history) in frame.iter_mut().zip(self.history.iter_mut()) {
+                // Uniform white noise of unit variance.
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 17;
+                self.seed ^= self.seed << 5;
+                let white = (self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0) * 3.0_f32.sqrt();
+
+                let prediction: f32 = model
+                    .coefficients
+                    .iter()
+                    .zip(history.iter())
+                    .map(|(coefficient, past)| coefficient * past)
+                    .sum();
+                let noise = white * model.excitation - prediction;
+                history.copy_within(..LPC_ORDER - 1, 1);
+                history[0] = noise;
+
+                *sample += noise * gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComfortNoise, ComfortNoiseOptions};
+    use crate::dsp::{GateBlock, GateHop};
+
+    const HOP: usize = 480;
+
+    /// Low-pass coloured noise, well under 0.01 RMS.
+    fn residual(length: usize) -> Vec<f32> {
+        let mut seed = 12_345_u32;
+        let mut previous = 0.0_f32;
+        (0..length)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let white = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+                previous = 0.9 * previous + 0.01 * white;
+                previous
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// Correlation of neighbouring samples; near 0 for white noise.
+    fn lag_one_correlation(samples: &[f32]) -> f32 {
+        let lagged: f32 = samples.windows(2).map(|pair| pair[0] * pair[1]).sum();
+        lagged / samples.iter().map(|sample| sample * sample).sum::<f32>()
+    }
+
+    fn block(residual: Vec<f32>, channels: usize, gain: f32, speech: bool) -> GateBlock {
+        let frames = residual.len() / channels;
+        let hops = (0..frames)
+            .step_by(HOP)
+            .map(|start| GateHop {
+                frames: HOP.min(frames - start),
+                gain,
+                speech,
+            })
+            .collect();
+        GateBlock { residual, hops }
+    }
+
+    #[test]
+    fn fills_a_closed_gate_with_matched_noise() {
+        let mut comfort_noise = ComfortNoise::new(ComfortNoiseOptions::default(), 48_000, 1);
+        let residual = residual(48_000);
+        let mut samples = vec![0.0; residual.len()];
+        comfort_noise.process(&mut samples, &block(residual.clone(), 1, 0.0, false));
+
+        let tail = 24_000..48_000;
+        let (expected, actual) = (rms(&residual[tail.clone()]), rms(&samples[tail.clone()]));
+        assert!(
+            (actual / expected - 1.0).abs() < 0.2,
+            "{actual} vs {expected}"
+        );
+        let correlation = lag_one_correlation(&samples[tail]);
+        assert!((correlation - 0.9).abs() < 0.05, "{correlation}");
+
+        let options: ComfortNoiseOptions =
+            serde_json::from_value(serde_json::json!({ "levelDb": 20.0 })).unwrap();
+        assert_eq!(
+            options.validate().unwrap_err(),
+            "Comfort noise level must be between -40 and 12 dB."
+        );
+    }
+
+    #[test]
+    fn leaves_an_open_gate_and_speech_alone() {
+        let options: ComfortNoiseOptions =
+            serde_json::from_value(serde_json::json!({ "levelDb": -6.0 })).unwrap();
+        let mut comfort_noise = ComfortNoise::new(options, 48_000, 2);
+
+        // Nothing is learned from speech, so nothing is injected either.
+        let mut samples = vec![0.0; 9_600];
+        comfort_noise.process(&mut samples, &block(vec![0.5; 9_600], 2, 0.0, true));
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+
+        let speech = residual(9_600);
+        let mut samples = speech.clone();
+        comfort_noise.process(&mut samples, &block(residual(9_600), 2, 1.0, false));
+        assert_eq!(samples, speech);
+
+        // Half closed: half the -6 dB noise on top of the signal.
+        let mut samples = vec![0.0; 96_000];
+        comfort_noise.process(&mut samples, &block(residual(96_000), 2, 0.5, false));
+        let ratio = rms(&samples[48_000..]) / rms(&residual(96_000)[48_000..]);
+        assert!((ratio - 0.25).abs() < 0.05, "{ratio}");
+    }
+}
@@ -0,0 +1,261 @@
+//! Panic handling and crash reports.
+//!
+//! Code that can recover from a panic (capture threads, voice filter
+//! processing, request handlers) runs inside [`catch_panics`] and turns the
+//! panic into an `*.ended` event or an error response. Any other panic is
+//! unrecoverable: the hook emits a `sidecar.fatal` event with the backtrace and
+//! aborts so the desktop app restarts the sidecar instead of talking to a
+//! half-dead process. Every panic writes a JSON crash report that the desktop
+//! app can attach to bug reports.
+//!
+//! The hook itself never goes through the shared stdout writer, since the
+//! panicking thread may hold it: fatal panics are logged to stderr only, and
+//! recoverable ones are logged by [`catch_panics`] once the unwind finished.
+
+use crate::logging::{self, LogLevel};
+use crate::{now_unix_ms, SidecarEvent, PROTOCOL_VERSION};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::json;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::path::PathBuf;
+use std::thread;
+
+const CRASH_DIR_ENV: &str = "SHARKORD_SIDECAR_CRASH_DIR";
+const CRASH_REPORT_PREFIX: &str = "sidecar-crash-";
+const MAX_CRASH_REPORTS: usize = 20;
+
+thread_local! {
+    static CATCH_DEPTH: Cell<u32> = const { Cell::new(0) };
+    static CAUGHT_PANIC: RefCell<Option<CaughtPanic>> = const { RefCell::new(None) };
+}
+
+/// What the hook saw of a recoverable panic, logged by [`catch_panics`].
+#[derive(Debug)]
+struct CaughtPanic {
+    thread: String,
+    location: Option<String>,
+    crash_report_path: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PanicReport {
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SidecarFatalEvent {
+    thread: String,
+    message: String,
+    location: Option<String>,
+    backtrace: String,
+    crash_report_path: Option<String>,
+    protocol_version: u32,
+}
+
+impl PanicReport {
+    pub(crate) fn describe(&self) -> String {
+        format!("internal error: {}", self.message)
+    }
+}
+
+pub(crate) fn crash_report_dir() -> PathBuf {
+    match std::env::var_os(CRASH_DIR_ENV) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => std::env::temp_dir().join("sharkord-sidecar-crashes"),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return (*message).to_string();
+    }
+
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message.clone();
+    }
+
+    "unknown panic payload".to_string()
+}
+
+/// Keeps the newest reports so a crash loop cannot fill the disk.
+fn prune_crash_reports(dir: &PathBuf) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut reports: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(CRASH_REPORT_PREFIX))
+        })
+        .collect();
+
+    if reports.len() <= MAX_CRASH_REPORTS {
+        return;
+    }
+
+    // Names embed the unix timestamp, so lexical order is chronological.
+    reports.sort();
+    for path in &reports[..reports.len() - MAX_CRASH_REPORTS] {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn write_crash_report(report: &serde_json::Value, timestamp_ms: u128) -> io::Result<PathBuf> {
+    let dir = crash_report_dir();
+    fs::create_dir_all(&dir)?;
+
+    let path = dir.join(format!(
+        "{CRASH_REPORT_PREFIX}{timestamp_ms:015}-{}.json",
+        std::process::id()
+    ));
+    let serialized = serde_json::to_string_pretty(report).map_err(io::Error::other)?;
+    fs::write(&path, serialized)?;
+
+    prune_crash_reports(&dir);
+    Ok(path)
+}
+
+fn handle_panic(info: &PanicHookInfo<'_>) {
+    let message = panic_message(info.payload());
+    let location = info.location().map(|location| {
+        format!(
+            "{}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        )
+    });
+    let thread_name = thread::current().name().unwrap_or("<unnamed>").to_string();
+    let recoverable = CATCH_DEPTH.with(|depth| depth.get() > 0);
+    let backtrace = Backtrace::force_capture().to_string();
+    let timestamp_ms = now_unix_ms();
+
+    let report = json!({
+        "timestampMs": timestamp_ms,
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocolVersion": PROTOCOL_VERSION,
+        "platform": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "thread": thread_name,
+        "message": message,
+        "location": location,
+        "recoverable": recoverable,
+        "backtrace": backtrace,
+    });
+
+    let crash_report_path = match write_crash_report(&report, timestamp_ms) {
+        Ok(path) => Some(path),
+        Err(error) => {
+            logging::write_stderr_record(
+                LogLevel::Warn,
+                "sidecar.crash",
+                "failed to write crash report",
+                vec![("error", json!(error.to_string()))],
+            );
+            None
+        }
+    };
+    let crash_report_path = crash_report_path.map(|path| path.display().to_string());
+
+    if recoverable {
+        CAUGHT_PANIC.with(|caught| {
+            *caught.borrow_mut() = Some(CaughtPanic {
+                thread: thread_name,
+                location,
+                crash_report_path,
+            });
+        });
+        return;
+    }
+
+    logging::write_stderr_record(
+        LogLevel::Error,
+        "sidecar.crash",
+        "panic",
+        vec![
+            ("thread", json!(thread_name)),
+            ("message", json!(message)),
+            ("location", json!(location)),
+            ("recoverable", json!(false)),
+            ("crashReportPath", json!(crash_report_path)),
+        ],
+    );
+
+    // Written straight to stdout rather than through the shared writer: the
+    // panicking thread may be the one holding it.
+    let fatal_event = SidecarEvent {
+        event: "sidecar.fatal",
+        params: SidecarFatalEvent {
+            thread: thread_name,
+            message,
+            location,
+            backtrace,
+            crash_report_path,
+            protocol_version: PROTOCOL_VERSION,
+        },
+    };
+
+    if let Ok(serialized) = serde_json::to_string(&fatal_event) {
+        let mut stdout = io::stdout().lock();
+        let _ = writeln!(stdout, "{serialized}");
+        let _ = stdout.flush();
+    }
+
+    std::process::abort();
+}
+
+pub(crate) fn install_panic_hook() {
+    panic::set_hook(Box::new(handle_panic));
+}
+
+/// Runs `operation`, converting a panic into a [`PanicReport`] instead of
+/// unwinding further. The panic hook sees the catch scope and skips the fatal
+/// path for panics raised inside it; such panics are logged here, after the
+/// unwind has released whatever the panicking code held.
+pub(crate) fn catch_panics<T>(operation: impl FnOnce() -> T) -> Result<T, PanicReport> {
+    CATCH_DEPTH.with(|depth| depth.set(depth.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(operation));
+    CATCH_DEPTH.with(|depth| depth.set(depth.get() - 1));
+
+    result.map_err(|payload| {
+        let report = PanicReport {
+            message: panic_message(payload.as_ref()),
+        };
+        if let Some(caught) = CAUGHT_PANIC.with(|caught| caught.borrow_mut().take()) {
+            log_error!(
+                "sidecar.crash",
+                "panic",
+                "thread" => caught.thread,
+                "message" => &report.message,
+                "location" => caught.location,
+                "recoverable" => true,
+                "crashReportPath" => caught.crash_report_path,
+            );
+        }
+        report
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::catch_panics;
+
+    #[test]
+    fn converts_panics_into_reports() {
+        assert_eq!(catch_panics(|| 7).ok(), Some(7));
+
+        let report = catch_panics(|| -> u32 { panic!("processor exploded") }).unwrap_err();
+        assert_eq!(report.message, "processor exploded");
+        assert_eq!(report.describe(), "internal error: processor exploded");
+    }
+}
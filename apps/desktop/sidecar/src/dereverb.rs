@@ -0,0 +1,372 @@
+//! Late reverberation suppression for the voice filter chain.
+//!
+//! DeepFilterNet targets additive noise, so a voice in an untreated room keeps
+//! its reverb and sounds distant. Past the first few reflections, the reverb
+//! of a room decays exponentially at a rate set by its reverb time (RT60).
+//! Following Lebart et al., the stage predicts the late reverb in each STFT
+//! bin from the power spectrum `LATE_REVERB_MS` earlier, decayed by that
+//! rate, and subtracts the prediction spectrally. The direct sound and early
+//! reflections that carry intelligibility are left alone.
+//!
+//! All channels share one gain per bin, computed from their summed power, so
+//! the stereo image is kept.
+
+use crate::params::check_range;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::{LN_10, PI};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+const DEFAULT_STRENGTH: f32 = 0.75;
+const STRENGTH_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+const DEFAULT_REVERB_TIME_MS: f32 = 500.0;
+const REVERB_TIME_RANGE_MS: RangeInclusive<f32> = 100.0..=3_000.0;
+
+/// STFT frames are the power of two at or above this; 512 at 48 kHz. The
+/// signal is delayed by one frame.
+const FRAME_MS: f32 = 10.0;
+/// Reflections arriving later than this count as late reverb.
+const LATE_REVERB_MS: f32 = 50.0;
+/// Smoothing of the power spectrum the gains are computed from; keeps the
+/// gains from fluctuating bin by bin into musical noise.
+const POWER_SMOOTHING_MS: f32 = 15.0;
+/// Floor of the gain at full strength.
+const MIN_GAIN_DB: f32 = -18.0;
+/// Bins below -120 dBFS are left alone.
+const MIN_POWER: f32 = 1e-12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DereverbOptions {
+    /// How much of the predicted late reverb is removed, from 0 to 1.
+    #[serde(default = "default_strength")]
+    strength: f32,
+    /// Reverb time (RT60) of the room.
+    #[serde(default = "default_reverb_time_ms")]
+    reverb_time_ms: f32,
+}
+
+fn default_strength() -> f32 {
+    DEFAULT_STRENGTH
+}
+
+fn default_reverb_time_ms() -> f32 {
+    DEFAULT_REVERB_TIME_MS
+}
+
+impl Default for DereverbOptions {
+    fn default() -> Self {
+        Self {
+            strength: DEFAULT_STRENGTH,
+            reverb_time_ms: DEFAULT_REVERB_TIME_MS,
+        }
+    }
+}
+
+impl DereverbOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range("Dereverb strength", self.strength, STRENGTH_RANGE, "")?;
+        check_range(
+            "Dereverb reverb time",
+            self.reverb_time_ms,
+            REVERB_TIME_RANGE_MS,
+            " ms",
+        )
+    }
+}
+
+pub(crate) struct Dereverb {
+    channels: usize,
+    strength: f32,
+    min_gain: f32,
+    frame_size: usize,
+    hop: usize,
+    /// Square-root Hann, applied on analysis and synthesis; the two overlap
+    /// at half a frame to unity.
+    window: Vec<f32>,
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    /// The last `frame_size` input samples of every channel.
+    input: Vec<VecDeque<f32>>,
+    /// Overlap-add accumulator of every channel.
+    overlap: Vec<Vec<f32>>,
+    /// Finished output of every channel, not yet handed out.
+    output: Vec<VecDeque<f32>>,
+    /// Input frames since the last STFT frame.
+    pending_frames: usize,
+    buffer: Vec<f32>,
+    spectra: Vec<Vec<Complex<f32>>>,
+    power_coeff: f32,
+    power: Vec<f32>,
+    /// Smoothed power of past STFT frames, newest last, reaching back to the
+    /// start of the late reverb.
+    power_history: VecDeque<Vec<f32>>,
+    /// Power the reverb loses over the late reverb delay.
+    late_decay: f32,
+    gains: Vec<f32>,
+}
+
+impl Dereverb {
+    pub(crate) fn new(options: DereverbOptions, sample_rate: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let frame_size = ((FRAME_MS * sample_rate as f32 / 1_000.0).round() as usize)
+            .max(2)
+            .next_power_of_two();
+        let hop = frame_size / 2;
+        let hop_ms = hop as f32 * 1_000.0 / sample_rate as f32;
+        let late_hops = ((LATE_REVERB_MS / hop_ms).round() as usize).max(1);
+        // Power decays by 60 dB over the reverb time.
+        let decay_per_ms = 6.0 * LN_10 / options.reverb_time_ms;
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(frame_size);
+        let inverse = planner.plan_fft_inverse(frame_size);
+        let bins = forward.complex_len();
+
+        Self {
+            channels,
+            strength: options.strength,
+            min_gain: 10.0_f32.powf(options.strength * MIN_GAIN_DB / 20.0),
+            frame_size,
+            hop,
+            window: (0..frame_size)
+                .map(|index| (PI * index as f32 / frame_size as f32).sin())
+                .collect(),
+            input: vec![VecDeque::from(vec![0.0; frame_size]); channels],
+            overlap: vec![vec![0.0; frame_size]; channels],
+            output: vec![VecDeque::from(vec![0.0; hop]); channels],
+            pending_frames: 0,
+            buffer: forward.make_input_vec(),
+            spectra: vec![forward.make_output_vec(); channels],
+            power_coeff: (-hop_ms / POWER_SMOOTHING_MS).exp(),
+            power: vec![0.0; bins],
+            power_history: VecDeque::from(vec![vec![0.0; bins]; late_hops]),
+            late_decay: (-decay_per_ms * late_hops as f32 * hop_ms).exp(),
+            gains: vec![1.0; bins],
+            forward,
+            inverse,
+        }
+    }
+
+    /// Frames by which the output lags the input.
+    pub(crate) fn latency_frames(&self) -> usize {
+        self.frame_size
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            for (input, sample) in self.input.iter_mut().zip(frame.iter()) {
+                input.pop_front();
+                input.push_back(*sample);
+            }
+
+            self.pending_frames += 1;
+            if self.pending_frames == self.hop {
+                self.pending_frames = 0;
+                self.process_stft_frame();
+            }
+
+            for (output, sample) in self.output.iter_mut().zip(frame.iter_mut()) {
+                *sample = output.pop_front().unwrap_or(0.0);
+            }
+        }
+    }
+
+    fn process_stft_frame(&mut self) {
+        let mut frame_power = vec![0.0_f32; self.power.len()];
+        for (input, spectrum) in self.input.iter().zip(self.spectra.iter_mut()) {
+            for ((slot, sample), weight) in self.buffer.iter_mut().zip(input).zip(&self.window) {
+                *slot = sample * weight;
+            }
+            if self.forward.process(&mut self.buffer, spectrum).is_err() {
+                return;
+            }
+            for (power, bin) in frame_power.iter_mut().zip(spectrum.iter()) {
+                *power += bin.norm_sqr();
+            }
+        }
+
+        let coeff = self.power_coeff;
+        for (power, frame_power) in self.power.iter_mut().zip(&frame_power) {
+            *power = *power * coeff + frame_power * (1.0 - coeff);
+        }
+
+        let Some(past) = self.power_history.pop_front() else {
+            return;
+        };
+        for ((gain, power), past) in self.gains.iter_mut().zip(&self.power).zip(&past) {
+            *gain = if *power <= MIN_POWER {
+                1.0
+            } else {
+                let late = past * self.late_decay;
+                (1.0 - self.strength * late / power).clamp(self.min_gain, 1.0)
+            };
+        }
+        self.power_history.push_back(self.power.clone());
+
+        let scale = 1.0 / self.frame_size as f32;
+        for ((spectrum, overlap), output) in self
+            .spectra
+            .iter_mut()
+            .zip(self.overlap.iter_mut())
+            .zip(self.output.iter_mut())
+        {
+            for (bin, gain) in spectrum.iter_mut().zip(&self.gains) {
+                *bin *= *gain;
+            }
+            if self.inverse.process(spectrum, &mut self.buffer).is_err() {
+                return;
+            }
+            for ((accumulated, sample), weight) in
+                overlap.iter_mut().zip(&self.buffer).zip(&self.window)
+            {
+                *accumulated += sample * weight * scale;
+            }
+
+            output.extend(overlap.drain(..self.hop));
+            overlap.resize(self.frame_size, 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dereverb, DereverbOptions};
+    use crate::test_signals::{noise, options};
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: usize = 16_000;
+    const SYLLABLE: usize = 2_400;
+    const PAUSE: usize = 4_000;
+    const SYLLABLES: usize = 4;
+
+    fn build(strength: f32) -> Dereverb {
+        let options = options(
+            serde_json::json!({ "strength": strength, "reverbTimeMs": 600.0 }),
+            DereverbOptions::validate,
+        );
+        Dereverb::new(options, SAMPLE_RATE, 1)
+    }
+
+    /// Voiced syllables of 150 ms with pauses of 250 ms between them.
+    fn dry_speech() -> Vec<f32> {
+        let mut samples = Vec::new();
+        for syllable in 0..SYLLABLES {
+            let pitch = 120.0 + 20.0 * syllable as f32;
+            samples.extend((0..SYLLABLE).map(|index| {
+                let envelope = (PI * index as f32 / SYLLABLE as f32).sin();
+                (1..12)
+                    .map(|harmonic| {
+                        let phase = 2.0 * PI * pitch * harmonic as f32 * index as f32;
+                        0.1 / harmonic as f32 * (phase / SAMPLE_RATE as f32).sin()
+                    })
+                    .sum::<f32>()
+                    * envelope
+            }));
+            samples.extend(std::iter::repeat_n(0.0, PAUSE));
+        }
+        samples
+    }
+
+    /// `dry` in a room with a 600 ms reverb time: the direct sound plus an
+    /// exponentially decaying tail of sparse reflections from 5 ms on.
+    fn reverberant(dry: &[f32]) -> Vec<f32> {
+        let decay_per_sample = 3.0 * 10.0_f32.ln() / (0.6 * SAMPLE_RATE as f32);
+        let reflections: Vec<(usize, f32)> = noise(2_400, 7)
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let delay = 80 + index * 4;
+                (
+                    delay,
+                    0.3 * sample * (-decay_per_sample * delay as f32).exp(),
+                )
+            })
+            .collect();
+
+        let mut wet = dry.to_vec();
+        for (index, sample) in dry.iter().enumerate().filter(|(_, sample)| **sample != 0.0) {
+            for (delay, gain) in &reflections {
+                if let Some(target) = wet.get_mut(index + delay) {
+                    *target += sample * gain;
+                }
+            }
+        }
+        wet
+    }
+
+    fn energy(samples: &[f32]) -> f32 {
+        samples.iter().map(|sample| sample * sample).sum()
+    }
+
+    /// Energy of `samples` over the given part of every syllable period,
+    /// skipping the first syllable while the estimate settles.
+    fn energy_in(samples: &[f32], range: std::ops::Range<usize>) -> f32 {
+        (1..SYLLABLES)
+            .map(|syllable| {
+                let start = syllable * (SYLLABLE + PAUSE);
+                energy(&samples[start + range.start..start + range.end])
+            })
+            .sum()
+    }
+
+    #[test]
+    fn passes_the_signal_through_at_zero_strength() {
+        let mut dereverb = build(0.0);
+        let latency = dereverb.latency_frames();
+        let input = reverberant(&dry_speech());
+        let mut samples = input.clone();
+        for block in samples.chunks_mut(160) {
+            dereverb.process(block);
+        }
+
+        assert!(samples[..latency].iter().all(|sample| sample.abs() < 1e-6));
+        assert!(samples[latency..]
+            .iter()
+            .zip(&input)
+            .all(|(output, input)| (output - input).abs() < 1e-4));
+    }
+
+    #[test]
+    fn suppresses_the_reverb_tail_of_synthetic_speech() {
+        let mut dereverb = build(1.0);
+        let latency = dereverb.latency_frames();
+        let dry = dry_speech();
+        let input = reverberant(&dry);
+        let mut samples = input.clone();
+        samples.resize(input.len() + latency, 0.0);
+        for block in samples.chunks_mut(160) {
+            dereverb.process(block);
+        }
+        let output = &samples[latency..];
+
+        // 100 to 250 ms into the pause there is only late reverb.
+        let tail = SYLLABLE + 1_600..SYLLABLE + PAUSE;
+        let tail_reduction_db =
+            10.0 * (energy_in(&input, tail.clone()) / energy_in(output, tail)).log10();
+        assert!(tail_reduction_db > 6.0, "{tail_reduction_db} dB");
+
+        // The syllables themselves keep most of their level...
+        let speech = 0..SYLLABLE;
+        let speech_loss_db =
+            10.0 * (energy_in(&input, speech.clone()) / energy_in(output, speech.clone())).log10();
+        assert!(speech_loss_db < 3.0, "{speech_loss_db} dB");
+
+        // ...and end up closer to the dry speech than the input was.
+        let error = |signal: &[f32]| {
+            energy_in(
+                &signal
+                    .iter()
+                    .zip(&dry)
+                    .map(|(signal, dry)| signal - dry)
+                    .collect::<Vec<_>>(),
+                0..SYLLABLE + PAUSE,
+            )
+        };
+        assert!(error(output) < error(&input) * 0.75);
+    }
+}
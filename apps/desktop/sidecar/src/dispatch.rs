@@ -0,0 +1,531 @@
+//! Concurrent request dispatch for the stdin command loop.
+//!
+//! Requests are routed to per-resource lanes. Each lane is a worker thread that
+//! runs its requests strictly in arrival order, so e.g. `voice_filter.start`
+//! followed by `voice_filter.push_frame` is still processed in sequence, while a
+//! slow DeepFilterNet start no longer blocks `health.ping` or
+//! `push_keybinds.set`. Cheap stateless requests are answered inline on the
+//! reader thread. Requests on the state lane touch every stateful resource,
+//! so the lanes that own those are paused while one runs.
+
+use crate::{result_value, write_event, write_response};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+pub(crate) const CANCEL_REQUEST_METHOD: &str = "$/cancelRequest";
+pub(crate) const REQUEST_CANCELLED_ERROR: &str = "Request cancelled";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RequestLane {
+    Inline,
+    Discovery,
+    AudioCapture,
+    PushKeybinds,
+    VoiceFilter,
+    State,
+}
+
+impl RequestLane {
+    const WORKER_LANES: [RequestLane; 5] = [
+        RequestLane::Discovery,
+        RequestLane::AudioCapture,
+        RequestLane::PushKeybinds,
+        RequestLane::VoiceFilter,
+        RequestLane::State,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inline => "inline",
+            Self::Discovery => "discovery",
+            Self::AudioCapture => "audio_capture",
+            Self::PushKeybinds => "push_keybinds",
+            Self::VoiceFilter => "voice_filter",
+            Self::State => "state",
+        }
+    }
+
+    /// Lanes that must have finished everything queued before a request on
+    /// this lane, and wait for it to finish before going on.
+    fn pauses(self) -> &'static [RequestLane] {
+        match self {
+            Self::State => &[Self::AudioCapture, Self::PushKeybinds, Self::VoiceFilter],
+            _ => &[],
+        }
+    }
+}
+
+/// Maps a method to the lane that owns the resource it touches. Requests on
+/// the same lane never overlap; requests on different lanes run concurrently.
+pub(crate) fn request_lane(method: &str) -> RequestLane {
+    match method {
+        "windows.resolve_source"
+        | "audio_targets.list"
+        | "mic_devices.list"
+        | "voice_filter.eq.response" => RequestLane::Discovery,
+        "audio_capture.start" | "audio_capture.stop" => RequestLane::AudioCapture,
+        "push_keybinds.set" | "hotkeys.set" => RequestLane::PushKeybinds,
+        "voice_filter.start"
+        | "voice_filter.start_with_capture"
+        | "voice_filter.push_frame"
+        | "voice_filter.push_reference_frame"
+        | "voice_filter.set_transmit"
+        | "voice_filter.eq.set"
+        | "voice_filter.metrics"
+        | "voice_filter.stop" => RequestLane::VoiceFilter,
+        "state.snapshot" | "state.restore" => RequestLane::State,
+        _ => RequestLane::Inline,
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub(crate) struct CancelRequestParams {
+    id: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct CancelRequestResult {
+    id: String,
+    cancelled: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RequestProgressEvent<'a> {
+    request_id: &'a str,
+    method: &'a str,
+    stage: &'a str,
+    completed: usize,
+    total: usize,
+}
+
+/// Per-request handle passed to handlers so long operations can observe
+/// cancellation and report progress back to the client.
+pub(crate) struct RequestContext {
+    id: Option<String>,
+    method: String,
+    cancelled: Arc<AtomicBool>,
+    stdout: Arc<Mutex<io::Stdout>>,
+}
+
+impl RequestContext {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check_cancelled(&self) -> Result<(), String> {
+        if self.is_cancelled() {
+            return Err(REQUEST_CANCELLED_ERROR.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Emits a `request.progress` event. Notifications have no id to correlate
+    /// with, so progress is only reported for requests.
+    pub(crate) fn report_progress(&self, stage: &str, completed: usize, total: usize) {
+        let Some(id) = self.id.as_deref() else {
+            return;
+        };
+
+        write_event(
+            &self.stdout,
+            "request.progress",
+            RequestProgressEvent {
+                request_id: id,
+                method: &self.method,
+                stage,
+                completed,
+                total,
+            },
+        );
+    }
+}
+
+/// Cancellation flags of requests that have been accepted but not answered yet.
+#[derive(Default)]
+struct PendingRequests {
+    flags: Mutex<HashMap<String, Arc<AtomicBool>>>,
+}
+
+impl PendingRequests {
+    /// Returns `None` when a request with the same id is still in flight, as
+    /// cancelling or answering it would then be ambiguous.
+    fn register(&self, id: &str) -> Option<Arc<AtomicBool>> {
+        let flag = Arc::new(AtomicBool::new(false));
+        if let Ok(mut lock) = self.flags.lock() {
+            if lock.contains_key(id) {
+                return None;
+            }
+            lock.insert(id.to_string(), Arc::clone(&flag));
+        }
+        Some(flag)
+    }
+
+    fn cancel(&self, id: &str) -> bool {
+        let Ok(lock) = self.flags.lock() else {
+            return false;
+        };
+
+        match lock.get(id) {
+            Some(flag) => {
+                flag.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn complete(&self, id: &str) {
+        if let Ok(mut lock) = self.flags.lock() {
+            lock.remove(id);
+        }
+    }
+}
+
+/// Holds lanes at a point in their queues while a request on a lane that
+/// [`RequestLane::pauses`] them runs.
+#[derive(Default)]
+struct LanePause {
+    state: Mutex<LanePauseState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct LanePauseState {
+    parked: usize,
+    released: bool,
+}
+
+impl LanePause {
+    /// Run by a paused lane once it reaches the pause in its queue.
+    fn park(&self) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.parked += 1;
+        self.changed.notify_all();
+        drop(self.changed.wait_while(state, |state| !state.released));
+    }
+
+    fn wait_until_parked(&self, lanes: usize) {
+        let Ok(state) = self.state.lock() else {
+            return;
+        };
+        drop(self.changed.wait_while(state, |state| state.parked < lanes));
+    }
+
+    fn release(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.released = true;
+        }
+        self.changed.notify_all();
+    }
+}
+
+pub(crate) type RequestHandler =
+    dyn Fn(&str, Value, &RequestContext) -> Result<Value, String> + Send + Sync;
+
+struct QueuedRequest {
+    method: String,
+    params: Value,
+    context: RequestContext,
+    /// The pause of the lanes this request waits for, and how many there are.
+    pause: Option<(Arc<LanePause>, usize)>,
+}
+
+enum LaneItem {
+    Request(QueuedRequest),
+    Pause(Arc<LanePause>),
+}
+
+pub(crate) struct Dispatcher {
+    stdout: Arc<Mutex<io::Stdout>>,
+    handler: Arc<RequestHandler>,
+    pending: Arc<PendingRequests>,
+    lanes: HashMap<RequestLane, Sender<LaneItem>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    pub(crate) fn new(stdout: Arc<Mutex<io::Stdout>>, handler: Arc<RequestHandler>) -> Self {
+        let pending = Arc::new(PendingRequests::default());
+        let mut lanes = HashMap::new();
+        let mut workers = Vec::new();
+
+        for lane in RequestLane::WORKER_LANES {
+            let (sender, receiver) = mpsc::channel::<LaneItem>();
+            let worker_stdout = Arc::clone(&stdout);
+            let worker_handler = Arc::clone(&handler);
+            let worker_pending = Arc::clone(&pending);
+
+            let handle = thread::Builder::new()
+                .name(format!("sidecar-{}", lane.as_str()))
+                .spawn(move || {
+                    while let Ok(item) = receiver.recv() {
+                        match item {
+                            LaneItem::Request(queued) => run_request(
+                                &worker_stdout,
+                                &worker_handler,
+                                &worker_pending,
+                                queued,
+                            ),
+                            LaneItem::Pause(pause) => pause.park(),
+                        }
+                    }
+                });
+
+            match handle {
+                Ok(handle) => {
+                    lanes.insert(lane, sender);
+                    workers.push(handle);
+                }
+                Err(error) => {
+                    log_error!(
+                        "dispatch",
+                        "failed to start request lane, running inline",
+                        "lane" => lane.as_str(),
+                        "error" => error.to_string(),
+                    );
+                }
+            }
+        }
+
+        Self {
+            stdout,
+            handler,
+            pending,
+            lanes,
+            workers,
+        }
+    }
+
+    pub(crate) fn dispatch(&self, id: Option<String>, method: String, params: Value) {
+        if method == CANCEL_REQUEST_METHOD {
+            let result = self.cancel_request(params);
+            if let Some(id) = id.as_deref() {
+                write_response(&self.stdout, id, result);
+            }
+            return;
+        }
+
+        let cancelled = match id.as_deref() {
+            Some(id) => match self.pending.register(id) {
+                Some(cancelled) => cancelled,
+                None => {
+                    write_response(
+                        &self.stdout,
+                        id,
+                        Err(format!("A request with id {id} is already in flight.")),
+                    );
+                    return;
+                }
+            },
+            None => Arc::new(AtomicBool::new(false)),
+        };
+
+        let lane = request_lane(&method);
+        let pause = (!lane.pauses().is_empty()).then(|| {
+            let pause = Arc::new(LanePause::default());
+            let paused_lanes = lane
+                .pauses()
+                .iter()
+                .filter_map(|paused| self.lanes.get(paused))
+                .filter(|sender| sender.send(LaneItem::Pause(Arc::clone(&pause))).is_ok())
+                .count();
+            (pause, paused_lanes)
+        });
+
+        let queued = QueuedRequest {
+            context: RequestContext {
+                id,
+                method: method.clone(),
+                cancelled,
+                stdout: Arc::clone(&self.stdout),
+            },
+            method,
+            params,
+            pause,
+        };
+
+        let queued = match self.lanes.get(&lane) {
+            Some(sender) => match sender.send(LaneItem::Request(queued)) {
+                Ok(()) => return,
+                Err(mpsc::SendError(LaneItem::Request(queued))) => queued,
+                Err(mpsc::SendError(LaneItem::Pause(_))) => return,
+            },
+            None => queued,
+        };
+
+        run_request(&self.stdout, &self.handler, &self.pending, queued);
+    }
+
+    fn cancel_request(&self, params: Value) -> Result<Value, String> {
+        let parsed: CancelRequestParams =
+            serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+        let cancelled = self.pending.cancel(&parsed.id);
+        result_value(CancelRequestResult {
+            id: parsed.id,
+            cancelled,
+        })
+    }
+
+    /// Stops accepting requests and waits for every lane to drain its queue.
+    pub(crate) fn shutdown(self) {
+        drop(self.lanes);
+
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_request(
+    stdout: &Arc<Mutex<io::Stdout>>,
+    handler: &Arc<RequestHandler>,
+    pending: &PendingRequests,
+    queued: QueuedRequest,
+) {
+    let QueuedRequest {
+        method,
+        params,
+        context,
+        pause,
+    } = queued;
+
+    if let Some((pause, lanes)) = &pause {
+        pause.wait_until_parked(*lanes);
+    }
+
+    let result = if context.is_cancelled() {
+        Err(REQUEST_CANCELLED_ERROR.to_string())
+    } else {
+        handler(&method, params, &context)
+    };
+
+    if let Some((pause, _)) = &pause {
+        pause.release();
+    }
+
+    if let Some(id) = context.id.as_deref() {
+        pending.complete(id);
+        write_response(stdout, id, result);
+    } else if let Err(error) = result {
+        log_warn!(
+            "dispatch",
+            "notification failed",
+            "method" => method,
+            "error" => error,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{request_lane, LanePause, PendingRequests, RequestLane};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn routes_methods_to_resource_lanes() {
+        assert_eq!(request_lane("health.ping"), RequestLane::Inline);
+        assert_eq!(request_lane("capabilities.get"), RequestLane::Inline);
+        assert_eq!(
+            request_lane("voice_filter.binary_ingress_info"),
+            RequestLane::Inline
+        );
+        assert_eq!(request_lane("mic_devices.list"), RequestLane::Discovery);
+        assert_eq!(
+            request_lane("audio_capture.stop"),
+            RequestLane::AudioCapture
+        );
+        assert_eq!(request_lane("push_keybinds.set"), RequestLane::PushKeybinds);
+        assert_eq!(request_lane("hotkeys.set"), RequestLane::PushKeybinds);
+        assert_eq!(request_lane("voice_filter.start"), RequestLane::VoiceFilter);
+        assert_eq!(
+            request_lane("voice_filter.push_frame"),
+            RequestLane::VoiceFilter
+        );
+        assert_eq!(
+            request_lane("voice_filter.set_transmit"),
+            RequestLane::VoiceFilter
+        );
+        assert_eq!(
+            request_lane("voice_filter.eq.set"),
+            RequestLane::VoiceFilter
+        );
+        assert_eq!(
+            request_lane("voice_filter.metrics"),
+            RequestLane::VoiceFilter
+        );
+        assert_eq!(
+            request_lane("voice_filter.eq.response"),
+            RequestLane::Discovery
+        );
+        assert_eq!(request_lane("state.snapshot"), RequestLane::State);
+        assert_eq!(request_lane("state.restore"), RequestLane::State);
+        assert_eq!(
+            RequestLane::State.pauses(),
+            [
+                RequestLane::AudioCapture,
+                RequestLane::PushKeybinds,
+                RequestLane::VoiceFilter
+            ]
+        );
+    }
+
+    #[test]
+    fn holds_paused_lanes_until_released() {
+        let pause = Arc::new(LanePause::default());
+        let resumed = Arc::new(AtomicBool::new(false));
+        let lanes: Vec<_> = (0..2)
+            .map(|_| {
+                let pause = Arc::clone(&pause);
+                let resumed = Arc::clone(&resumed);
+                thread::spawn(move || {
+                    pause.park();
+                    assert!(resumed.load(Ordering::Relaxed));
+                })
+            })
+            .collect();
+
+        pause.wait_until_parked(2);
+        resumed.store(true, Ordering::Relaxed);
+        pause.release();
+        for lane in lanes {
+            lane.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn cancels_only_pending_requests() {
+        let pending = PendingRequests::default();
+        let flag = pending.register("7").unwrap();
+
+        assert!(!pending.cancel("8"));
+        assert!(pending.cancel("7"));
+        assert!(flag.load(Ordering::Relaxed));
+
+        pending.complete("7");
+        assert!(!pending.cancel("7"));
+    }
+
+    #[test]
+    fn rejects_ids_already_in_flight() {
+        let pending = PendingRequests::default();
+        assert!(pending.register("7").is_some());
+        assert!(pending.register("7").is_none());
+
+        pending.complete("7");
+        assert!(pending.register("7").is_some());
+    }
+}
@@ -0,0 +1,1224 @@
+//! The voice filter processing chain.
+//!
+//! A session runs every frame through an ordered list of stages, each built
+//! from a [`StageDescriptor`] with its own params and a bypass flag. Without an
+//! explicit `chain` in the start params the order is the one the sidecar always
+//! used: AGC, DeepFilterNet, noise gate, echo cancellation, limiter, with the
+//! stages the boolean flags turn off kept in place but bypassed.
+
+use crate::comfort_noise::{ComfortNoise, ComfortNoiseOptions};
+use crate::dereverb::{Dereverb, DereverbOptions};
+use crate::dynamics::{
+    Compressor, CompressorOptions, DeEsser, DeEsserOptions, GainReductionMetrics,
+};
+use crate::eq::{self, EqBand, ParametricEq};
+use crate::filters::{Biquad, BiquadFilter, DcBlocker, HumNotch};
+use crate::limiter::{LimiterOptions, TruePeakLimiter};
+use crate::loudness::{self, LoudnessAutoGainControl, LoudnessMetrics};
+use crate::params::check_range;
+use crate::transients::{TransientMetrics, TransientSuppressor, TransientSuppressorOptions};
+use deep_filter::tract::DfTract;
+use ndarray::{s, Array2, ArrayView2, ArrayViewMut2};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+
+const DEFAULT_HIGH_PASS_HZ: f32 = 80.0;
+const HIGH_PASS_RANGE_HZ: std::ops::RangeInclusive<f32> = 20.0..=500.0;
+const DEFAULT_HUM_HARMONICS: u32 = 4;
+const MAX_HUM_HARMONICS: u32 = 8;
+
+const AGC_TARGET_RMS: f32 = 0.12;
+const AGC_MIN_RMS: f32 = 0.0005;
+const AGC_MIN_GAIN: f32 = 0.5;
+const AGC_MAX_GAIN: f32 = 3.0;
+const AGC_ATTACK_SMOOTHING: f32 = 0.3;
+const AGC_RELEASE_SMOOTHING: f32 = 0.08;
+const AGC_LIMITER: f32 = 0.98;
+const AGC_PAUSE_RMS_THRESHOLD: f32 = 0.006;
+const AGC_PAUSE_RECOVERY_SMOOTHING: f32 = 0.3;
+const AGC_POST_PAUSE_HOLD_BLOCKS: u32 = 20;
+const AGC_STARTUP_BYPASS_MS: u32 = 1_500;
+
+const ECHO_REFERENCE_MIN_ENERGY: f32 = 1e-6;
+const ECHO_SUBTRACTION_MAX: f32 = 0.85;
+const ECHO_DUCKING_MIN_GAIN: f32 = 0.55;
+
+// Noise gate — applied per DeepFilterNet hop using the lsnr value returned by process().
+// lsnr > threshold → speech detected → gate opens; lsnr <= threshold → gate closes.
+// DeepFilterNet returns -15.0 for near-silence and positive values for clear speech.
+const GATE_LSNR_THRESHOLD: f32 = -3.0; // dB; tune upward to gate more aggressively
+
+// lsnr is smoothed before threshold comparison to prevent chattering during sustained
+// sounds (e.g. singing) where lsnr fluctuates briefly around the threshold.
+const GATE_LSNR_SMOOTH_COEFF: f32 = 0.904_8; // exp(-1/10): ~100 ms smoothing (10 hops)
+const GATE_ATTACK_COEFF: f32 = 0.606_5; // exp(-1/2): ~20 ms to open (2 × 10 ms hops)
+const GATE_RELEASE_COEFF: f32 = 0.980_2; // exp(-1/50): ~500 ms time constant to close
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum StageKind {
+    /// Second-order Butterworth high-pass against desk rumble and handling
+    /// noise.
+    #[serde(rename_all = "camelCase")]
+    HighPass {
+        #[serde(default = "default_high_pass_hz")]
+        cutoff_hz: f32,
+    },
+    /// Removes DC offset.
+    DcBlocker,
+    /// Notches mains hum and its harmonics. Detects 50 vs 60 Hz mains unless
+    /// `mainsHz` is given.
+    #[serde(rename_all = "camelCase")]
+    HumNotch {
+        mains_hz: Option<u32>,
+        /// Number of harmonics notched, the fundamental included.
+        #[serde(default = "default_hum_harmonics")]
+        harmonics: u32,
+    },
+    /// Block RMS gain control. Ahead of denoising by default so the model
+    /// receives a level-normalised signal.
+    AutoGainControl,
+    /// Gain control towards a short-term loudness measured over speech only.
+    /// Uses the LSNR of a denoise stage ahead of it to find speech.
+    #[serde(rename_all = "camelCase")]
+    LoudnessAutoGainControl {
+        #[serde(default = "default_loudness_target_lufs")]
+        target_lufs: f32,
+    },
+    /// DeepFilterNet at the session's `suppressionLevel`. At most one per
+    /// chain.
+    Denoise,
+    /// Suppresses the late reverb of untreated rooms; delays the signal by
+    /// about 11 ms. Best right after the denoise stage, so residual noise
+    /// isn't taken for reverb.
+    Dereverb(DereverbOptions),
+    /// Noise gate driven by the LSNR of the denoise stage, which it must
+    /// follow.
+    #[serde(rename_all = "camelCase")]
+    Gate {
+        #[serde(default = "default_gate_lsnr_threshold_db")]
+        lsnr_threshold_db: f32,
+    },
+    /// Fills in what the gate it must follow takes away with noise matched
+    /// to the residual noise.
+    ComfortNoise(ComfortNoiseOptions),
+    /// Subtracts the signal pushed with `voice_filter.push_reference_frame`.
+    EchoCancellation,
+    /// Parametric EQ; `voice_filter.eq.set` replaces the bands of the first
+    /// one in the chain.
+    Eq {
+        #[serde(default)]
+        bands: Vec<EqBand>,
+    },
+    /// Feed-forward compressor. Not part of the default chain.
+    Compressor(CompressorOptions),
+    /// Compresses only the sibilance band. Not part of the default chain.
+    DeEsser(DeEsserOptions),
+    /// Lookahead true-peak limiter; delays the signal by its lookahead.
+    Limiter(LimiterOptions),
+    /// Ducks keyboard and mouse clicks; delays the signal by 12 ms. Best
+    /// ahead of the denoise stage, so clicks never open the gate. Not part of
+    /// the default chain.
+    TransientSuppressor(TransientSuppressorOptions),
+}
+
+fn default_high_pass_hz() -> f32 {
+    DEFAULT_HIGH_PASS_HZ
+}
+
+fn default_hum_harmonics() -> u32 {
+    DEFAULT_HUM_HARMONICS
+}
+
+fn default_gate_lsnr_threshold_db() -> f32 {
+    GATE_LSNR_THRESHOLD
+}
+
+fn default_loudness_target_lufs() -> f32 {
+    loudness::DEFAULT_TARGET_LUFS
+}
+
+/// How the AGC of the default chain measures level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum AutoGainControlMode {
+    /// Block RMS, ahead of denoising.
+    #[default]
+    Rms,
+    /// Short-term loudness of speech, right after denoising.
+    #[serde(rename_all = "camelCase")]
+    Loudness {
+        #[serde(default = "default_loudness_target_lufs")]
+        target_lufs: f32,
+    },
+}
+
+/// How the denoise stage treats the channels of a stereo session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ChannelMode {
+    /// One mask for all channels, averaged across them.
+    #[default]
+    Linked,
+    /// One mask for all channels, the largest of theirs, so a source on one
+    /// side isn't pulled down by the other.
+    LinkedMax,
+    /// A mask of its own for every channel; keeps the image of stereo mics
+    /// and instruments.
+    Independent,
+    /// Denoises the mono downmix and copies it to every channel. Runs a
+    /// single-channel model, so costs the least CPU.
+    Downmix,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StageDescriptor {
+    #[serde(flatten)]
+    pub(crate) kind: StageKind,
+    /// Keeps the stage in the chain without touching the signal.
+    #[serde(default)]
+    pub(crate) bypass: bool,
+}
+
+/// The chain implied by the `preFilter`, `noiseSuppression`,
+/// `autoGainControl`, `autoGainControlMode`, `dereverb`, `comfortNoise` and
+/// `echoCancellation` start params.
+pub(crate) fn default_chain(
+    pre_filter: bool,
+    noise_suppression: bool,
+    auto_gain_control: bool,
+    auto_gain_control_mode: AutoGainControlMode,
+    dereverb: Option<DereverbOptions>,
+    comfort_noise: Option<ComfortNoiseOptions>,
+    echo_cancellation: bool,
+) -> Vec<StageDescriptor> {
+    let stage = |kind, enabled: bool| StageDescriptor {
+        kind,
+        bypass: !enabled,
+    };
+    let (rms_agc, loudness_agc) = match auto_gain_control_mode {
+        AutoGainControlMode::Rms => (Some(StageKind::AutoGainControl), None),
+        AutoGainControlMode::Loudness { target_lufs } => (
+            None,
+            Some(StageKind::LoudnessAutoGainControl { target_lufs }),
+        ),
+    };
+
+    let mut chain = vec![
+        // Cleaned up ahead of AGC so offset, rumble and hum don't count
+        // towards the target level.
+        stage(StageKind::DcBlocker, pre_filter),
+        stage(
+            StageKind::HighPass {
+                cutoff_hz: DEFAULT_HIGH_PASS_HZ,
+            },
+            pre_filter,
+        ),
+        stage(
+            StageKind::HumNotch {
+                mains_hz: None,
+                harmonics: DEFAULT_HUM_HARMONICS,
+            },
+            pre_filter,
+        ),
+    ];
+    chain.extend(rms_agc.map(|kind| stage(kind, auto_gain_control)));
+    chain.push(stage(StageKind::Denoise, noise_suppression));
+    chain.extend(dereverb.map(|options| stage(StageKind::Dereverb(options), true)));
+    chain.extend(loudness_agc.map(|kind| stage(kind, auto_gain_control)));
+    chain.push(stage(
+        StageKind::Gate {
+            lsnr_threshold_db: GATE_LSNR_THRESHOLD,
+        },
+        noise_suppression,
+    ));
+    chain.extend(
+        comfort_noise.map(|options| stage(StageKind::ComfortNoise(options), noise_suppression)),
+    );
+    chain.extend([
+        stage(StageKind::EchoCancellation, echo_cancellation),
+        // Flat until `voice_filter.eq.set` adds bands.
+        stage(StageKind::Eq { bands: Vec::new() }, true),
+        // The limiter only guards against DeepFilterNet output peaks; without
+        // it the raw signal is left unmodified.
+        stage(
+            StageKind::Limiter(LimiterOptions::default()),
+            noise_suppression,
+        ),
+    ]);
+    chain
+}
+
+pub(crate) fn validate_chain(stages: &[StageDescriptor]) -> Result<(), String> {
+    let mut has_denoise = false;
+    let mut has_gate = false;
+
+    for stage in stages {
+        match &stage.kind {
+            StageKind::Denoise if has_denoise => {
+                return Err("A voice filter chain can contain only one denoise stage.".to_string());
+            }
+            StageKind::Denoise => has_denoise = true,
+            StageKind::Gate { .. } if !has_denoise => {
+                return Err("The gate stage must follow the denoise stage.".to_string());
+            }
+            StageKind::Gate { .. } => has_gate = true,
+            StageKind::ComfortNoise(_) if !has_gate => {
+                return Err("The comfort noise stage must follow the gate stage.".to_string());
+            }
+            StageKind::ComfortNoise(options) => options.validate()?,
+            StageKind::Dereverb(options) => options.validate()?,
+            StageKind::HighPass { cutoff_hz } => {
+                check_range("High-pass cutoff", *cutoff_hz, HIGH_PASS_RANGE_HZ, " Hz")?
+            }
+            StageKind::HumNotch {
+                mains_hz: Some(mains_hz),
+                ..
+            } if *mains_hz != 50 && *mains_hz != 60 => {
+                return Err("Hum notch mains frequency must be 50 or 60 Hz.".to_string());
+            }
+            StageKind::HumNotch { harmonics, .. } => {
+                check_range("Hum notch harmonics", *harmonics, 1..=MAX_HUM_HARMONICS, "")?
+            }
+            StageKind::Eq { bands } => eq::validate_bands(bands)?,
+            StageKind::Compressor(options) => options.validate()?,
+            StageKind::DeEsser(options) => options.validate()?,
+            StageKind::Limiter(options) => options.validate()?,
+            StageKind::TransientSuppressor(options) => options.validate()?,
+            StageKind::LoudnessAutoGainControl { target_lufs } => check_range(
+                "Loudness target",
+                *target_lufs,
+                loudness::TARGET_RANGE_LUFS,
+                " LUFS",
+            )?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether building the chain needs a DeepFilterNet model.
+pub(crate) fn uses_denoiser(stages: &[StageDescriptor]) -> bool {
+    stages
+        .iter()
+        .any(|stage| stage.kind == StageKind::Denoise && !stage.bypass)
+}
+
+pub(crate) struct DeepFilterProcessor {
+    models: Vec<DfTract>,
+    buffers: HopBuffers,
+}
+
+// SAFETY: `DeepFilterProcessor` is never accessed concurrently. It is always
+// stored inside `SidecarState`, which is guarded by `Mutex<SidecarState>`.
+// This guarantees serialized access when the state is touched from different
+// threads (command loop and binary-ingress worker).
+unsafe impl Send for DeepFilterProcessor {}
+
+impl DeepFilterProcessor {
+    /// `models` all have `model_channels` channels and take consecutive
+    /// channels of the input between them: either one model for every
+    /// channel, or one single-channel model per channel. A lone
+    /// single-channel model denoises the downmix of multichannel input.
+    pub(crate) fn new(models: Vec<DfTract>, model_channels: usize) -> Self {
+        let hop_size = models.first().map_or(0, |model| model.hop_size);
+        Self {
+            buffers: HopBuffers::new(hop_size, models.len() * model_channels),
+            models,
+        }
+    }
+
+    /// Denoises `samples` in place and returns the LSNR of every hop the
+    /// models ran on.
+    fn process(&mut self, samples: &mut [f32], channels: usize) -> Result<Vec<f32>, String> {
+        let models = &mut self.models;
+        self.buffers.process(samples, channels, |noisy, enhanced| {
+            process_split(models, noisy, enhanced, |model, noisy, enhanced| {
+                model
+                    .process(noisy, enhanced)
+                    .map_err(|error| format!("DeepFilterNet processing failed: {error}"))
+            })
+        })
+    }
+}
+
+/// Splits the channels of a hop evenly between `models` and runs each on its
+/// share. The hop's LSNR is the highest any of them reported, so speech on
+/// one channel is enough to open the gate.
+fn process_split<M>(
+    models: &mut [M],
+    noisy: ArrayView2<f32>,
+    mut enhanced: ArrayViewMut2<f32>,
+    mut process: impl FnMut(&mut M, ArrayView2<f32>, ArrayViewMut2<f32>) -> Result<f32, String>,
+) -> Result<f32, String> {
+    let model_channels = noisy.nrows() / models.len().max(1);
+    let mut lsnr = f32::NEG_INFINITY;
+    for (index, model) in models.iter_mut().enumerate() {
+        let rows = s![index * model_channels..(index + 1) * model_channels, ..];
+        lsnr = lsnr.max(process(model, noisy.slice(rows), enhanced.slice_mut(rows))?);
+    }
+    Ok(lsnr)
+}
+
+/// Collects interleaved input into per-channel hops for the model and hands
+/// its output back out in the same layout, one hop later.
+struct HopBuffers {
+    hop_size: usize,
+    input_buffers: Vec<VecDeque<f32>>,
+    output_buffers: Vec<VecDeque<f32>>,
+}
+
+impl HopBuffers {
+    fn new(hop_size: usize, model_channels: usize) -> Self {
+        Self {
+            hop_size,
+            input_buffers: (0..model_channels).map(|_| VecDeque::new()).collect(),
+            output_buffers: (0..model_channels).map(|_| VecDeque::new()).collect(),
+        }
+    }
+
+    /// Runs `model` on every complete hop of `samples` and writes its output
+    /// back in place, returning what each run returned.
+    fn process(
+        &mut self,
+        samples: &mut [f32],
+        channels: usize,
+        mut model: impl FnMut(ArrayView2<f32>, ArrayViewMut2<f32>) -> Result<f32, String>,
+    ) -> Result<Vec<f32>, String> {
+        let model_channels = self.input_buffers.len();
+        let downmix = model_channels == 1 && channels > 1;
+        let hop_size = self.hop_size;
+        let mut hop_lsnr = Vec::new();
+
+        for frame in samples.chunks_exact(channels) {
+            if downmix {
+                let mono = frame.iter().sum::<f32>() / channels as f32;
+                self.input_buffers[0].push_back(mono);
+            } else {
+                for (buffer, sample) in self.input_buffers.iter_mut().zip(frame) {
+                    buffer.push_back(*sample);
+                }
+            }
+        }
+
+        while self
+            .input_buffers
+            .iter()
+            .all(|buffer| buffer.len() >= hop_size)
+        {
+            let mut noisy = Array2::<f32>::zeros((model_channels, hop_size));
+            let mut enhanced = Array2::<f32>::zeros((model_channels, hop_size));
+
+            for channel_index in 0..model_channels {
+                for sample_index in 0..hop_size {
+                    noisy[(channel_index, sample_index)] =
+                        self.input_buffers[channel_index].pop_front().unwrap_or(0.0);
+                }
+            }
+
+            hop_lsnr.push(model(noisy.view(), enhanced.view_mut())?);
+
+            for channel_index in 0..model_channels {
+                for sample_index in 0..hop_size {
+                    self.output_buffers[channel_index]
+                        .push_back(enhanced[(channel_index, sample_index)]);
+                }
+            }
+        }
+
+        for frame in samples.chunks_exact_mut(channels) {
+            if downmix {
+                if let Some(filtered_sample) = self.output_buffers[0].pop_front() {
+                    frame.fill(filtered_sample);
+                }
+            } else {
+                for (buffer, sample) in self.output_buffers.iter_mut().zip(frame) {
+                    if let Some(filtered_sample) = buffer.pop_front() {
+                        *sample = filtered_sample;
+                    }
+                }
+            }
+        }
+
+        Ok(hop_lsnr)
+    }
+}
+
+struct AutoGainControlState {
+    current_gain: f32,
+    post_pause_hold_blocks_remaining: u32,
+    startup_bypass_ms_remaining: u32,
+}
+
+impl AutoGainControlState {
+    fn process(&mut self, samples: &mut [f32], frame_count: usize, sample_rate: usize) {
+        if self.startup_bypass_ms_remaining == 0 {
+            apply_auto_gain_control(samples, self);
+            return;
+        }
+
+        let input_ms = (frame_count.saturating_mul(1000))
+            .checked_div(sample_rate)
+            .unwrap_or(0)
+            .max(1) as u32;
+        self.startup_bypass_ms_remaining =
+            self.startup_bypass_ms_remaining.saturating_sub(input_ms);
+
+        // Hold gain at unity during startup — pass samples through unmodified
+        // so raw mic audio isn't hard-clipped before DeepFilterNet warms up.
+        self.current_gain = 1.0;
+    }
+}
+
+fn apply_auto_gain_control(samples: &mut [f32], state: &mut AutoGainControlState) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let mut sum_squares = 0.0_f64;
+    for sample in samples.iter() {
+        let sample_f64 = f64::from(*sample);
+        sum_squares += sample_f64 * sample_f64;
+    }
+
+    let rms = (sum_squares / samples.len() as f64).sqrt() as f32;
+    let (desired_gain, smoothing) = if rms <= AGC_PAUSE_RMS_THRESHOLD {
+        state.post_pause_hold_blocks_remaining = AGC_POST_PAUSE_HOLD_BLOCKS;
+        // Avoid AGC ramp-up during pauses; it over-amplifies the next phrase onset.
+        (1.0, AGC_PAUSE_RECOVERY_SMOOTHING)
+    } else if state.post_pause_hold_blocks_remaining > 0 {
+        state.post_pause_hold_blocks_remaining =
+            state.post_pause_hold_blocks_remaining.saturating_sub(1);
+        // Hold AGC at unity briefly after silence so phrase onsets stay natural.
+        (1.0, AGC_PAUSE_RECOVERY_SMOOTHING)
+    } else {
+        let desired_gain = if rms <= AGC_MIN_RMS {
+            AGC_MAX_GAIN
+        } else {
+            (AGC_TARGET_RMS / rms).clamp(AGC_MIN_GAIN, AGC_MAX_GAIN)
+        };
+
+        let smoothing = if desired_gain < state.current_gain {
+            AGC_ATTACK_SMOOTHING
+        } else {
+            AGC_RELEASE_SMOOTHING
+        };
+
+        (desired_gain, smoothing)
+    };
+    state.current_gain = state.current_gain * (1.0 - smoothing) + desired_gain * smoothing;
+
+    for sample in samples.iter_mut() {
+        *sample = (*sample * state.current_gain).clamp(-AGC_LIMITER, AGC_LIMITER);
+    }
+}
+
+/// What the gate did to one block, for the comfort noise stage.
+pub(crate) struct GateBlock {
+    /// The block as it reached the gate.
+    pub(crate) residual: Vec<f32>,
+    /// Consecutive runs of frames that share a gain, covering the block.
+    pub(crate) hops: Vec<GateHop>,
+}
+
+pub(crate) struct GateHop {
+    pub(crate) frames: usize,
+    pub(crate) gain: f32,
+    /// Whether the gate took the hop for speech.
+    pub(crate) speech: bool,
+}
+
+struct GateState {
+    lsnr_threshold_db: f32,
+    /// DeepFilterNet hop size in frames; every LSNR value covers one hop.
+    hop_size: usize,
+    gain: f32,
+    lsnr_smooth: f32,
+    /// LSNR of denoised hops whose frames have not reached the gate yet.
+    pending_lsnr: VecDeque<f32>,
+    /// Frames of the current hop still to come in later blocks.
+    hop_frames_remaining: usize,
+}
+
+impl GateState {
+    /// Applies one gain step per denoised hop, to exactly the frames of that
+    /// hop, carrying a hop that straddles blocks into the next one. Frames
+    /// the denoiser passed through before its first hop keep the current
+    /// gain.
+    fn process(&mut self, samples: &mut [f32], channels: usize, hop_lsnr: &[f32]) -> GateBlock {
+        let frame_count = samples.len() / channels;
+        let residual = samples.to_vec();
+        self.pending_lsnr.extend(hop_lsnr);
+
+        let mut hops = Vec::new();
+        let mut start = 0;
+        while start < frame_count {
+            if self.hop_frames_remaining == 0 {
+                if let Some(lsnr) = self.pending_lsnr.pop_front() {
+                    self.step(lsnr);
+                    self.hop_frames_remaining = self.hop_size;
+                }
+            }
+
+            let frames = match self.hop_frames_remaining {
+                0 => frame_count - start,
+                remaining => remaining.min(frame_count - start),
+            };
+            self.hop_frames_remaining = self.hop_frames_remaining.saturating_sub(frames);
+
+            for sample in &mut samples[start * channels..(start + frames) * channels] {
+                *sample *= self.gain;
+            }
+            hops.push(GateHop {
+                frames,
+                gain: self.gain,
+                speech: self.lsnr_smooth > self.lsnr_threshold_db,
+            });
+            start += frames;
+        }
+
+        GateBlock { residual, hops }
+    }
+
+    fn step(&mut self, lsnr: f32) {
+        // Smooth lsnr before thresholding so brief fluctuations during
+        // sustained sounds (e.g. singing a held note) don't cause chattering.
+        self.lsnr_smooth =
+            self.lsnr_smooth * GATE_LSNR_SMOOTH_COEFF + lsnr * (1.0 - GATE_LSNR_SMOOTH_COEFF);
+
+        // Move gate gain toward open (1.0) when speech is detected, or
+        // toward closed (0.0) otherwise.  Attack is fast (~20 ms) so the
+        // start of speech isn't clipped; release is slow (~500 ms) so word
+        // endings and sustained tones trail off naturally.
+        let target_gain = if self.lsnr_smooth > self.lsnr_threshold_db {
+            1.0_f32
+        } else {
+            0.0_f32
+        };
+        if target_gain > self.gain {
+            self.gain = self.gain * GATE_ATTACK_COEFF + target_gain * (1.0 - GATE_ATTACK_COEFF);
+        } else {
+            self.gain = self.gain * GATE_RELEASE_COEFF + target_gain * (1.0 - GATE_RELEASE_COEFF);
+        }
+    }
+}
+
+fn apply_reference_echo_cancellation(reference_samples: &[f32], samples: &mut [f32]) {
+    if samples.is_empty() || reference_samples.len() != samples.len() {
+        return;
+    }
+
+    let mut near_energy = 0.0_f64;
+    let mut reference_energy = 0.0_f64;
+    let mut near_reference_dot = 0.0_f64;
+
+    for index in 0..samples.len() {
+        let near = f64::from(samples[index]);
+        let reference = f64::from(reference_samples[index]);
+        near_energy += near * near;
+        reference_energy += reference * reference;
+        near_reference_dot += near * reference;
+    }
+
+    if reference_energy <= f64::from(ECHO_REFERENCE_MIN_ENERGY) {
+        return;
+    }
+
+    let near_rms = (near_energy / samples.len() as f64).sqrt() as f32;
+    let reference_rms = (reference_energy / samples.len() as f64).sqrt() as f32;
+
+    let mut subtraction_gain =
+        (near_reference_dot / reference_energy).clamp(0.0, f64::from(ECHO_SUBTRACTION_MAX)) as f32;
+    let near_dominance = near_rms / (reference_rms + 1e-6);
+    if near_dominance > 1.35 {
+        subtraction_gain *= 0.5;
+    } else if near_dominance > 1.1 {
+        subtraction_gain *= 0.75;
+    }
+
+    let ducking_gain = if reference_rms > near_rms * 0.8 {
+        ECHO_DUCKING_MIN_GAIN
+    } else if reference_rms > near_rms * 0.55 {
+        0.72
+    } else if reference_rms > near_rms * 0.35 {
+        0.86
+    } else {
+        1.0
+    };
+
+    for index in 0..samples.len() {
+        let canceled = samples[index] - reference_samples[index] * subtraction_gain;
+        samples[index] = canceled * ducking_gain;
+    }
+}
+
+enum StageState {
+    HighPass(BiquadFilter),
+    DcBlocker(DcBlocker),
+    HumNotch(HumNotch),
+    AutoGainControl(AutoGainControlState),
+    LoudnessAutoGainControl(LoudnessAutoGainControl),
+    /// Empty when the stage is bypassed, so no model is loaded for it.
+    Denoise(Option<DeepFilterProcessor>),
+    Dereverb(Dereverb),
+    Gate(GateState),
+    ComfortNoise(ComfortNoise),
+    EchoCancellation,
+    Eq(ParametricEq),
+    Compressor(Compressor),
+    DeEsser(DeEsser),
+    Limiter(TruePeakLimiter),
+    TransientSuppressor(TransientSuppressor),
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum StageMetricsKind {
+    Compressor(GainReductionMetrics),
+    DeEsser(GainReductionMetrics),
+    Limiter(GainReductionMetrics),
+    LoudnessAutoGainControl(LoudnessMetrics),
+    TransientSuppressor(TransientMetrics),
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StageMetrics {
+    /// Position of the stage in the session's chain.
+    index: usize,
+    bypass: bool,
+    #[serde(flatten)]
+    kind: StageMetricsKind,
+}
+
+struct Stage {
+    descriptor: StageDescriptor,
+    state: StageState,
+}
+
+pub(crate) struct DspChain {
+    sample_rate: usize,
+    stages: Vec<Stage>,
+}
+
+impl DspChain {
+    /// Builds the stages in order. `denoiser` is required when
+    /// [`uses_denoiser`] is true for `descriptors`.
+    pub(crate) fn new(
+        descriptors: &[StageDescriptor],
+        sample_rate: usize,
+        channels: usize,
+        mut denoiser: Option<DeepFilterProcessor>,
+    ) -> Self {
+        let hop_size = denoiser
+            .as_ref()
+            .map_or(0, |processor| processor.buffers.hop_size);
+        let stages = descriptors
+            .iter()
+            .map(|descriptor| {
+                let state = match &descriptor.kind {
+                    StageKind::HighPass { cutoff_hz } => StageState::HighPass(BiquadFilter::new(
+                        Biquad::high_pass(sample_rate as f32, *cutoff_hz, FRAC_1_SQRT_2),
+                        channels,
+                    )),
+                    StageKind::DcBlocker => {
+                        StageState::DcBlocker(DcBlocker::new(sample_rate, channels))
+                    }
+                    StageKind::HumNotch {
+                        mains_hz,
+                        harmonics,
+                    } => StageState::HumNotch(HumNotch::new(
+                        sample_rate,
+                        channels,
+                        *mains_hz,
+                        *harmonics,
+                    )),
+                    StageKind::AutoGainControl => {
+                        StageState::AutoGainControl(AutoGainControlState {
+                            current_gain: 1.0,
+                            post_pause_hold_blocks_remaining: 0,
+                            startup_bypass_ms_remaining: AGC_STARTUP_BYPASS_MS,
+                        })
+                    }
+                    StageKind::LoudnessAutoGainControl { target_lufs } => {
+                        StageState::LoudnessAutoGainControl(LoudnessAutoGainControl::new(
+                            *target_lufs,
+                            sample_rate,
+                            channels,
+                        ))
+                    }
+                    StageKind::Denoise if descriptor.bypass => StageState::Denoise(None),
+                    StageKind::Denoise => StageState::Denoise(denoiser.take()),
+                    StageKind::Dereverb(options) => {
+                        StageState::Dereverb(Dereverb::new(*options, sample_rate, channels))
+                    }
+                    StageKind::Gate { lsnr_threshold_db } => StageState::Gate(GateState {
+                        lsnr_threshold_db: *lsnr_threshold_db,
+                        hop_size,
+                        gain: 0.0,
+                        lsnr_smooth: -15.0,
+                        pending_lsnr: VecDeque::new(),
+                        hop_frames_remaining: 0,
+                    }),
+                    StageKind::ComfortNoise(options) => {
+                        StageState::ComfortNoise(ComfortNoise::new(*options, sample_rate, channels))
+                    }
+                    StageKind::EchoCancellation => StageState::EchoCancellation,
+                    StageKind::Eq { bands } => {
+                        StageState::Eq(ParametricEq::new(bands, sample_rate, channels))
+                    }
+                    StageKind::Compressor(options) => {
+                        StageState::Compressor(Compressor::new(*options, sample_rate, channels))
+                    }
+                    StageKind::DeEsser(options) => {
+                        StageState::DeEsser(DeEsser::new(*options, sample_rate, channels))
+                    }
+                    StageKind::Limiter(options) => {
+                        StageState::Limiter(TruePeakLimiter::new(*options, sample_rate, channels))
+                    }
+                    StageKind::TransientSuppressor(options) if descriptor.bypass => {
+                        StageState::TransientSuppressor(TransientSuppressor::new(
+                            options.without_key_hints(),
+                            sample_rate,
+                            channels,
+                        ))
+                    }
+                    StageKind::TransientSuppressor(options) => StageState::TransientSuppressor(
+                        TransientSuppressor::new(*options, sample_rate, channels),
+                    ),
+                };
+
+                Stage {
+                    descriptor: descriptor.clone(),
+                    state,
+                }
+            })
+            .collect();
+
+        Self {
+            sample_rate,
+            stages,
+        }
+    }
+
+    pub(crate) fn descriptors(&self) -> Vec<StageDescriptor> {
+        self.stages
+            .iter()
+            .map(|stage| stage.descriptor.clone())
+            .collect()
+    }
+
+    /// Replaces the bands of the first EQ stage; `bands` must be valid.
+    pub(crate) fn set_eq_bands(&mut self, bands: &[EqBand]) -> Result<(), String> {
+        let Some(stage) = self
+            .stages
+            .iter_mut()
+            .find(|stage| matches!(stage.state, StageState::Eq(_)))
+        else {
+            return Err("The voice filter chain has no EQ stage.".to_string());
+        };
+
+        if let StageState::Eq(eq) = &mut stage.state {
+            eq.set_bands(bands);
+        }
+        stage.descriptor.kind = StageKind::Eq {
+            bands: bands.to_vec(),
+        };
+
+        Ok(())
+    }
+
+    /// Metrics of every stage that reports any, resetting the maxima.
+    pub(crate) fn take_metrics(&mut self) -> Vec<StageMetrics> {
+        self.stages
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, stage)| {
+                let kind = match &mut stage.state {
+                    StageState::Compressor(compressor) => {
+                        StageMetricsKind::Compressor(compressor.take_metrics())
+                    }
+                    StageState::DeEsser(de_esser) => {
+                        StageMetricsKind::DeEsser(de_esser.take_metrics())
+                    }
+                    StageState::Limiter(limiter) => {
+                        StageMetricsKind::Limiter(limiter.take_metrics())
+                    }
+                    StageState::LoudnessAutoGainControl(agc) => {
+                        StageMetricsKind::LoudnessAutoGainControl(agc.metrics())
+                    }
+                    StageState::TransientSuppressor(suppressor) => {
+                        StageMetricsKind::TransientSuppressor(suppressor.metrics())
+                    }
+                    _ => return None,
+                };
+
+                Some(StageMetrics {
+                    index,
+                    bypass: stage.descriptor.bypass,
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Frames by which the output lags the input, on top of the denoiser's
+    /// own buffering.
+    pub(crate) fn latency_frames(&self) -> usize {
+        self.stages
+            .iter()
+            .filter(|stage| !stage.descriptor.bypass)
+            .map(|stage| match &stage.state {
+                StageState::Dereverb(dereverb) => dereverb.latency_frames(),
+                StageState::Limiter(limiter) => limiter.latency_frames(),
+                StageState::TransientSuppressor(suppressor) => suppressor.latency_frames(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// The DeepFilterNet hop size when the chain denoises.
+    pub(crate) fn hop_size(&self) -> Option<usize> {
+        self.stages.iter().find_map(|stage| match &stage.state {
+            StageState::Denoise(Some(processor)) => Some(processor.buffers.hop_size),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn uses_echo_reference(&self) -> bool {
+        self.stages.iter().any(|stage| {
+            !stage.descriptor.bypass && matches!(stage.state, StageState::EchoCancellation)
+        })
+    }
+
+    /// Runs interleaved `samples` through every stage that is not bypassed.
+    /// `echo_reference` is the time-aligned far-end signal of the same length.
+    pub(crate) fn process(
+        &mut self,
+        samples: &mut [f32],
+        channels: usize,
+        echo_reference: Option<&[f32]>,
+    ) -> Result<(), String> {
+        let frame_count = samples.len() / channels;
+        let sample_rate = self.sample_rate;
+        // LSNR of each hop the denoise stage ran on; `None` until it ran.
+        let mut hop_lsnr: Option<Vec<f32>> = None;
+        // What the gate did, once it ran.
+        let mut gate: Option<GateBlock> = None;
+
+        for stage in self.stages.iter_mut() {
+            if stage.descriptor.bypass {
+                continue;
+            }
+
+            match &mut stage.state {
+                StageState::HighPass(filter) => filter.process(samples),
+                StageState::DcBlocker(blocker) => blocker.process(samples),
+                StageState::HumNotch(notch) => {
+                    let mains_hz = notch.mains_hz();
+                    notch.process(samples);
+                    if notch.mains_hz() != mains_hz {
+                        log_info!("voice_filter", "mains hum detected", "mainsHz" => notch.mains_hz());
+                    }
+                }
+                StageState::AutoGainControl(state) => {
+                    state.process(samples, frame_count, sample_rate)
+                }
+                StageState::LoudnessAutoGainControl(agc) => {
+                    agc.process(samples, hop_lsnr.as_deref())
+                }
+                StageState::Denoise(Some(processor)) => {
+                    hop_lsnr = Some(processor.process(samples, channels)?);
+                }
+                StageState::Denoise(None) => {}
+                StageState::Dereverb(dereverb) => dereverb.process(samples),
+                StageState::Gate(state) => {
+                    if let Some(hop_lsnr) = &hop_lsnr {
+                        gate = Some(state.process(samples, channels, hop_lsnr));
+                    }
+                }
+                StageState::ComfortNoise(comfort_noise) => {
+                    if let Some(gate) = &gate {
+                        comfort_noise.process(samples, gate);
+                    }
+                }
+                StageState::EchoCancellation => {
+                    if let Some(reference) = echo_reference {
+                        apply_reference_echo_cancellation(reference, samples);
+                    }
+                }
+                StageState::Eq(eq) => eq.process(samples),
+                StageState::Compressor(compressor) => compressor.process(samples),
+                StageState::DeEsser(de_esser) => de_esser.process(samples),
+                StageState::Limiter(limiter) => limiter.process(samples),
+                StageState::TransientSuppressor(suppressor) => suppressor.process(samples),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_chain, process_split, validate_chain, Array2, ArrayView2, ArrayViewMut2,
+        AutoGainControlMode, DspChain, GateBlock, GateState, HopBuffers, StageDescriptor,
+        StageKind,
+    };
+    use crate::comfort_noise::ComfortNoiseOptions;
+    use crate::dereverb::DereverbOptions;
+    use std::collections::VecDeque;
+
+    fn parse(chain: serde_json::Value) -> Result<Vec<StageDescriptor>, String> {
+        let stages: Vec<StageDescriptor> =
+            serde_json::from_value(chain).map_err(|error| error.to_string())?;
+        validate_chain(&stages)?;
+        Ok(stages)
+    }
+
+    #[test]
+    fn parses_and_validates_stage_descriptors() {
+        let stages = parse(serde_json::json!([
+            { "type": "echoCancellation" },
+            { "type": "denoise" },
+            { "type": "gate", "lsnrThresholdDb": 2.0, "bypass": true },
+            { "type": "limiter" },
+        ]))
+        .unwrap();
+        assert_eq!(
+            stages[2],
+            StageDescriptor {
+                kind: StageKind::Gate {
+                    lsnr_threshold_db: 2.0
+                },
+                bypass: true,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&stages[0]).unwrap(),
+            serde_json::json!({ "type": "echoCancellation", "bypass": false })
+        );
+
+        assert!(validate_chain(&default_chain(
+            false,
+            false,
+            false,
+            AutoGainControlMode::Rms,
+            None,
+            None,
+            false
+        ))
+        .is_ok());
+        let loudness = default_chain(
+            false,
+            true,
+            true,
+            AutoGainControlMode::Loudness { target_lufs: -20.0 },
+            Some(DereverbOptions::default()),
+            Some(ComfortNoiseOptions::default()),
+            false,
+        );
+        assert!(validate_chain(&loudness).is_ok());
+        let denoise = loudness
+            .iter()
+            .position(|stage| stage.kind == StageKind::Denoise)
+            .unwrap();
+        assert_eq!(
+            loudness[denoise + 1].kind,
+            StageKind::Dereverb(DereverbOptions::default())
+        );
+        assert_eq!(
+            loudness[denoise + 2].kind,
+            StageKind::LoudnessAutoGainControl { target_lufs: -20.0 }
+        );
+        assert!(loudness
+            .iter()
+            .all(|stage| stage.kind != StageKind::AutoGainControl));
+        assert_eq!(
+            loudness[denoise + 4].kind,
+            StageKind::ComfortNoise(ComfortNoiseOptions::default())
+        );
+        assert_eq!(
+            parse(serde_json::json!([{ "type": "denoise" }, { "type": "comfortNoise" }]))
+                .unwrap_err(),
+            "The comfort noise stage must follow the gate stage."
+        );
+        assert_eq!(
+            parse(serde_json::json!([{ "type": "gate" }, { "type": "denoise" }])).unwrap_err(),
+            "The gate stage must follow the denoise stage."
+        );
+        assert_eq!(
+            parse(serde_json::json!([{ "type": "denoise" }, { "type": "denoise" }])).unwrap_err(),
+            "A voice filter chain can contain only one denoise stage."
+        );
+        assert!(parse(serde_json::json!([{ "type": "reverb" }])).is_err());
+        assert_eq!(
+            parse(serde_json::json!([{ "type": "dereverb", "strength": 1.5 }])).unwrap_err(),
+            "Dereverb strength must be between 0 and 1."
+        );
+        let dynamics = parse(serde_json::json!([
+            { "type": "compressor", "ratio": 4.0, "bypass": true },
+            { "type": "deEsser" },
+        ]))
+        .unwrap();
+        assert!(dynamics[0].bypass && !dynamics[1].bypass);
+        assert_eq!(
+            parse(serde_json::json!([{ "type": "compressor", "ratio": 0.5 }])).unwrap_err(),
+            "Compressor ratio must be between 1 and 20."
+        );
+    }
+
+    #[test]
+    fn runs_stages_in_order_and_skips_bypassed_ones() {
+        let loud = || vec![0.99_f32; 480];
+
+        // Echo cancellation ahead of the limiter sees the unlimited signal and
+        // removes it entirely.
+        let mut chain = DspChain::new(
+            &parse(serde_json::json!([{ "type": "echoCancellation" }, { "type": "limiter" }]))
+                .unwrap(),
+            48_000,
+            1,
+            None,
+        );
+        assert!(chain.uses_echo_reference());
+        let mut samples = loud();
+        chain.process(&mut samples, 1, Some(&loud())).unwrap();
+        assert!(samples.iter().all(|sample| sample.abs() < 0.1));
+
+        let mut chain = DspChain::new(
+            &parse(serde_json::json!([{ "type": "limiter" }])).unwrap(),
+            48_000,
+            1,
+            None,
+        );
+        let mut samples = loud();
+        chain.process(&mut samples, 1, None).unwrap();
+        assert!(samples[479] < 0.96);
+
+        let mut chain = DspChain::new(
+            &default_chain(
+                false,
+                false,
+                false,
+                AutoGainControlMode::Rms,
+                None,
+                None,
+                false,
+            ),
+            48_000,
+            1,
+            None,
+        );
+        assert!(!chain.uses_echo_reference());
+        assert_eq!(chain.hop_size(), None);
+        let mut samples = loud();
+        chain.process(&mut samples, 1, Some(&loud())).unwrap();
+        assert_eq!(samples, loud());
+    }
+
+    #[test]
+    fn downmixes_into_a_mono_model_and_fills_every_channel() {
+        let mut buffers = HopBuffers::new(4, 1);
+        // Doubles whatever the model is given, reporting the hop's shape.
+        let mut model = |noisy: ArrayView2<f32>, mut enhanced: ArrayViewMut2<f32>| {
+            enhanced.assign(&(&noisy * 2.0));
+            Ok(noisy.nrows() as f32)
+        };
+
+        // Six stereo frames: one full hop, two frames left over.
+        let mut first: Vec<f32> = (0..6).flat_map(|frame| [frame as f32, 0.0]).collect();
+        assert_eq!(buffers.process(&mut first, 2, &mut model).unwrap(), [1.0]);
+        assert_eq!(&first[..8], [0.0, 0.0, 1.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+        // Not enough for a hop yet, so the input passes through.
+        assert_eq!(&first[8..], [4.0, 0.0, 5.0, 0.0]);
+
+        let mut second: Vec<f32> = (6..12).flat_map(|frame| [frame as f32, 0.0]).collect();
+        assert_eq!(
+            buffers.process(&mut second, 2, &mut model).unwrap(),
+            [1.0, 1.0]
+        );
+        let expected: Vec<f32> = (4..10).flat_map(|frame| [frame as f32; 2]).collect();
+        assert_eq!(second, expected);
+    }
+
+    #[test]
+    fn keeps_channels_apart_for_a_multichannel_model() {
+        let mut buffers = HopBuffers::new(2, 2);
+        let mut samples = vec![0.5, -0.5, 0.25, -0.25];
+
+        let hops = buffers
+            .process(&mut samples, 2, |noisy, mut enhanced| {
+                assert_eq!(noisy.dim(), (2, 2));
+                enhanced.row_mut(0).assign(&noisy.row(1));
+                enhanced.row_mut(1).assign(&noisy.row(0));
+                Ok(0.0)
+            })
+            .unwrap();
+
+        assert_eq!(hops.len(), 1);
+        assert_eq!(samples, [-0.5, 0.5, -0.25, 0.25]);
+    }
+
+    #[test]
+    fn gates_hops_that_straddle_blocks() {
+        let mut gate = GateState {
+            lsnr_threshold_db: 0.0,
+            hop_size: 4,
+            gain: 0.0,
+            lsnr_smooth: 100.0,
+            pending_lsnr: VecDeque::new(),
+            hop_frames_remaining: 0,
+        };
+        let frames =
+            |block: &GateBlock| -> Vec<usize> { block.hops.iter().map(|hop| hop.frames).collect() };
+
+        // One hop, then two passed-through frames at the same gain.
+        let mut first = vec![1.0; 6];
+        let block = gate.process(&mut first, 1, &[100.0]);
+        assert_eq!(frames(&block), [4, 2]);
+        assert!(first.iter().all(|sample| *sample == first[0]));
+
+        // A whole hop, then the first half of the next.
+        let mut second = vec![1.0; 6];
+        let block = gate.process(&mut second, 1, &[100.0, 100.0]);
+        assert_eq!(frames(&block), [4, 2]);
+        assert!(second[0] > first[0]);
+        assert!(second[4] > second[3]);
+
+        // The rest of that hop keeps its gain before the next one starts.
+        let mut third = vec![1.0; 6];
+        let block = gate.process(&mut third, 1, &[100.0]);
+        assert_eq!(frames(&block), [2, 4]);
+        assert_eq!(third[..2], [second[5]; 2]);
+        assert!(third[2] > third[1]);
+    }
+
+    #[test]
+    fn runs_a_model_of_its_own_on_every_channel() {
+        // Each model scales its channel by its own gain and reports it as LSNR.
+        let mut models = [2.0_f32, -1.0];
+        let noisy = Array2::from_shape_vec((2, 2), vec![0.5, 0.25, 0.5, 0.25]).unwrap();
+        let mut enhanced = Array2::<f32>::zeros((2, 2));
+
+        let lsnr = process_split(
+            &mut models,
+            noisy.view(),
+            enhanced.view_mut(),
+            |gain, noisy, mut enhanced| {
+                assert_eq!(noisy.nrows(), 1);
+                enhanced.assign(&(&noisy * *gain));
+                Ok(*gain)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(lsnr, 2.0);
+        assert_eq!(enhanced.into_raw_vec(), [1.0, 0.5, -0.5, -0.25]);
+    }
+}
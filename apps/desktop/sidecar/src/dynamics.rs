@@ -0,0 +1,444 @@
+//! Dynamics stages for the voice filter chain: a feed-forward compressor and a
+//! split-band de-esser.
+//!
+//! Both run a peak detector linked across channels that releases over the
+//! release time, derive a gain reduction in dB from it that builds up over the
+//! attack time, and report that reduction through `voice_filter.metrics`.
+
+use crate::filters::{Biquad, BiquadFilter};
+use crate::params::check_range;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::ops::RangeInclusive;
+
+const DEFAULT_COMPRESSOR_THRESHOLD_DB: f32 = -24.0;
+const DEFAULT_COMPRESSOR_RATIO: f32 = 3.0;
+const DEFAULT_COMPRESSOR_KNEE_DB: f32 = 6.0;
+const DEFAULT_COMPRESSOR_ATTACK_MS: f32 = 5.0;
+const DEFAULT_COMPRESSOR_RELEASE_MS: f32 = 80.0;
+
+const DEFAULT_DE_ESSER_FREQUENCY_HZ: f32 = 5_500.0;
+const DEFAULT_DE_ESSER_THRESHOLD_DB: f32 = -30.0;
+const DEFAULT_DE_ESSER_RATIO: f32 = 4.0;
+const DEFAULT_DE_ESSER_MAX_REDUCTION_DB: f32 = 10.0;
+/// Sibilants are short; the de-esser has to clamp down within a millisecond
+/// and let go before the following vowel.
+const DE_ESSER_ATTACK_MS: f32 = 1.0;
+const DE_ESSER_RELEASE_MS: f32 = 60.0;
+
+const THRESHOLD_RANGE_DB: RangeInclusive<f32> = -60.0..=0.0;
+const RATIO_RANGE: RangeInclusive<f32> = 1.0..=20.0;
+const KNEE_RANGE_DB: RangeInclusive<f32> = 0.0..=24.0;
+const ATTACK_RANGE_MS: RangeInclusive<f32> = 0.1..=200.0;
+const RELEASE_RANGE_MS: RangeInclusive<f32> = 5.0..=2_000.0;
+const MAKEUP_RANGE_DB: RangeInclusive<f32> = 0.0..=24.0;
+const DE_ESSER_FREQUENCY_RANGE_HZ: RangeInclusive<f32> = 2_000.0..=12_000.0;
+const DE_ESSER_MAX_REDUCTION_RANGE_DB: RangeInclusive<f32> = 0.0..=24.0;
+
+/// Level reported for digital silence instead of negative infinity.
+const SILENCE_DB: f32 = -120.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CompressorOptions {
+    /// Peak level in dBFS above which the signal is compressed.
+    #[serde(default = "default_compressor_threshold_db")]
+    threshold_db: f32,
+    #[serde(default = "default_compressor_ratio")]
+    ratio: f32,
+    /// Width of the soft knee centred on the threshold; 0 is a hard knee.
+    #[serde(default = "default_compressor_knee_db")]
+    knee_db: f32,
+    #[serde(default = "default_compressor_attack_ms")]
+    attack_ms: f32,
+    #[serde(default = "default_compressor_release_ms")]
+    release_ms: f32,
+    #[serde(default)]
+    makeup_db: f32,
+}
+
+fn default_compressor_threshold_db() -> f32 {
+    DEFAULT_COMPRESSOR_THRESHOLD_DB
+}
+
+fn default_compressor_ratio() -> f32 {
+    DEFAULT_COMPRESSOR_RATIO
+}
+
+fn default_compressor_knee_db() -> f32 {
+    DEFAULT_COMPRESSOR_KNEE_DB
+}
+
+fn default_compressor_attack_ms() -> f32 {
+    DEFAULT_COMPRESSOR_ATTACK_MS
+}
+
+fn default_compressor_release_ms() -> f32 {
+    DEFAULT_COMPRESSOR_RELEASE_MS
+}
+
+impl CompressorOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range(
+            "Compressor threshold",
+            self.threshold_db,
+            THRESHOLD_RANGE_DB,
+            " dB",
+        )?;
+        check_range("Compressor ratio", self.ratio, RATIO_RANGE, "")?;
+        check_range("Compressor knee", self.knee_db, KNEE_RANGE_DB, " dB")?;
+        check_range("Compressor attack", self.attack_ms, ATTACK_RANGE_MS, " ms")?;
+        check_range(
+            "Compressor release",
+            self.release_ms,
+            RELEASE_RANGE_MS,
+            " ms",
+        )?;
+        check_range(
+            "Compressor makeup gain",
+            self.makeup_db,
+            MAKEUP_RANGE_DB,
+            " dB",
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DeEsserOptions {
+    /// Crossover above which sibilance is detected and reduced.
+    #[serde(default = "default_de_esser_frequency_hz")]
+    frequency_hz: f32,
+    /// Peak level of the upper band in dBFS above which it is reduced.
+    #[serde(default = "default_de_esser_threshold_db")]
+    threshold_db: f32,
+    #[serde(default = "default_de_esser_ratio")]
+    ratio: f32,
+    #[serde(default = "default_de_esser_max_reduction_db")]
+    max_reduction_db: f32,
+}
+
+fn default_de_esser_frequency_hz() -> f32 {
+    DEFAULT_DE_ESSER_FREQUENCY_HZ
+}
+
+fn default_de_esser_threshold_db() -> f32 {
+    DEFAULT_DE_ESSER_THRESHOLD_DB
+}
+
+fn default_de_esser_ratio() -> f32 {
+    DEFAULT_DE_ESSER_RATIO
+}
+
+fn default_de_esser_max_reduction_db() -> f32 {
+    DEFAULT_DE_ESSER_MAX_REDUCTION_DB
+}
+
+impl DeEsserOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range(
+            "De-esser frequency",
+            self.frequency_hz,
+            DE_ESSER_FREQUENCY_RANGE_HZ,
+            " Hz",
+        )?;
+        check_range(
+            "De-esser threshold",
+            self.threshold_db,
+            THRESHOLD_RANGE_DB,
+            " dB",
+        )?;
+        check_range("De-esser ratio", self.ratio, RATIO_RANGE, "")?;
+        check_range(
+            "De-esser maximum reduction",
+            self.max_reduction_db,
+            DE_ESSER_MAX_REDUCTION_RANGE_DB,
+            " dB",
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GainReductionMetrics {
+    /// Gain reduction applied to the most recent frame.
+    gain_reduction_db: f32,
+    /// Highest gain reduction since the previous `voice_filter.metrics` call.
+    max_gain_reduction_db: f32,
+}
+
+impl GainReductionMetrics {
+    pub(crate) fn new(gain_reduction_db: f32, max_gain_reduction_db: f32) -> Self {
+        Self {
+            gain_reduction_db,
+            max_gain_reduction_db,
+        }
+    }
+}
+
+/// Smoothed gain reduction in dB, always positive.
+struct GainReduction {
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Linear peak envelope: rises instantly, decays over the release time.
+    envelope: f32,
+    current_db: f32,
+    max_db: f32,
+}
+
+impl GainReduction {
+    fn new(attack_ms: f32, release_ms: f32, sample_rate: usize) -> Self {
+        Self {
+            attack_coeff: time_constant_coeff(attack_ms, sample_rate),
+            release_coeff: time_constant_coeff(release_ms, sample_rate),
+            envelope: 0.0,
+            current_db: 0.0,
+            max_db: 0.0,
+        }
+    }
+
+    /// Feeds the peak of one frame through the detector and returns the gain
+    /// reduction `curve` asks for at the detected level, after attack
+    /// smoothing.
+    fn follow(&mut self, frame: &[f32], curve: impl Fn(f32) -> f32) -> f32 {
+        let peak = frame
+            .iter()
+            .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        self.envelope = peak.max(self.envelope * self.release_coeff);
+
+        let target_db = curve(level_db(self.envelope));
+        self.current_db = if target_db > self.current_db {
+            self.current_db * self.attack_coeff + target_db * (1.0 - self.attack_coeff)
+        } else {
+            target_db
+        };
+        self.max_db = self.max_db.max(self.current_db);
+        self.current_db
+    }
+
+    fn take_metrics(&mut self) -> GainReductionMetrics {
+        let metrics = GainReductionMetrics::new(self.current_db, self.max_db);
+        self.max_db = self.current_db;
+        metrics
+    }
+}
+
+fn time_constant_coeff(time_ms: f32, sample_rate: usize) -> f32 {
+    (-1_000.0 / (time_ms * sample_rate as f32)).exp()
+}
+
+/// Static curve of a compressor with a quadratic soft knee; returns the gain
+/// reduction in dB for a `level_db` input.
+fn static_gain_reduction_db(level_db: f32, threshold_db: f32, ratio: f32, knee_db: f32) -> f32 {
+    let overshoot = level_db - threshold_db;
+    let slope = 1.0 - 1.0 / ratio;
+
+    if knee_db > 0.0 && overshoot.abs() <= knee_db / 2.0 {
+        slope * (overshoot + knee_db / 2.0).powi(2) / (2.0 * knee_db)
+    } else if overshoot > 0.0 {
+        slope * overshoot
+    } else {
+        0.0
+    }
+}
+
+fn level_db(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DB;
+    }
+
+    (20.0 * amplitude.log10()).max(SILENCE_DB)
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+pub(crate) struct Compressor {
+    options: CompressorOptions,
+    channels: usize,
+    gain_reduction: GainReduction,
+}
+
+impl Compressor {
+    pub(crate) fn new(options: CompressorOptions, sample_rate: usize, channels: usize) -> Self {
+        Self {
+            options,
+            channels,
+            gain_reduction: GainReduction::new(options.attack_ms, options.release_ms, sample_rate),
+        }
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let CompressorOptions {
+            threshold_db,
+            ratio,
+            knee_db,
+            makeup_db,
+            ..
+        } = self.options;
+
+        for frame in samples.chunks_mut(self.channels.max(1)) {
+            let gain_reduction_db = self.gain_reduction.follow(frame, |level_db| {
+                static_gain_reduction_db(level_db, threshold_db, ratio, knee_db)
+            });
+            let gain = db_to_gain(makeup_db - gain_reduction_db);
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+
+    pub(crate) fn take_metrics(&mut self) -> GainReductionMetrics {
+        self.gain_reduction.take_metrics()
+    }
+}
+
+/// Splits the signal at `frequencyHz` and compresses only the upper band, so
+/// a sibilant is turned down without dulling the rest of the voice. The split
+/// is a Linkwitz-Riley crossover, whose bands sum to a flat response while no
+/// reduction applies.
+pub(crate) struct DeEsser {
+    options: DeEsserOptions,
+    channels: usize,
+    lower_split: [BiquadFilter; 2],
+    upper_split: [BiquadFilter; 2],
+    upper_band: Vec<f32>,
+    gain_reduction: GainReduction,
+}
+
+impl DeEsser {
+    pub(crate) fn new(options: DeEsserOptions, sample_rate: usize, channels: usize) -> Self {
+        // Two Butterworth sections per band make a 4th-order Linkwitz-Riley
+        // crossover.
+        let section = |biquad: fn(f32, f32, f32) -> Biquad| {
+            BiquadFilter::new(
+                biquad(sample_rate as f32, options.frequency_hz, FRAC_1_SQRT_2),
+                channels,
+            )
+        };
+
+        Self {
+            options,
+            channels,
+            lower_split: [section(Biquad::low_pass), section(Biquad::low_pass)],
+            upper_split: [section(Biquad::high_pass), section(Biquad::high_pass)],
+            upper_band: Vec::new(),
+            gain_reduction: GainReduction::new(
+                DE_ESSER_ATTACK_MS,
+                DE_ESSER_RELEASE_MS,
+                sample_rate,
+            ),
+        }
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let DeEsserOptions {
+            threshold_db,
+            ratio,
+            max_reduction_db,
+            ..
+        } = self.options;
+        let channels = self.channels.max(1);
+
+        self.upper_band.clear();
+        self.upper_band.extend_from_slice(samples);
+        for filter in self.upper_split.iter_mut() {
+            filter.process(&mut self.upper_band);
+        }
+        for filter in self.lower_split.iter_mut() {
+            filter.process(samples);
+        }
+
+        for (frame, upper) in samples
+            .chunks_mut(channels)
+            .zip(self.upper_band.chunks(channels))
+        {
+            let gain_reduction_db = self.gain_reduction.follow(upper, |level_db| {
+                static_gain_reduction_db(level_db, threshold_db, ratio, 0.0).min(max_reduction_db)
+            });
+            let upper_gain = db_to_gain(-gain_reduction_db);
+            for (sample, upper) in frame.iter_mut().zip(upper) {
+                *sample += upper * upper_gain;
+            }
+        }
+    }
+
+    pub(crate) fn take_metrics(&mut self) -> GainReductionMetrics {
+        self.gain_reduction.take_metrics()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{static_gain_reduction_db, Compressor, CompressorOptions, DeEsser, DeEsserOptions};
+    use crate::test_signals::{peak, sine};
+
+    const SAMPLE_RATE: usize = 48_000;
+
+    #[test]
+    fn follows_the_static_curve_with_a_soft_knee() {
+        assert_eq!(static_gain_reduction_db(-40.0, -20.0, 4.0, 6.0), 0.0);
+        assert_eq!(static_gain_reduction_db(-8.0, -20.0, 4.0, 0.0), 9.0);
+        assert_eq!(static_gain_reduction_db(-8.0, -20.0, 4.0, 6.0), 9.0);
+        // Halfway into the knee at the threshold itself.
+        assert!((static_gain_reduction_db(-20.0, -20.0, 4.0, 6.0) - 0.5625).abs() < 1e-6);
+    }
+
+    #[test]
+    fn compresses_loud_passages_and_reports_gain_reduction() {
+        let options: CompressorOptions = serde_json::from_value(serde_json::json!({
+            "thresholdDb": -20.0,
+            "ratio": 4.0,
+            "kneeDb": 0.0,
+            "makeupDb": 3.0
+        }))
+        .unwrap();
+        assert!(options.validate().is_ok());
+        let mut compressor = Compressor::new(options, SAMPLE_RATE, 1);
+
+        // -6 dBFS peaks, 14 dB over the threshold: 10.5 dB of reduction.
+        let mut loud = sine(440.0, 0.5, SAMPLE_RATE, SAMPLE_RATE);
+        compressor.process(&mut loud);
+        let expected = 0.5 * 10.0_f32.powf((3.0 - 10.5) / 20.0);
+        assert!((peak(&loud[SAMPLE_RATE / 2..]) - expected).abs() < 0.01);
+
+        let metrics = compressor.take_metrics();
+        assert!((metrics.max_gain_reduction_db - 10.5).abs() < 0.1);
+
+        // Quiet input recovers to the makeup gain alone.
+        let mut quiet = sine(440.0, 0.01, SAMPLE_RATE, SAMPLE_RATE);
+        compressor.process(&mut quiet);
+        assert!(compressor.take_metrics().gain_reduction_db < 0.01);
+        assert!((peak(&quiet[SAMPLE_RATE / 2..]) - 0.01 * 10.0_f32.powf(0.15)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn de_esser_reduces_only_the_upper_band() {
+        let options: DeEsserOptions = serde_json::from_value(serde_json::json!({
+            "thresholdDb": -30.0,
+            "maxReductionDb": 12.0
+        }))
+        .unwrap();
+        let mut de_esser = DeEsser::new(options, SAMPLE_RATE, 1);
+
+        let mut voice = sine(300.0, 0.3, SAMPLE_RATE, SAMPLE_RATE);
+        de_esser.process(&mut voice);
+        assert!((peak(&voice[SAMPLE_RATE / 2..]) - 0.3).abs() < 0.01);
+        assert!(de_esser.take_metrics().max_gain_reduction_db < 0.5);
+
+        let mut sibilant = sine(10_000.0, 0.3, SAMPLE_RATE, SAMPLE_RATE);
+        de_esser.process(&mut sibilant);
+        assert!(peak(&sibilant[SAMPLE_RATE / 2..]) < 0.3 * 10.0_f32.powf(-9.0 / 20.0));
+        assert!((de_esser.take_metrics().gain_reduction_db - 12.0).abs() < 0.1);
+
+        assert_eq!(
+            serde_json::from_value::<DeEsserOptions>(serde_json::json!({ "frequencyHz": 500.0 }))
+                .unwrap()
+                .validate()
+                .unwrap_err(),
+            "De-esser frequency must be between 2000 and 12000 Hz."
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Parametric EQ stage for the voice filter chain.
+//!
+//! `voice_filter.eq.set` replaces the bands of the running session's EQ stage
+//! without restarting it. `voice_filter.eq.response` evaluates the curve of a
+//! set of bands without a session, so the settings UI can draw it while the
+//! user drags a band around.
+
+use crate::filters::{Biquad, BiquadFilter};
+use crate::params::check_range;
+use crate::{result_value, SidecarState, PROTOCOL_VERSION, TARGET_SAMPLE_RATE};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::ops::RangeInclusive;
+
+const MAX_EQ_BANDS: usize = 10;
+const FREQUENCY_RANGE_HZ: RangeInclusive<f32> = 20.0..=20_000.0;
+const GAIN_RANGE_DB: RangeInclusive<f32> = -24.0..=24.0;
+const Q_RANGE: RangeInclusive<f32> = 0.1..=18.0;
+const DEFAULT_RESPONSE_POINTS: usize = 128;
+const RESPONSE_POINTS_RANGE: RangeInclusive<usize> = 2..=1_024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum EqBandType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    HighPass,
+    LowPass,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EqBand {
+    #[serde(rename = "type")]
+    band_type: EqBandType,
+    frequency_hz: f32,
+    /// Ignored by `highPass` and `lowPass`.
+    #[serde(default)]
+    gain_db: f32,
+    #[serde(default = "default_q")]
+    q: f32,
+}
+
+fn default_q() -> f32 {
+    FRAC_1_SQRT_2
+}
+
+impl EqBand {
+    fn biquad(&self, sample_rate: f32) -> Biquad {
+        let Self {
+            frequency_hz,
+            gain_db,
+            q,
+            ..
+        } = *self;
+
+        match self.band_type {
+            EqBandType::Peaking => Biquad::peaking(sample_rate, frequency_hz, q, gain_db),
+            EqBandType::LowShelf => Biquad::low_shelf(sample_rate, frequency_hz, q, gain_db),
+            EqBandType::HighShelf => Biquad::high_shelf(sample_rate, frequency_hz, q, gain_db),
+            EqBandType::HighPass => Biquad::high_pass(sample_rate, frequency_hz, q),
+            EqBandType::LowPass => Biquad::low_pass(sample_rate, frequency_hz, q),
+        }
+    }
+}
+
+pub(crate) fn validate_bands(bands: &[EqBand]) -> Result<(), String> {
+    if bands.len() > MAX_EQ_BANDS {
+        return Err(format!("An EQ can have at most {MAX_EQ_BANDS} bands."));
+    }
+
+    for (index, band) in bands.iter().enumerate() {
+        let number = index + 1;
+        check_range(
+            &format!("EQ band {number}: frequency"),
+            band.frequency_hz,
+            FREQUENCY_RANGE_HZ,
+            " Hz",
+        )?;
+        check_range(
+            &format!("EQ band {number}: gain"),
+            band.gain_db,
+            GAIN_RANGE_DB,
+            " dB",
+        )?;
+        check_range(&format!("EQ band {number}: Q"), band.q, Q_RANGE, "")?;
+    }
+
+    Ok(())
+}
+
+/// One biquad per band, in series.
+pub(crate) struct ParametricEq {
+    sample_rate: usize,
+    channels: usize,
+    filters: Vec<BiquadFilter>,
+}
+
+impl ParametricEq {
+    pub(crate) fn new(bands: &[EqBand], sample_rate: usize, channels: usize) -> Self {
+        let mut eq = Self {
+            sample_rate,
+            channels,
+            filters: Vec::new(),
+        };
+        eq.set_bands(bands);
+        eq
+    }
+
+    /// Retunes the filters in place when the band count is unchanged, so
+    /// dragging a band in the settings UI is click-free.
+    pub(crate) fn set_bands(&mut self, bands: &[EqBand]) {
+        let sample_rate = self.sample_rate as f32;
+
+        if bands.len() == self.filters.len() {
+            for (filter, band) in self.filters.iter_mut().zip(bands) {
+                filter.set_coefficients(band.biquad(sample_rate));
+            }
+            return;
+        }
+
+        self.filters = bands
+            .iter()
+            .map(|band| BiquadFilter::new(band.biquad(sample_rate), self.channels))
+            .collect();
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        for filter in self.filters.iter_mut() {
+            filter.process(samples);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetEqParams {
+    session_id: String,
+    bands: Vec<EqBand>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetEqResult {
+    bands: Vec<EqBand>,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EqResponseParams {
+    bands: Vec<EqBand>,
+    /// Defaults to 48 kHz, the rate voice filter sessions run at.
+    sample_rate: Option<usize>,
+    /// Log-spaced between 20 Hz and 20 kHz; defaults to 128.
+    points: Option<usize>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EqResponsePoint {
+    frequency_hz: f32,
+    gain_db: f32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EqResponseResult {
+    points: Vec<EqResponsePoint>,
+    protocol_version: u32,
+}
+
+pub(crate) fn handle_eq_set(state: &mut SidecarState, params: Value) -> Result<Value, String> {
+    let parsed: SetEqParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+    validate_bands(&parsed.bands)?;
+
+    let Some(session) = state.voice_filter_session.as_mut() else {
+        return Err("No active voice filter session".to_string());
+    };
+
+    if session.session_id != parsed.session_id {
+        return Err("Voice filter session mismatch".to_string());
+    }
+
+    session.chain.set_eq_bands(&parsed.bands)?;
+
+    result_value(SetEqResult {
+        bands: parsed.bands,
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+fn frequency_response(bands: &[EqBand], sample_rate: usize, points: usize) -> Vec<EqResponsePoint> {
+    let sample_rate = sample_rate as f32;
+    let biquads: Vec<Biquad> = bands.iter().map(|band| band.biquad(sample_rate)).collect();
+    let (low, high) = (
+        FREQUENCY_RANGE_HZ.start().ln(),
+        FREQUENCY_RANGE_HZ.end().ln(),
+    );
+    // Frequencies above Nyquist have no meaning at low sample rates.
+    let nyquist = sample_rate / 2.0;
+
+    (0..points)
+        .map(|index| (low + (high - low) * index as f32 / (points - 1) as f32).exp())
+        .filter(|frequency_hz| *frequency_hz < nyquist)
+        .map(|frequency_hz| EqResponsePoint {
+            frequency_hz,
+            gain_db: biquads
+                .iter()
+                .map(|biquad| biquad.magnitude_db(sample_rate, frequency_hz))
+                .sum(),
+        })
+        .collect()
+}
+
+pub(crate) fn handle_eq_response(params: Value) -> Result<Value, String> {
+    let parsed: EqResponseParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+    validate_bands(&parsed.bands)?;
+
+    let points = parsed.points.unwrap_or(DEFAULT_RESPONSE_POINTS);
+    if !RESPONSE_POINTS_RANGE.contains(&points) {
+        return Err(format!(
+            "points must be between {} and {}.",
+            RESPONSE_POINTS_RANGE.start(),
+            RESPONSE_POINTS_RANGE.end()
+        ));
+    }
+
+    let sample_rate = parsed.sample_rate.unwrap_or(TARGET_SAMPLE_RATE as usize);
+    if sample_rate == 0 {
+        return Err("sampleRate must be positive.".to_string());
+    }
+
+    result_value(EqResponseResult {
+        points: frequency_response(&parsed.bands, sample_rate, points),
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frequency_response, validate_bands, EqBand, ParametricEq};
+    use std::f32::consts::PI;
+
+    fn bands(bands: serde_json::Value) -> Vec<EqBand> {
+        serde_json::from_value(bands).unwrap()
+    }
+
+    fn gain_at(response: &[super::EqResponsePoint], frequency_hz: f32) -> f32 {
+        response
+            .iter()
+            .min_by(|left, right| {
+                (left.frequency_hz - frequency_hz)
+                    .abs()
+                    .total_cmp(&(right.frequency_hz - frequency_hz).abs())
+            })
+            .unwrap()
+            .gain_db
+    }
+
+    #[test]
+    fn computes_the_frequency_response_of_all_bands() {
+        let bands = bands(serde_json::json!([
+            { "type": "lowShelf", "frequencyHz": 150.0, "gainDb": -6.0 },
+            { "type": "peaking", "frequencyHz": 3000.0, "gainDb": 4.0, "q": 1.0 },
+            { "type": "lowPass", "frequencyHz": 12000.0 },
+        ]));
+        let response = frequency_response(&bands, 48_000, 512);
+
+        assert_eq!(response.len(), 512);
+        assert!((response[0].frequency_hz - 20.0).abs() < 0.01);
+        assert!((gain_at(&response, 20.0) + 6.0).abs() < 0.3);
+        assert!((gain_at(&response, 3_000.0) - 4.0).abs() < 0.3);
+        assert!(gain_at(&response, 1_000.0).abs() < 1.0);
+        assert!(gain_at(&response, 19_000.0) < -6.0);
+        assert!(frequency_response(&[], 16_000, 64)
+            .iter()
+            .all(|point| point.frequency_hz < 8_000.0 && point.gain_db.abs() < 1e-4));
+    }
+
+    #[test]
+    fn applies_bands_to_the_signal() {
+        let mut eq = ParametricEq::new(
+            &bands(serde_json::json!([
+                { "type": "peaking", "frequencyHz": 1000.0, "gainDb": 6.0, "q": 2.0 }
+            ])),
+            48_000,
+            1,
+        );
+        let mut samples: Vec<f32> = (0..48_000)
+            .map(|index| 0.1 * (2.0 * PI * 1_000.0 * index as f32 / 48_000.0).sin())
+            .collect();
+        eq.process(&mut samples);
+
+        let peak = samples[24_000..]
+            .iter()
+            .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 0.1995).abs() < 0.002);
+    }
+
+    #[test]
+    fn rejects_out_of_range_bands() {
+        let error = |band: serde_json::Value| validate_bands(&bands(band)).unwrap_err();
+
+        assert_eq!(
+            error(serde_json::json!([
+                { "type": "peaking", "frequencyHz": 1000.0 },
+                { "type": "peaking", "frequencyHz": 5.0 },
+            ])),
+            "EQ band 2: frequency must be between 20 and 20000 Hz."
+        );
+        assert_eq!(
+            error(
+                serde_json::json!([{ "type": "highShelf", "frequencyHz": 8000.0, "gainDb": 30.0 }])
+            ),
+            "EQ band 1: gain must be between -24 and 24 dB."
+        );
+        assert_eq!(
+            error(serde_json::json!([{ "type": "highPass", "frequencyHz": 100.0, "q": 0.0 }])),
+            "EQ band 1: Q must be between 0.1 and 18."
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! IIR filters for the voice filter chain: biquads, a DC blocker and an
+//! adaptive mains-hum notch.
+//!
+//! Every filter keeps separate state per channel and works on interleaved
+//! samples in place.
+
+use std::f32::consts::PI;
+
+/// Cutoff of the DC blocker's one-pole high-pass, well below any voice
+/// content.
+const DC_BLOCKER_CUTOFF_HZ: f32 = 10.0;
+/// Narrow enough to leave voice alone around each hum harmonic (~4 Hz wide at
+/// 60 Hz), wide enough to follow a slightly drifting mains frequency.
+const HUM_NOTCH_Q: f32 = 15.0;
+/// Harmonics the detector compares; hum energy is highest in these.
+const HUM_DETECTOR_HARMONICS: u32 = 3;
+/// Distance of the reference bins either side of each harmonic. Hum is a
+/// line; speech and noise are about as loud next to it.
+const HUM_DETECTOR_NEIGHBOUR_HZ: f32 = 4.0;
+/// How much louder than its neighbours a harmonic has to be, on average, to
+/// count as hum.
+const HUM_DETECTOR_MIN_PROMINENCE: f32 = 10.0;
+/// Quietest fundamental worth notching (-70 dBFS); below it the prominence of
+/// near-silent bins means nothing.
+const HUM_DETECTOR_MIN_AMPLITUDE: f32 = 3e-4;
+const HUM_DETECTOR_WINDOW_MS: usize = 1_000;
+
+const MAINS_FREQUENCIES: [u32; 2] = [50, 60];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    fn normalized(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// High-pass from the RBJ audio EQ cookbook; `q` of `FRAC_1_SQRT_2` is
+    /// Butterworth.
+    pub(crate) fn high_pass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+
+        Self::normalized(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub(crate) fn low_pass(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+
+        Self::normalized(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub(crate) fn notch(sample_rate: f32, frequency: f32, q: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+
+        Self::normalized(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    pub(crate) fn peaking(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+        let amplitude = 10.0_f32.powf(gain_db / 40.0);
+
+        Self::normalized(
+            1.0 + alpha * amplitude,
+            -2.0 * cos,
+            1.0 - alpha * amplitude,
+            1.0 + alpha / amplitude,
+            -2.0 * cos,
+            1.0 - alpha / amplitude,
+        )
+    }
+
+    pub(crate) fn low_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+        let amplitude = 10.0_f32.powf(gain_db / 40.0);
+        let shelf = 2.0 * amplitude.sqrt() * alpha;
+
+        Self::normalized(
+            amplitude * ((amplitude + 1.0) - (amplitude - 1.0) * cos + shelf),
+            2.0 * amplitude * ((amplitude - 1.0) - (amplitude + 1.0) * cos),
+            amplitude * ((amplitude + 1.0) - (amplitude - 1.0) * cos - shelf),
+            (amplitude + 1.0) + (amplitude - 1.0) * cos + shelf,
+            -2.0 * ((amplitude - 1.0) + (amplitude + 1.0) * cos),
+            (amplitude + 1.0) + (amplitude - 1.0) * cos - shelf,
+        )
+    }
+
+    pub(crate) fn high_shelf(sample_rate: f32, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let omega = 2.0 * PI * frequency / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+        let amplitude = 10.0_f32.powf(gain_db / 40.0);
+        let shelf = 2.0 * amplitude.sqrt() * alpha;
+
+        Self::normalized(
+            amplitude * ((amplitude + 1.0) + (amplitude - 1.0) * cos + shelf),
+            -2.0 * amplitude * ((amplitude - 1.0) + (amplitude + 1.0) * cos),
+            amplitude * ((amplitude + 1.0) + (amplitude - 1.0) * cos - shelf),
+            (amplitude + 1.0) - (amplitude - 1.0) * cos + shelf,
+            2.0 * ((amplitude - 1.0) - (amplitude + 1.0) * cos),
+            (amplitude + 1.0) - (amplitude - 1.0) * cos - shelf,
+        )
+    }
+
+    /// The BS.1770 K-weighting filter: a +4 dB high shelf modelling the head
+    /// followed by a high-pass. Derived from the analogue prototypes so it
+    /// matches the 48 kHz coefficients of the standard at any sample rate.
+    pub(crate) fn k_weighting(sample_rate: f32) -> [Self; 2] {
+        const SHELF_HZ: f32 = 1_681.974_5;
+        const SHELF_GAIN_DB: f32 = 3.999_844;
+        const SHELF_Q: f32 = 0.707_175_2;
+        const HIGH_PASS_HZ: f32 = 38.135_47;
+        const HIGH_PASS_Q: f32 = 0.500_327;
+
+        let k = (PI * SHELF_HZ / sample_rate).tan();
+        let high_gain = 10.0_f32.powf(SHELF_GAIN_DB / 20.0);
+        let band_gain = high_gain.powf(0.499_666_77);
+        let shelf = Self::normalized(
+            high_gain + band_gain * k / SHELF_Q + k * k,
+            2.0 * (k * k - high_gain),
+            high_gain - band_gain * k / SHELF_Q + k * k,
+            1.0 + k / SHELF_Q + k * k,
+            2.0 * (k * k - 1.0),
+            1.0 - k / SHELF_Q + k * k,
+        );
+
+        let k = (PI * HIGH_PASS_HZ / sample_rate).tan();
+        let high_pass = Self::normalized(
+            1.0,
+            -2.0,
+            1.0,
+            1.0,
+            2.0 * (k * k - 1.0) / (1.0 + k / HIGH_PASS_Q + k * k),
+            (1.0 - k / HIGH_PASS_Q + k * k) / (1.0 + k / HIGH_PASS_Q + k * k),
+        );
+
+        [shelf, high_pass]
+    }
+
+    /// Gain in dB at `frequency`, from the transfer function on the unit
+    /// circle.
+    pub(crate) fn magnitude_db(&self, sample_rate: f32, frequency: f32) -> f32 {
+        let omega = f64::from(2.0 * PI * frequency / sample_rate);
+        let (sin1, cos1) = omega.sin_cos();
+        let (sin2, cos2) = (2.0 * omega).sin_cos();
+        let [b0, b1, b2, a1, a2] = [self.b0, self.b1, self.b2, self.a1, self.a2].map(f64::from);
+
+        let numerator = (b0 + b1 * cos1 + b2 * cos2).powi(2) + (b1 * sin1 + b2 * sin2).powi(2);
+        let denominator = (1.0 + a1 * cos1 + a2 * cos2).powi(2) + (a1 * sin1 + a2 * sin2).powi(2);
+        (10.0 * (numerator / denominator).log10()) as f32
+    }
+}
+
+/// A biquad in transposed direct form II with state per channel.
+pub(crate) struct BiquadFilter {
+    coefficients: Biquad,
+    state: Vec<[f32; 2]>,
+}
+
+impl BiquadFilter {
+    pub(crate) fn new(coefficients: Biquad, channels: usize) -> Self {
+        Self {
+            coefficients,
+            state: vec![[0.0; 2]; channels],
+        }
+    }
+
+    /// Swaps the coefficients but keeps the filter state, so retuning a
+    /// running filter does not click.
+    pub(crate) fn set_coefficients(&mut self, coefficients: Biquad) {
+        self.coefficients = coefficients;
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let Biquad { b0, b1, b2, a1, a2 } = self.coefficients;
+
+        for frame in samples.chunks_mut(self.state.len()) {
+            for (sample, [z1, z2]) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let input = *sample;
+                let output = b0 * input + *z1;
+                *z1 = b1 * input - a1 * output + *z2;
+                *z2 = b2 * input - a2 * output;
+                *sample = output;
+            }
+        }
+    }
+}
+
+pub(crate) struct DcBlocker {
+    coefficient: f32,
+    previous_input: Vec<f32>,
+    previous_output: Vec<f32>,
+}
+
+impl DcBlocker {
+    pub(crate) fn new(sample_rate: usize, channels: usize) -> Self {
+        Self {
+            coefficient: 1.0 - 2.0 * PI * DC_BLOCKER_CUTOFF_HZ / sample_rate as f32,
+            previous_input: vec![0.0; channels],
+            previous_output: vec![0.0; channels],
+        }
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.previous_input.len()) {
+            for (channel_index, sample) in frame.iter_mut().enumerate() {
+                let input = *sample;
+                let output = input - self.previous_input[channel_index]
+                    + self.coefficient * self.previous_output[channel_index];
+                self.previous_input[channel_index] = input;
+                self.previous_output[channel_index] = output;
+                *sample = output;
+            }
+        }
+    }
+}
+
+/// Single-bin DFT, updated one sample at a time. Accumulates in `f64`: a
+/// second of low-frequency bins loses too much precision in `f32`.
+struct Goertzel {
+    coefficient: f64,
+    s1: f64,
+    s2: f64,
+    samples: usize,
+}
+
+impl Goertzel {
+    fn new(sample_rate: f32, frequency: f32) -> Self {
+        Self {
+            coefficient: 2.0
+                * (2.0 * std::f64::consts::PI * f64::from(frequency / sample_rate)).cos(),
+            s1: 0.0,
+            s2: 0.0,
+            samples: 0,
+        }
+    }
+
+    fn push(&mut self, sample: f32) {
+        let s0 = f64::from(sample) + self.coefficient * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+        self.samples += 1;
+    }
+
+    /// Amplitude of a sine at the bin frequency, then starts over.
+    fn take_amplitude(&mut self) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coefficient * self.s1 * self.s2;
+        let amplitude = 2.0 * power.max(0.0).sqrt() / self.samples.max(1) as f64;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        self.samples = 0;
+        amplitude as f32
+    }
+}
+
+/// Decides between 50 and 60 Hz mains from how far the first harmonics of
+/// each stand out from their neighbourhood, over one-second windows.
+struct HumDetector {
+    /// Per mains frequency and harmonic: below, on and above the harmonic.
+    bins: Vec<[Goertzel; 3]>,
+    window_frames: usize,
+    frames: usize,
+}
+
+impl HumDetector {
+    fn new(sample_rate: usize) -> Self {
+        let rate = sample_rate as f32;
+        let bins = MAINS_FREQUENCIES
+            .iter()
+            .flat_map(|mains| (1..=HUM_DETECTOR_HARMONICS).map(move |harmonic| mains * harmonic))
+            .map(|frequency| {
+                let frequency = frequency as f32;
+                [
+                    Goertzel::new(rate, frequency - HUM_DETECTOR_NEIGHBOUR_HZ),
+                    Goertzel::new(rate, frequency),
+                    Goertzel::new(rate, frequency + HUM_DETECTOR_NEIGHBOUR_HZ),
+                ]
+            })
+            .collect();
+
+        Self {
+            bins,
+            window_frames: sample_rate * HUM_DETECTOR_WINDOW_MS / 1_000,
+            frames: 0,
+        }
+    }
+
+    /// Feeds a mono frame; returns a decision at the end of every window in
+    /// which hum was found.
+    fn push(&mut self, sample: f32) -> Option<u32> {
+        for bin in self.bins.iter_mut().flatten() {
+            bin.push(sample);
+        }
+
+        self.frames += 1;
+        if self.frames < self.window_frames {
+            return None;
+        }
+        self.frames = 0;
+
+        let prominence: Vec<f32> = self
+            .bins
+            .chunks_mut(HUM_DETECTOR_HARMONICS as usize)
+            .map(|harmonics| {
+                let amplitudes: Vec<[f32; 3]> = harmonics
+                    .iter_mut()
+                    .map(|bins| bins.each_mut().map(Goertzel::take_amplitude))
+                    .collect();
+                if amplitudes[0][1] < HUM_DETECTOR_MIN_AMPLITUDE {
+                    return 0.0;
+                }
+
+                amplitudes
+                    .iter()
+                    .map(|[below, on, above]| {
+                        let neighbourhood = (below + above) / 2.0;
+                        on / (neighbourhood + f32::MIN_POSITIVE)
+                    })
+                    .sum::<f32>()
+                    / HUM_DETECTOR_HARMONICS as f32
+            })
+            .collect();
+
+        MAINS_FREQUENCIES
+            .iter()
+            .zip(prominence)
+            .filter(|(_, prominence)| *prominence >= HUM_DETECTOR_MIN_PROMINENCE)
+            .max_by(|(_, left), (_, right)| left.total_cmp(right))
+            .map(|(mains, _)| *mains)
+    }
+}
+
+/// Notches mains hum and its harmonics. With a fixed `mains_hz` the notches are
+/// in place from the start; otherwise the signal passes untouched until hum is
+/// detected, and the notches follow later detections.
+pub(crate) struct HumNotch {
+    sample_rate: usize,
+    channels: usize,
+    harmonics: u32,
+    detector: Option<HumDetector>,
+    mains_hz: Option<u32>,
+    notches: Vec<BiquadFilter>,
+}
+
+impl HumNotch {
+    pub(crate) fn new(
+        sample_rate: usize,
+        channels: usize,
+        mains_hz: Option<u32>,
+        harmonics: u32,
+    ) -> Self {
+        let mut notch = Self {
+            sample_rate,
+            channels,
+            harmonics,
+            detector: mains_hz.is_none().then(|| HumDetector::new(sample_rate)),
+            mains_hz: None,
+            notches: Vec::new(),
+        };
+
+        if let Some(mains_hz) = mains_hz {
+            notch.tune(mains_hz);
+        }
+
+        notch
+    }
+
+    /// The mains frequency currently notched, if any.
+    pub(crate) fn mains_hz(&self) -> Option<u32> {
+        self.mains_hz
+    }
+
+    fn tune(&mut self, mains_hz: u32) {
+        if self.mains_hz == Some(mains_hz) {
+            return;
+        }
+
+        self.mains_hz = Some(mains_hz);
+        self.notches = (1..=self.harmonics)
+            .map(|harmonic| {
+                let frequency = (mains_hz * harmonic) as f32;
+                BiquadFilter::new(
+                    Biquad::notch(self.sample_rate as f32, frequency, HUM_NOTCH_Q),
+                    self.channels,
+                )
+            })
+            .collect();
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        if let Some(detector) = self.detector.as_mut() {
+            let mut detected = None;
+            for frame in samples.chunks(self.channels) {
+                let mono = frame.iter().sum::<f32>() / self.channels as f32;
+                detected = detector.push(mono).or(detected);
+            }
+
+            if let Some(mains_hz) = detected {
+                self.tune(mains_hz);
+            }
+        }
+
+        for notch in self.notches.iter_mut() {
+            notch.process(samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Biquad, BiquadFilter, DcBlocker, Goertzel, HumNotch};
+    use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+    const SAMPLE_RATE: usize = 48_000;
+
+    fn sine(frequency: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+        (0..(SAMPLE_RATE as f32 * seconds) as usize)
+            .map(|index| {
+                amplitude * (2.0 * PI * frequency * index as f32 / SAMPLE_RATE as f32).sin()
+            })
+            .collect()
+    }
+
+    fn mix(signals: &[Vec<f32>]) -> Vec<f32> {
+        (0..signals[0].len())
+            .map(|index| signals.iter().map(|signal| signal[index]).sum())
+            .collect()
+    }
+
+    /// Amplitude of `frequency` over the last half second.
+    fn amplitude(samples: &[f32], frequency: f32) -> f32 {
+        let tail = &samples[samples.len() - SAMPLE_RATE / 2..];
+        let mut bin = Goertzel::new(SAMPLE_RATE as f32, frequency);
+        for sample in tail {
+            bin.push(*sample);
+        }
+        bin.take_amplitude()
+    }
+
+    #[test]
+    fn high_pass_removes_rumble_and_keeps_voice() {
+        let mut filter = BiquadFilter::new(
+            Biquad::high_pass(SAMPLE_RATE as f32, 80.0, FRAC_1_SQRT_2),
+            1,
+        );
+        let mut samples = mix(&[sine(20.0, 0.5, 1.0), sine(1_000.0, 0.5, 1.0)]);
+        filter.process(&mut samples);
+
+        assert!(amplitude(&samples, 20.0) < 0.05);
+        assert!((amplitude(&samples, 1_000.0) - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn dc_blocker_removes_offset_per_channel() {
+        let mut blocker = DcBlocker::new(SAMPLE_RATE, 2);
+        let mut samples: Vec<f32> = sine(440.0, 0.3, 1.0)
+            .into_iter()
+            .flat_map(|sample| [sample + 0.2, -0.4])
+            .collect();
+        blocker.process(&mut samples);
+
+        let tail = &samples[samples.len() - 9_600..];
+        let mean = |channel: usize| tail.iter().skip(channel).step_by(2).sum::<f32>() / 4_800.0;
+        assert!(mean(0).abs() < 0.01);
+        assert!(mean(1).abs() < 0.01);
+    }
+
+    #[test]
+    fn hum_notch_detects_and_removes_mains_hum() {
+        let voice = || sine(1_000.0, 0.3, 3.0);
+        let hum = || mix(&[sine(60.0, 0.05, 3.0), sine(120.0, 0.03, 3.0)]);
+
+        let mut notch = HumNotch::new(SAMPLE_RATE, 1, None, 4);
+        let mut samples = mix(&[voice(), hum()]);
+        for block in samples.chunks_mut(480) {
+            notch.process(block);
+        }
+
+        assert_eq!(notch.mains_hz(), Some(60));
+        assert!(amplitude(&samples, 60.0) < 0.005);
+        assert!(amplitude(&samples, 120.0) < 0.003);
+        assert!((amplitude(&samples, 1_000.0) - 0.3).abs() < 0.01);
+
+        // A hum-free signal is left alone.
+        let mut notch = HumNotch::new(SAMPLE_RATE, 1, None, 4);
+        let mut samples = voice();
+        notch.process(&mut samples);
+        assert_eq!(notch.mains_hz(), None);
+        assert_eq!(samples, voice());
+    }
+}
@@ -0,0 +1,434 @@
+//! Global hotkeys for app actions (`hotkeys.set`).
+//!
+//! Each entry binds an action id chosen by the app (`toggleDeafen`,
+//! `soundboard.3`, …) to a keybind and a mode:
+//!
+//! - `hold`: active while the keybind is held, like push-to-talk.
+//! - `toggle`: flips between active and inactive on every press.
+//! - `press`: fires once per press and has no state.
+//!
+//! Every press emits `hotkey.triggered`; `hold` and `toggle` entries also
+//! emit `hotkey.state` when their state changes. Toggle states survive
+//! re-registration as long as the action keeps its id and mode. Hotkeys are
+//! watched separately from the push keybinds, which keep their own method.
+
+use crate::keybind::Keybind;
+use crate::{enqueue_event, result_value, FrameQueue, KeybindWatcher, SidecarState};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum HotkeyMode {
+    Hold,
+    Toggle,
+    Press,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HotkeyBinding {
+    action_id: String,
+    /// Same syntax as the push keybinds, e.g. `Control+Shift+KeyD` or `Mouse5`.
+    keybind: String,
+    mode: HotkeyMode,
+}
+
+/// Replaces every registered hotkey; an empty list unregisters them all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetHotkeysParams {
+    hotkeys: Vec<HotkeyBinding>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HotkeyError {
+    /// Unset when the error affects every hotkey, e.g. no X11 session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action_id: Option<String>,
+    message: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetHotkeysResult {
+    /// Action ids now being watched, in request order.
+    registered: Vec<String>,
+    errors: Vec<HotkeyError>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HotkeyTriggeredEvent<'a> {
+    action_id: &'a str,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HotkeyStateEvent<'a> {
+    action_id: &'a str,
+    active: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum HotkeyEvent {
+    Triggered(String),
+    State(String, bool),
+}
+
+#[derive(Debug)]
+struct HotkeyAction {
+    action_id: String,
+    mode: HotkeyMode,
+    active: bool,
+}
+
+/// Turns keybind presses and releases into hotkey events according to each
+/// action's mode. Indexed like the keybinds handed to the watcher.
+#[derive(Debug, Default)]
+struct HotkeyActions {
+    actions: Vec<HotkeyAction>,
+}
+
+impl HotkeyActions {
+    fn keybind_changed(&mut self, index: usize, pressed: bool) -> Vec<HotkeyEvent> {
+        let Some(action) = self.actions.get_mut(index) else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+
+        if pressed {
+            events.push(HotkeyEvent::Triggered(action.action_id.clone()));
+        }
+
+        let active = match action.mode {
+            HotkeyMode::Hold => pressed,
+            HotkeyMode::Toggle if pressed => !action.active,
+            HotkeyMode::Toggle | HotkeyMode::Press => action.active,
+        };
+
+        if active != action.active {
+            action.active = active;
+            events.push(HotkeyEvent::State(action.action_id.clone(), active));
+        }
+
+        events
+    }
+
+    /// Action ids of toggles that are currently on.
+    fn active_toggles(&self) -> HashSet<String> {
+        self.actions
+            .iter()
+            .filter(|action| action.mode == HotkeyMode::Toggle && action.active)
+            .map(|action| action.action_id.clone())
+            .collect()
+    }
+}
+
+/// The running watcher and the action states it drives.
+pub(crate) struct HotkeyRegistration {
+    watcher: KeybindWatcher,
+    actions: Arc<Mutex<HotkeyActions>>,
+}
+
+fn emit_hotkey_events(frame_queue: &Arc<FrameQueue>, events: Vec<HotkeyEvent>) {
+    for event in events {
+        match event {
+            HotkeyEvent::Triggered(action_id) => enqueue_event(
+                frame_queue,
+                "hotkey.triggered",
+                HotkeyTriggeredEvent {
+                    action_id: &action_id,
+                },
+            ),
+            HotkeyEvent::State(action_id, active) => enqueue_event(
+                frame_queue,
+                "hotkey.state",
+                HotkeyStateEvent {
+                    action_id: &action_id,
+                    active,
+                },
+            ),
+        }
+    }
+}
+
+/// Stops the watcher, which releases any held `hold` hotkeys, and returns the
+/// toggles that were on.
+pub(crate) fn stop_hotkeys(state: &mut SidecarState) -> HashSet<String> {
+    let Some(registration) = state.hotkey_registration.take() else {
+        return HashSet::new();
+    };
+
+    registration.watcher.stop();
+    registration
+        .actions
+        .lock()
+        .map(|actions| actions.active_toggles())
+        .unwrap_or_default()
+}
+
+/// Validates the entries in order. Later entries lose against earlier ones on
+/// duplicate action ids and overlapping keybinds.
+fn validate_hotkeys(
+    hotkeys: &[HotkeyBinding],
+    parse_keybind: impl Fn(Option<&str>) -> Result<Option<Keybind>, String>,
+) -> (Vec<(&HotkeyBinding, Keybind)>, Vec<HotkeyError>) {
+    let mut accepted: Vec<(&HotkeyBinding, Keybind)> = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen_action_ids = HashSet::new();
+
+    for hotkey in hotkeys {
+        let error = |message: String| HotkeyError {
+            action_id: Some(hotkey.action_id.clone()),
+            message,
+        };
+
+        if hotkey.action_id.trim().is_empty() {
+            errors.push(error("Action id is empty.".to_string()));
+            continue;
+        }
+
+        if !seen_action_ids.insert(hotkey.action_id.as_str()) {
+            errors.push(error("Duplicate action id.".to_string()));
+            continue;
+        }
+
+        let keybind = match parse_keybind(Some(&hotkey.keybind)) {
+            Ok(Some(keybind)) => keybind,
+            Ok(None) => {
+                errors.push(error("Keybind is empty.".to_string()));
+                continue;
+            }
+            Err(message) => {
+                errors.push(error(format!("Keybind is invalid: {message}")));
+                continue;
+            }
+        };
+
+        if let Some((other, _)) = accepted
+            .iter()
+            .find(|(_, other_keybind)| other_keybind.overlaps(&keybind))
+        {
+            errors.push(error(format!(
+                "Keybind conflicts with \"{}\" and was ignored.",
+                other.action_id
+            )));
+            continue;
+        }
+
+        accepted.push((hotkey, keybind));
+    }
+
+    (accepted, errors)
+}
+
+pub(crate) fn handle_hotkeys_set(
+    frame_queue: Arc<FrameQueue>,
+    state: &mut SidecarState,
+    params: Value,
+) -> Result<Value, String> {
+    let parsed: SetHotkeysParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    result_value(set_hotkeys(frame_queue, state, parsed))
+}
+
+pub(crate) fn set_hotkeys(
+    frame_queue: Arc<FrameQueue>,
+    state: &mut SidecarState,
+    parsed: SetHotkeysParams,
+) -> SetHotkeysResult {
+    let mut active_toggles = stop_hotkeys(state);
+    state.hotkeys = parsed.clone();
+
+    #[cfg(any(windows, target_os = "linux"))]
+    let result = {
+        #[cfg(target_os = "linux")]
+        use crate::linux_keybinds::{parse_global_keybind, start_keybind_watcher};
+        #[cfg(windows)]
+        use crate::{parse_global_keybind, start_keybind_watcher};
+
+        let (accepted, mut errors) = validate_hotkeys(&parsed.hotkeys, parse_global_keybind);
+        let mut registered = Vec::new();
+
+        if !accepted.is_empty() {
+            let mut keybinds = Vec::new();
+            let mut actions = HotkeyActions::default();
+
+            for (hotkey, keybind) in accepted {
+                keybinds.push(keybind);
+                actions.actions.push(HotkeyAction {
+                    action_id: hotkey.action_id.clone(),
+                    mode: hotkey.mode,
+                    active: hotkey.mode == HotkeyMode::Toggle
+                        && active_toggles.remove(&hotkey.action_id),
+                });
+            }
+
+            let action_ids: Vec<String> = actions
+                .actions
+                .iter()
+                .map(|action| action.action_id.clone())
+                .collect();
+            let actions = Arc::new(Mutex::new(actions));
+            let watcher_actions = Arc::clone(&actions);
+            let watcher_queue = Arc::clone(&frame_queue);
+            let on_change = move |index: usize, pressed: bool| {
+                let events = match watcher_actions.lock() {
+                    Ok(mut actions) => actions.keybind_changed(index, pressed),
+                    Err(_) => return,
+                };
+                emit_hotkey_events(&watcher_queue, events);
+            };
+
+            match start_keybind_watcher(keybinds, Box::new(on_change)) {
+                Ok(watcher) => {
+                    state.hotkey_registration = Some(HotkeyRegistration { watcher, actions });
+                    registered = action_ids;
+                }
+                Err(message) => {
+                    // Toggles that were carried over are off again.
+                    if let Ok(actions) = actions.lock() {
+                        active_toggles.extend(actions.active_toggles());
+                    }
+                    errors.push(HotkeyError {
+                        action_id: None,
+                        message,
+                    });
+                }
+            }
+        }
+
+        SetHotkeysResult { registered, errors }
+    };
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let result = SetHotkeysResult {
+        registered: Vec::new(),
+        errors: parsed
+            .hotkeys
+            .iter()
+            .map(|hotkey| HotkeyError {
+                action_id: Some(hotkey.action_id.clone()),
+                message: "Global hotkeys are only available on Windows and Linux.".to_string(),
+            })
+            .collect(),
+    };
+
+    let mut turned_off: Vec<String> = active_toggles.into_iter().collect();
+    turned_off.sort();
+    emit_hotkey_events(
+        &frame_queue,
+        turned_off
+            .into_iter()
+            .map(|action_id| HotkeyEvent::State(action_id, false))
+            .collect(),
+    );
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        validate_hotkeys, HotkeyAction, HotkeyActions, HotkeyBinding, HotkeyEvent, HotkeyMode,
+    };
+    use crate::keybind::Keybind;
+
+    fn binding(action_id: &str, keybind: &str, mode: HotkeyMode) -> HotkeyBinding {
+        HotkeyBinding {
+            action_id: action_id.to_string(),
+            keybind: keybind.to_string(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn drives_action_states_by_mode() {
+        let mut actions = HotkeyActions {
+            actions: [
+                ("deafen", HotkeyMode::Toggle),
+                ("whisper", HotkeyMode::Hold),
+                ("soundboard.1", HotkeyMode::Press),
+            ]
+            .into_iter()
+            .map(|(action_id, mode)| HotkeyAction {
+                action_id: action_id.to_string(),
+                mode,
+                active: false,
+            })
+            .collect(),
+        };
+        let triggered = |action_id: &str| HotkeyEvent::Triggered(action_id.to_string());
+        let state = |action_id: &str, active| HotkeyEvent::State(action_id.to_string(), active);
+
+        assert_eq!(
+            actions.keybind_changed(0, true),
+            [triggered("deafen"), state("deafen", true)]
+        );
+        assert_eq!(actions.keybind_changed(0, false), []);
+        assert_eq!(
+            actions.keybind_changed(0, true),
+            [triggered("deafen"), state("deafen", false)]
+        );
+        assert_eq!(
+            actions.keybind_changed(1, true),
+            [triggered("whisper"), state("whisper", true)]
+        );
+        assert_eq!(actions.keybind_changed(1, false), [state("whisper", false)]);
+        assert_eq!(
+            actions.keybind_changed(2, true),
+            [triggered("soundboard.1")]
+        );
+        assert_eq!(actions.keybind_changed(2, false), []);
+        assert_eq!(actions.keybind_changed(3, true), []);
+    }
+
+    #[test]
+    fn rejects_invalid_and_conflicting_entries() {
+        let hotkeys = [
+            binding("deafen", "Control+Shift+KeyD", HotkeyMode::Toggle),
+            binding("screenShare", "ControlLeft+Shift+KeyD", HotkeyMode::Toggle),
+            binding("deafen", "F9", HotkeyMode::Press),
+            binding("", "F10", HotkeyMode::Press),
+            binding("mute", "", HotkeyMode::Toggle),
+            binding("soundboard.1", "Control+Nope", HotkeyMode::Press),
+            binding("soundboard.2", "Mouse5", HotkeyMode::Press),
+        ];
+
+        let (accepted, errors) = validate_hotkeys(&hotkeys, Keybind::parse_optional);
+
+        let accepted_ids: Vec<&str> = accepted
+            .iter()
+            .map(|(hotkey, _)| hotkey.action_id.as_str())
+            .collect();
+        assert_eq!(accepted_ids, ["deafen", "soundboard.2"]);
+
+        let errors: Vec<(Option<&str>, &str)> = errors
+            .iter()
+            .map(|error| (error.action_id.as_deref(), error.message.as_str()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (
+                    Some("screenShare"),
+                    "Keybind conflicts with \"deafen\" and was ignored."
+                ),
+                (Some("deafen"), "Duplicate action id."),
+                (Some(""), "Action id is empty."),
+                (Some("mute"), "Keybind is empty."),
+                (
+                    Some("soundboard.1"),
+                    "Keybind is invalid: Unknown key code \"Nope\"."
+                ),
+            ]
+        );
+    }
+}
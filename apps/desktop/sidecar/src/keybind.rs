@@ -0,0 +1,731 @@
+//! Platform-independent model of global keybinds.
+//!
+//! Keybinds are `+`-separated tokens: any number of modifiers followed by one
+//! key named by its DOM `KeyboardEvent.code` (`Control+Shift+KeyX`) or a
+//! mouse button (`Mouse4`). `Control`, `Alt`, `Shift` and `Meta` accept either
+//! side of the keyboard; `ControlLeft`, `AltRight` and so on require that
+//! side. A keybind made only of modifiers uses its last sided modifier as the
+//! key, so `ControlRight` on its own binds the right Control key.
+//!
+//! Matching is exact: modifiers a keybind doesn't name must be released. Each
+//! key carries its Windows virtual-key code and Linux input event code, so the
+//! watchers only need to report which keys are down.
+
+use std::fmt;
+
+/// Offset between Linux input event codes and X11 keycodes on evdev servers.
+const X11_EVDEV_KEYCODE_OFFSET: u16 = 8;
+
+macro_rules! keys {
+    ($($key:ident => $windows_virtual_key:literal, $evdev_code:literal;)*) => {
+        /// A physical key, named after its DOM `KeyboardEvent.code`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub(crate) enum Key {
+            $($key,)*
+        }
+
+        impl Key {
+            #[cfg(any(windows, test))]
+            pub(crate) const ALL: &'static [Key] = &[$(Key::$key,)*];
+
+            pub(crate) fn from_code(code: &str) -> Option<Self> {
+                match code {
+                    $(stringify!($key) => Some(Self::$key),)*
+                    _ => None,
+                }
+            }
+
+            pub(crate) fn code(self) -> &'static str {
+                match self {
+                    $(Self::$key => stringify!($key),)*
+                }
+            }
+
+            #[cfg_attr(not(windows), allow(dead_code))]
+            pub(crate) fn windows_virtual_key(self) -> u16 {
+                match self {
+                    $(Self::$key => $windows_virtual_key,)*
+                }
+            }
+
+            /// The Linux input event code (`KEY_*` in `input-event-codes.h`).
+            #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+            pub(crate) fn evdev_code(self) -> u16 {
+                match self {
+                    $(Self::$key => $evdev_code,)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    KeyA => 0x41, 30;
+    KeyB => 0x42, 48;
+    KeyC => 0x43, 46;
+    KeyD => 0x44, 32;
+    KeyE => 0x45, 18;
+    KeyF => 0x46, 33;
+    KeyG => 0x47, 34;
+    KeyH => 0x48, 35;
+    KeyI => 0x49, 23;
+    KeyJ => 0x4A, 36;
+    KeyK => 0x4B, 37;
+    KeyL => 0x4C, 38;
+    KeyM => 0x4D, 50;
+    KeyN => 0x4E, 49;
+    KeyO => 0x4F, 24;
+    KeyP => 0x50, 25;
+    KeyQ => 0x51, 16;
+    KeyR => 0x52, 19;
+    KeyS => 0x53, 31;
+    KeyT => 0x54, 20;
+    KeyU => 0x55, 22;
+    KeyV => 0x56, 47;
+    KeyW => 0x57, 17;
+    KeyX => 0x58, 45;
+    KeyY => 0x59, 21;
+    KeyZ => 0x5A, 44;
+    Digit0 => 0x30, 11;
+    Digit1 => 0x31, 2;
+    Digit2 => 0x32, 3;
+    Digit3 => 0x33, 4;
+    Digit4 => 0x34, 5;
+    Digit5 => 0x35, 6;
+    Digit6 => 0x36, 7;
+    Digit7 => 0x37, 8;
+    Digit8 => 0x38, 9;
+    Digit9 => 0x39, 10;
+    F1 => 0x70, 59;
+    F2 => 0x71, 60;
+    F3 => 0x72, 61;
+    F4 => 0x73, 62;
+    F5 => 0x74, 63;
+    F6 => 0x75, 64;
+    F7 => 0x76, 65;
+    F8 => 0x77, 66;
+    F9 => 0x78, 67;
+    F10 => 0x79, 68;
+    F11 => 0x7A, 87;
+    F12 => 0x7B, 88;
+    F13 => 0x7C, 183;
+    F14 => 0x7D, 184;
+    F15 => 0x7E, 185;
+    F16 => 0x7F, 186;
+    F17 => 0x80, 187;
+    F18 => 0x81, 188;
+    F19 => 0x82, 189;
+    F20 => 0x83, 190;
+    F21 => 0x84, 191;
+    F22 => 0x85, 192;
+    F23 => 0x86, 193;
+    F24 => 0x87, 194;
+    Numpad0 => 0x60, 82;
+    Numpad1 => 0x61, 79;
+    Numpad2 => 0x62, 80;
+    Numpad3 => 0x63, 81;
+    Numpad4 => 0x64, 75;
+    Numpad5 => 0x65, 76;
+    Numpad6 => 0x66, 77;
+    Numpad7 => 0x67, 71;
+    Numpad8 => 0x68, 72;
+    Numpad9 => 0x69, 73;
+    NumpadMultiply => 0x6A, 55;
+    NumpadAdd => 0x6B, 78;
+    NumpadSubtract => 0x6D, 74;
+    NumpadDecimal => 0x6E, 83;
+    NumpadDivide => 0x6F, 98;
+    // Windows reports both Enter keys as VK_RETURN.
+    NumpadEnter => 0x0D, 96;
+    Space => 0x20, 57;
+    Enter => 0x0D, 28;
+    Escape => 0x1B, 1;
+    Backspace => 0x08, 14;
+    Tab => 0x09, 15;
+    CapsLock => 0x14, 58;
+    NumLock => 0x90, 69;
+    ScrollLock => 0x91, 70;
+    PrintScreen => 0x2C, 99;
+    Pause => 0x13, 119;
+    ContextMenu => 0x5D, 127;
+    ArrowUp => 0x26, 103;
+    ArrowDown => 0x28, 108;
+    ArrowLeft => 0x25, 105;
+    ArrowRight => 0x27, 106;
+    Delete => 0x2E, 111;
+    Insert => 0x2D, 110;
+    Home => 0x24, 102;
+    End => 0x23, 107;
+    PageUp => 0x21, 104;
+    PageDown => 0x22, 109;
+    Minus => 0xBD, 12;
+    Equal => 0xBB, 13;
+    BracketLeft => 0xDB, 26;
+    BracketRight => 0xDD, 27;
+    Backslash => 0xDC, 43;
+    IntlBackslash => 0xE2, 86;
+    Semicolon => 0xBA, 39;
+    Quote => 0xDE, 40;
+    Comma => 0xBC, 51;
+    Period => 0xBE, 52;
+    Slash => 0xBF, 53;
+    Backquote => 0xC0, 41;
+    ControlLeft => 0xA2, 29;
+    ControlRight => 0xA3, 97;
+    ShiftLeft => 0xA0, 42;
+    ShiftRight => 0xA1, 54;
+    AltLeft => 0xA4, 56;
+    AltRight => 0xA5, 100;
+    MetaLeft => 0x5B, 125;
+    MetaRight => 0x5C, 126;
+}
+
+impl Key {
+    /// The X11 keycode on servers using the evdev driver, which covers Xorg,
+    /// XWayland and Xvfb.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn x11_keycode(self) -> u16 {
+        self.evdev_code() + X11_EVDEV_KEYCODE_OFFSET
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Modifier {
+    Control,
+    Alt,
+    Shift,
+    Meta,
+}
+
+impl Modifier {
+    /// Canonical serialization order.
+    pub(crate) const ALL: [Modifier; 4] = [Self::Control, Self::Alt, Self::Shift, Self::Meta];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Control => "Control",
+            Self::Alt => "Alt",
+            Self::Shift => "Shift",
+            Self::Meta => "Meta",
+        }
+    }
+
+    pub(crate) fn keys(self) -> [Key; 2] {
+        match self {
+            Self::Control => [Key::ControlLeft, Key::ControlRight],
+            Self::Alt => [Key::AltLeft, Key::AltRight],
+            Self::Shift => [Key::ShiftLeft, Key::ShiftRight],
+            Self::Meta => [Key::MetaLeft, Key::MetaRight],
+        }
+    }
+}
+
+/// Which side of one modifier a keybind requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ModifierSide {
+    Either,
+    Left,
+    Right,
+}
+
+/// Parses a modifier token; `Ctrl` and `Command` are accepted aliases.
+fn parse_modifier(token: &str) -> Option<(Modifier, ModifierSide)> {
+    let (name, side) = if let Some(name) = token.strip_suffix("Left") {
+        (name, ModifierSide::Left)
+    } else if let Some(name) = token.strip_suffix("Right") {
+        (name, ModifierSide::Right)
+    } else {
+        (token, ModifierSide::Either)
+    };
+
+    let modifier = match (name, side) {
+        ("Control", _) | ("Ctrl", ModifierSide::Either) => Modifier::Control,
+        ("Alt", _) => Modifier::Alt,
+        ("Shift", _) => Modifier::Shift,
+        ("Meta", _) | ("Command", ModifierSide::Either) => Modifier::Meta,
+        _ => return None,
+    };
+
+    Some((modifier, side))
+}
+
+/// The modifiers a keybind requires, by side.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct ModifierSet {
+    control: Option<ModifierSide>,
+    alt: Option<ModifierSide>,
+    shift: Option<ModifierSide>,
+    meta: Option<ModifierSide>,
+}
+
+impl ModifierSet {
+    pub(crate) fn get(&self, modifier: Modifier) -> Option<ModifierSide> {
+        match modifier {
+            Modifier::Control => self.control,
+            Modifier::Alt => self.alt,
+            Modifier::Shift => self.shift,
+            Modifier::Meta => self.meta,
+        }
+    }
+
+    pub(crate) fn with(mut self, modifier: Modifier, side: ModifierSide) -> Self {
+        let slot = match modifier {
+            Modifier::Control => &mut self.control,
+            Modifier::Alt => &mut self.alt,
+            Modifier::Shift => &mut self.shift,
+            Modifier::Meta => &mut self.meta,
+        };
+        *slot = Some(side);
+        self
+    }
+}
+
+/// A mouse button, numbered like most voice apps: `Mouse1` left, `Mouse2`
+/// right, `Mouse3` middle, `Mouse4`/`Mouse5` back/forward, then extra buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct MouseButton(u8);
+
+impl MouseButton {
+    pub(crate) const MAX: u8 = 16;
+
+    pub(crate) fn new(number: u8) -> Option<Self> {
+        (1..=Self::MAX).contains(&number).then_some(Self(number))
+    }
+
+    pub(crate) fn number(self) -> u8 {
+        self.0
+    }
+
+    fn parse(token: &str) -> Option<Self> {
+        let digits = token.strip_prefix("Mouse")?;
+        if digits.starts_with('0') {
+            return None;
+        }
+
+        Self::new(digits.parse().ok()?)
+    }
+
+    /// `VK_LBUTTON` … `VK_XBUTTON2`; Windows has no virtual keys for
+    /// buttons past the fifth.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn windows_virtual_key(self) -> Option<u16> {
+        match self.0 {
+            1 => Some(0x01),
+            2 => Some(0x02),
+            3 => Some(0x04),
+            4 => Some(0x05),
+            5 => Some(0x06),
+            _ => None,
+        }
+    }
+
+    /// The X11 core button number. X11 numbers the middle button 2 and uses
+    /// 4–7 for scrolling, so back/forward and extra buttons start at 8.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn x11_button(self) -> u32 {
+        match self.0 {
+            1 => 1,
+            2 => 3,
+            3 => 2,
+            number => u32::from(number) + 4,
+        }
+    }
+}
+
+/// What a keybind waits for besides its modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Trigger {
+    Key(Key),
+    Mouse(MouseButton),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Keybind {
+    pub(crate) modifiers: ModifierSet,
+    pub(crate) trigger: Trigger,
+}
+
+impl Keybind {
+    /// Parses a keybind string; blank strings are `None`.
+    pub(crate) fn parse(keybind: &str) -> Result<Option<Self>, String> {
+        let tokens: Vec<&str> = keybind
+            .split('+')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .collect();
+
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let mut modifiers: Vec<(Modifier, ModifierSide, &str)> = Vec::new();
+        let mut trigger_token: Option<&str> = None;
+
+        for token in tokens {
+            if let Some((modifier, side)) = parse_modifier(token) {
+                modifiers.push((modifier, side, token));
+                continue;
+            }
+
+            if trigger_token.is_some() {
+                return Err("Invalid keybind format.".to_string());
+            }
+
+            trigger_token = Some(token);
+        }
+
+        let trigger_token = match trigger_token {
+            Some(token) => token,
+            None => {
+                let sided = modifiers
+                    .iter()
+                    .rposition(|(_, side, _)| *side != ModifierSide::Either)
+                    .ok_or_else(|| "Missing key code in keybind.".to_string())?;
+                modifiers.remove(sided).2
+            }
+        };
+
+        let trigger = match Key::from_code(trigger_token) {
+            Some(key) => Trigger::Key(key),
+            None => Trigger::Mouse(
+                MouseButton::parse(trigger_token)
+                    .ok_or_else(|| format!("Unknown key code \"{trigger_token}\"."))?,
+            ),
+        };
+
+        let mut modifier_set = ModifierSet::default();
+        for (modifier, side, _) in modifiers {
+            if modifier_set
+                .get(modifier)
+                .is_some_and(|existing| existing != side)
+            {
+                return Err("Invalid keybind format.".to_string());
+            }
+
+            modifier_set = modifier_set.with(modifier, side);
+        }
+
+        if let Trigger::Key(key) = trigger {
+            if Modifier::ALL.iter().any(|modifier| {
+                modifier.keys().contains(&key) && modifier_set.get(*modifier).is_some()
+            }) {
+                return Err("Invalid keybind format.".to_string());
+            }
+        }
+
+        Ok(Some(Self {
+            modifiers: modifier_set,
+            trigger,
+        }))
+    }
+
+    /// Parses an optional keybind as sent in RPC params.
+    pub(crate) fn parse_optional(keybind: Option<&str>) -> Result<Option<Self>, String> {
+        keybind.map_or(Ok(None), Self::parse)
+    }
+
+    /// Whether one press can activate both keybinds: they share a trigger and
+    /// some set of held modifiers satisfies both, e.g. `Control+KeyA` and
+    /// `ControlLeft+KeyA`.
+    pub(crate) fn overlaps(&self, other: &Self) -> bool {
+        self.trigger == other.trigger
+            && Modifier::ALL.iter().all(|modifier| {
+                match (
+                    self.modifiers.get(*modifier),
+                    other.modifiers.get(*modifier),
+                ) {
+                    (None, None) => true,
+                    (Some(side), Some(other_side)) => {
+                        side == other_side
+                            || side == ModifierSide::Either
+                            || other_side == ModifierSide::Either
+                    }
+                    _ => false,
+                }
+            })
+    }
+
+    /// Whether the keybind is held, given which keys and mouse buttons are
+    /// down.
+    pub(crate) fn is_active(
+        &self,
+        key_down: impl Fn(Key) -> bool,
+        button_down: impl Fn(MouseButton) -> bool,
+    ) -> bool {
+        let trigger_down = match self.trigger {
+            Trigger::Key(key) => key_down(key),
+            Trigger::Mouse(button) => button_down(button),
+        };
+
+        trigger_down
+            && Modifier::ALL.iter().all(|modifier| {
+                // A sided modifier used as the trigger doesn't count as held.
+                let [left, right] = modifier
+                    .keys()
+                    .map(|key| self.trigger != Trigger::Key(key) && key_down(key));
+
+                match self.modifiers.get(*modifier) {
+                    None => !left && !right,
+                    Some(ModifierSide::Either) => left || right,
+                    Some(ModifierSide::Left) => left && !right,
+                    Some(ModifierSide::Right) => right && !left,
+                }
+            })
+    }
+}
+
+/// The canonical form: modifiers in `Control`, `Alt`, `Shift`, `Meta` order,
+/// then the key.
+impl fmt::Display for Keybind {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for modifier in Modifier::ALL {
+            let suffix = match self.modifiers.get(modifier) {
+                None => continue,
+                Some(ModifierSide::Either) => "",
+                Some(ModifierSide::Left) => "Left",
+                Some(ModifierSide::Right) => "Right",
+            };
+            write!(formatter, "{}{suffix}+", modifier.name())?;
+        }
+
+        match self.trigger {
+            Trigger::Key(key) => formatter.write_str(key.code()),
+            Trigger::Mouse(button) => write!(formatter, "Mouse{}", button.number()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, Keybind, Modifier, ModifierSet, ModifierSide, MouseButton, Trigger};
+    use std::collections::HashSet;
+
+    fn keybind(modifiers: &[(Modifier, ModifierSide)], trigger: Trigger) -> Keybind {
+        Keybind {
+            modifiers: modifiers
+                .iter()
+                .fold(ModifierSet::default(), |set, (modifier, side)| {
+                    set.with(*modifier, *side)
+                }),
+            trigger,
+        }
+    }
+
+    #[test]
+    fn parses_keybinds() {
+        use Modifier::*;
+        use ModifierSide::*;
+
+        let cases = [
+            ("KeyA", keybind(&[], Trigger::Key(Key::KeyA))),
+            (
+                "Control+Shift+KeyX",
+                keybind(
+                    &[(Control, Either), (Shift, Either)],
+                    Trigger::Key(Key::KeyX),
+                ),
+            ),
+            (
+                " Ctrl + Command + F13 ",
+                keybind(&[(Control, Either), (Meta, Either)], Trigger::Key(Key::F13)),
+            ),
+            (
+                "AltRight+ShiftLeft+Space",
+                keybind(&[(Alt, Right), (Shift, Left)], Trigger::Key(Key::Space)),
+            ),
+            (
+                "ControlRight",
+                keybind(&[], Trigger::Key(Key::ControlRight)),
+            ),
+            (
+                "Shift+AltLeft",
+                keybind(&[(Shift, Either)], Trigger::Key(Key::AltLeft)),
+            ),
+            (
+                "Control+Mouse4",
+                keybind(
+                    &[(Control, Either)],
+                    Trigger::Mouse(MouseButton::new(4).unwrap()),
+                ),
+            ),
+            (
+                "Control+Control+KeyA",
+                keybind(&[(Control, Either)], Trigger::Key(Key::KeyA)),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(Keybind::parse(input), Ok(Some(expected)), "{input}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_keybinds() {
+        let cases = [
+            ("KeyA+KeyB", "Invalid keybind format."),
+            ("Control+ControlLeft+KeyA", "Invalid keybind format."),
+            ("ControlLeft+ControlLeft", "Invalid keybind format."),
+            ("Control+Shift", "Missing key code in keybind."),
+            ("CtrlLeft", "Unknown key code \"CtrlLeft\"."),
+            ("Control+KeyÄ", "Unknown key code \"KeyÄ\"."),
+            ("F25", "Unknown key code \"F25\"."),
+            ("Mouse0", "Unknown key code \"Mouse0\"."),
+            ("Mouse04", "Unknown key code \"Mouse04\"."),
+            ("Mouse17", "Unknown key code \"Mouse17\"."),
+        ];
+
+        for (input, error) in cases {
+            assert_eq!(Keybind::parse(input), Err(error.to_string()), "{input}");
+        }
+
+        assert_eq!(Keybind::parse(""), Ok(None));
+        assert_eq!(Keybind::parse(" + "), Ok(None));
+        assert_eq!(Keybind::parse_optional(None), Ok(None));
+    }
+
+    #[test]
+    fn serializes_canonically() {
+        let cases = [
+            ("Shift+Ctrl+KeyA", "Control+Shift+KeyA"),
+            ("Meta+AltLeft+Digit1", "AltLeft+Meta+Digit1"),
+            ("Command+Mouse5", "Meta+Mouse5"),
+            ("ShiftRight", "ShiftRight"),
+            ("Alt+ControlRight", "Alt+ControlRight"),
+        ];
+
+        for (input, canonical) in cases {
+            let parsed = Keybind::parse(input).unwrap().unwrap();
+            assert_eq!(parsed.to_string(), canonical, "{input}");
+            assert_eq!(Keybind::parse(canonical), Ok(Some(parsed)), "{canonical}");
+        }
+
+        for key in Key::ALL {
+            let parsed = Keybind::parse(key.code()).unwrap().unwrap();
+            assert_eq!(parsed.trigger, Trigger::Key(*key));
+            assert_eq!(parsed.to_string(), key.code());
+        }
+    }
+
+    #[test]
+    fn maps_keys_to_platform_codes() {
+        let cases = [
+            (Key::KeyA, 0x41, 30, 38),
+            (Key::KeyQ, 0x51, 16, 24),
+            (Key::Digit0, 0x30, 11, 19),
+            (Key::F1, 0x70, 59, 67),
+            (Key::F13, 0x7C, 183, 191),
+            (Key::Space, 0x20, 57, 65),
+            (Key::ArrowUp, 0x26, 103, 111),
+            (Key::ControlRight, 0xA3, 97, 105),
+            (Key::MetaLeft, 0x5B, 125, 133),
+        ];
+
+        for (key, virtual_key, evdev_code, x11_keycode) in cases {
+            assert_eq!(key.windows_virtual_key(), virtual_key, "{key:?}");
+            assert_eq!(key.evdev_code(), evdev_code, "{key:?}");
+            assert_eq!(key.x11_keycode(), x11_keycode, "{key:?}");
+        }
+
+        // Only the two Enter keys share a Windows virtual key.
+        let evdev_codes: HashSet<u16> = Key::ALL.iter().map(|key| key.evdev_code()).collect();
+        let virtual_keys: HashSet<u16> = Key::ALL
+            .iter()
+            .map(|key| key.windows_virtual_key())
+            .collect();
+        assert_eq!(evdev_codes.len(), Key::ALL.len());
+        assert_eq!(virtual_keys.len(), Key::ALL.len() - 1);
+    }
+
+    #[test]
+    fn maps_mouse_buttons_to_platform_buttons() {
+        let cases = [
+            (1, Some(0x01), 1),
+            (2, Some(0x02), 3),
+            (3, Some(0x04), 2),
+            (4, Some(0x05), 8),
+            (5, Some(0x06), 9),
+            (6, None, 10),
+        ];
+
+        for (number, virtual_key, x11_button) in cases {
+            let button = MouseButton::new(number).unwrap();
+            assert_eq!(button.windows_virtual_key(), virtual_key, "Mouse{number}");
+            assert_eq!(button.x11_button(), x11_button, "Mouse{number}");
+        }
+    }
+
+    #[test]
+    fn detects_overlapping_keybinds() {
+        let cases = [
+            ("Control+KeyA", "Ctrl+KeyA", true),
+            ("Control+KeyA", "ControlLeft+KeyA", true),
+            ("ControlLeft+KeyA", "ControlRight+KeyA", false),
+            ("Control+KeyA", "KeyA", false),
+            ("Control+KeyA", "Control+Shift+KeyA", false),
+            ("Control+KeyA", "Control+KeyB", false),
+            ("Mouse4", "Mouse4", true),
+            ("Mouse4", "Mouse5", false),
+        ];
+
+        for (left, right, expected) in cases {
+            let left_keybind = Keybind::parse(left).unwrap().unwrap();
+            let right_keybind = Keybind::parse(right).unwrap().unwrap();
+            assert_eq!(
+                left_keybind.overlaps(&right_keybind),
+                expected,
+                "{left} / {right}"
+            );
+            assert_eq!(
+                right_keybind.overlaps(&left_keybind),
+                expected,
+                "{right} / {left}"
+            );
+        }
+    }
+
+    #[test]
+    fn matches_held_keys_exactly() {
+        let cases: [(&str, &[Key], bool); 10] = [
+            ("Control+KeyF", &[Key::ControlLeft, Key::KeyF], true),
+            ("Control+KeyF", &[Key::ControlRight, Key::KeyF], true),
+            ("Control+KeyF", &[Key::KeyF], false),
+            (
+                "Control+KeyF",
+                &[Key::ControlLeft, Key::ShiftLeft, Key::KeyF],
+                false,
+            ),
+            ("ControlLeft+KeyF", &[Key::ControlLeft, Key::KeyF], true),
+            ("ControlLeft+KeyF", &[Key::ControlRight, Key::KeyF], false),
+            (
+                "ControlLeft+KeyF",
+                &[Key::ControlLeft, Key::ControlRight, Key::KeyF],
+                false,
+            ),
+            ("ControlRight", &[Key::ControlRight], true),
+            (
+                "ControlRight",
+                &[Key::ControlRight, Key::ControlLeft],
+                false,
+            ),
+            (
+                "Shift+ControlRight",
+                &[Key::ShiftLeft, Key::ControlRight],
+                true,
+            ),
+        ];
+
+        for (input, held, expected) in cases {
+            let parsed = Keybind::parse(input).unwrap().unwrap();
+            assert_eq!(
+                parsed.is_active(|key| held.contains(&key), |_| false),
+                expected,
+                "{input} with {held:?}"
+            );
+        }
+
+        let mouse = Keybind::parse("Shift+Mouse4").unwrap().unwrap();
+        let button_down = |button: MouseButton| button.number() == 4;
+        assert!(mouse.is_active(|key| key == Key::ShiftLeft, button_down));
+        assert!(!mouse.is_active(|_| false, button_down));
+    }
+}
@@ -0,0 +1,298 @@
+//! Lookahead true-peak limiter, used as the last voice filter stage and on app
+//! audio capture.
+//!
+//! Peaks are measured on a 4x oversampled signal, so the peaks between samples
+//! that reappear after Opus decoding are caught as well. The signal is delayed
+//! by the lookahead so the gain is already down when a transient arrives; the
+//! delay is reported as `latencyMs` of the session.
+
+use crate::dynamics::GainReductionMetrics;
+use crate::params::check_range;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::ops::RangeInclusive;
+
+const DEFAULT_CEILING_DBTP: f32 = -1.0;
+const DEFAULT_LOOKAHEAD_MS: f32 = 1.5;
+const CEILING_RANGE_DBTP: RangeInclusive<f32> = -12.0..=0.0;
+const LOOKAHEAD_RANGE_MS: RangeInclusive<f32> = 0.5..=10.0;
+const RELEASE_MS: f32 = 100.0;
+
+/// BS.1770 asks for at least 4x oversampling at 48 kHz.
+const OVERSAMPLING: usize = 4;
+/// Half the length of each interpolation filter in input samples, which is
+/// also the delay of the peak estimate.
+const INTERPOLATION_HALF_TAPS: usize = 6;
+const INTERPOLATION_TAPS: usize = 2 * INTERPOLATION_HALF_TAPS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LimiterOptions {
+    /// Highest true peak let through, in dBTP.
+    #[serde(default = "default_ceiling_dbtp")]
+    ceiling_dbtp: f32,
+    /// How far ahead the limiter looks for peaks; adds this much latency.
+    #[serde(default = "default_lookahead_ms")]
+    lookahead_ms: f32,
+}
+
+fn default_ceiling_dbtp() -> f32 {
+    DEFAULT_CEILING_DBTP
+}
+
+fn default_lookahead_ms() -> f32 {
+    DEFAULT_LOOKAHEAD_MS
+}
+
+impl Default for LimiterOptions {
+    fn default() -> Self {
+        Self {
+            ceiling_dbtp: DEFAULT_CEILING_DBTP,
+            lookahead_ms: DEFAULT_LOOKAHEAD_MS,
+        }
+    }
+}
+
+impl LimiterOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range(
+            "Limiter ceiling",
+            self.ceiling_dbtp,
+            CEILING_RANGE_DBTP,
+            " dBTP",
+        )?;
+        check_range(
+            "Limiter lookahead",
+            self.lookahead_ms,
+            LOOKAHEAD_RANGE_MS,
+            " ms",
+        )
+    }
+
+    fn lookahead_frames(&self, sample_rate: usize) -> usize {
+        ((self.lookahead_ms * sample_rate as f32 / 1_000.0).round() as usize).max(1)
+    }
+
+    /// Frames by which a limiter with these options delays the signal.
+    pub(crate) fn latency_frames(&self, sample_rate: usize) -> usize {
+        self.lookahead_frames(sample_rate) + INTERPOLATION_HALF_TAPS
+    }
+}
+
+/// Estimates the true peak of each frame from Hann-windowed sinc
+/// interpolation at the positions between samples.
+struct TruePeakDetector {
+    channels: usize,
+    /// One filter per fractional position, 1/4 to 3/4.
+    phases: Vec<[f32; INTERPOLATION_TAPS]>,
+    /// The last `INTERPOLATION_TAPS` samples of every channel, ring-buffered.
+    history: Vec<f32>,
+    position: usize,
+}
+
+impl TruePeakDetector {
+    fn new(channels: usize) -> Self {
+        let half = INTERPOLATION_HALF_TAPS as f32;
+        let phases = (1..OVERSAMPLING)
+            .map(|phase| {
+                let fraction = phase as f32 / OVERSAMPLING as f32;
+                std::array::from_fn(|tap| {
+                    let offset = tap as f32 - half + fraction;
+                    let window = 0.5 * (1.0 + (PI * offset / half).cos());
+                    window * (PI * offset).sin() / (PI * offset)
+                })
+            })
+            .collect();
+
+        Self {
+            channels,
+            phases,
+            history: vec![0.0; channels * INTERPOLATION_TAPS],
+            position: 0,
+        }
+    }
+
+    /// Adds one frame and returns the true peak of the signal
+    /// `INTERPOLATION_HALF_TAPS` frames back.
+    fn push(&mut self, frame: &[f32]) -> f32 {
+        let mut peak = 0.0_f32;
+
+        for (channel, sample) in frame.iter().enumerate().take(self.channels) {
+            let history =
+                &mut self.history[channel * INTERPOLATION_TAPS..(channel + 1) * INTERPOLATION_TAPS];
+            history[self.position] = *sample;
+            // `tap` frames back from the newest sample.
+            let past = |tap: usize| {
+                history[(self.position + INTERPOLATION_TAPS - tap) % INTERPOLATION_TAPS]
+            };
+
+            peak = peak.max(past(INTERPOLATION_HALF_TAPS).abs());
+            for phase in self.phases.iter() {
+                let interpolated: f32 = phase
+                    .iter()
+                    .enumerate()
+                    .map(|(tap, coefficient)| coefficient * past(tap))
+                    .sum();
+                peak = peak.max(interpolated.abs());
+            }
+        }
+
+        self.position = (self.position + 1) % INTERPOLATION_TAPS;
+        peak
+    }
+}
+
+pub(crate) struct TruePeakLimiter {
+    channels: usize,
+    ceiling: f32,
+    lookahead_frames: usize,
+    release_coeff: f32,
+    detector: TruePeakDetector,
+    /// Gain each recent frame needs to stay under the ceiling; the minimum is
+    /// held for the whole lookahead.
+    required_gains: VecDeque<f32>,
+    released_gain: f32,
+    /// The released gain of the last `lookahead_frames` frames. Their average
+    /// ramps the gain down over the lookahead instead of stepping it.
+    ramp: VecDeque<f32>,
+    ramp_sum: f64,
+    delay: VecDeque<f32>,
+    gain_reduction_db: f32,
+    max_gain_reduction_db: f32,
+}
+
+impl TruePeakLimiter {
+    pub(crate) fn new(options: LimiterOptions, sample_rate: usize, channels: usize) -> Self {
+        let lookahead_frames = options.lookahead_frames(sample_rate);
+        // Two extra frames of hold cover the half-sample spread of the
+        // interpolated peaks.
+        let required_gains = VecDeque::from(vec![1.0; lookahead_frames + 2]);
+        let ramp = VecDeque::from(vec![1.0; lookahead_frames]);
+        let latency_frames = options.latency_frames(sample_rate);
+
+        Self {
+            channels: channels.max(1),
+            ceiling: 10.0_f32.powf(options.ceiling_dbtp / 20.0),
+            lookahead_frames,
+            release_coeff: (-1_000.0 / (RELEASE_MS * sample_rate as f32)).exp(),
+            detector: TruePeakDetector::new(channels.max(1)),
+            required_gains,
+            released_gain: 1.0,
+            ramp,
+            ramp_sum: lookahead_frames as f64,
+            delay: VecDeque::from(vec![0.0; latency_frames * channels.max(1)]),
+            gain_reduction_db: 0.0,
+            max_gain_reduction_db: 0.0,
+        }
+    }
+
+    /// Frames by which the output lags the input.
+    pub(crate) fn latency_frames(&self) -> usize {
+        self.delay.len() / self.channels
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_mut(self.channels) {
+            let peak = self.detector.push(frame);
+            let required_gain = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.0
+            };
+
+            self.required_gains.pop_front();
+            self.required_gains.push_back(required_gain);
+            let held_gain = self
+                .required_gains
+                .iter()
+                .fold(1.0_f32, |held, gain| held.min(*gain));
+
+            self.released_gain = if held_gain < self.released_gain {
+                held_gain
+            } else {
+                self.released_gain * self.release_coeff + held_gain * (1.0 - self.release_coeff)
+            };
+
+            self.ramp_sum -= f64::from(self.ramp.pop_front().unwrap_or(1.0));
+            self.ramp.push_back(self.released_gain);
+            self.ramp_sum += f64::from(self.released_gain);
+            let gain = (self.ramp_sum / self.lookahead_frames as f64) as f32;
+
+            for sample in frame.iter_mut() {
+                self.delay.push_back(*sample);
+                let delayed = self.delay.pop_front().unwrap_or(0.0);
+                // The interpolation can underestimate a peak slightly; never
+                // let a sample itself past the ceiling.
+                *sample = (delayed * gain).clamp(-self.ceiling, self.ceiling);
+            }
+
+            self.gain_reduction_db = -20.0 * gain.log10();
+            self.max_gain_reduction_db = self.max_gain_reduction_db.max(self.gain_reduction_db);
+        }
+    }
+
+    pub(crate) fn take_metrics(&mut self) -> GainReductionMetrics {
+        let metrics = GainReductionMetrics::new(self.gain_reduction_db, self.max_gain_reduction_db);
+        self.max_gain_reduction_db = self.gain_reduction_db;
+        metrics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LimiterOptions, TruePeakDetector, TruePeakLimiter};
+    use crate::test_signals::{options, peak};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn detects_peaks_between_samples() {
+        // A quarter of the sample rate, sampled 45 degrees off its peaks.
+        let samples: Vec<f32> = (0..480)
+            .map(|index| (PI / 2.0 * index as f32 + PI / 4.0).sin())
+            .collect();
+        assert!(samples.iter().all(|sample| sample.abs() < 0.71));
+
+        let mut detector = TruePeakDetector::new(1);
+        let peak = samples
+            .iter()
+            .map(|sample| detector.push(std::slice::from_ref(sample)))
+            .fold(0.0_f32, f32::max);
+        assert!((peak - 1.0).abs() < 0.03, "{peak}");
+    }
+
+    #[test]
+    fn ramps_down_ahead_of_a_transient() {
+        let options = options(
+            serde_json::json!({ "ceilingDbtp": -6.0, "lookaheadMs": 1.0 }),
+            LimiterOptions::validate,
+        );
+        let mut limiter = TruePeakLimiter::new(options, 48_000, 2);
+        let latency = limiter.latency_frames();
+        assert_eq!(latency, 48 + 6);
+
+        // 20 ms of a 1 kHz tone at -14 dBFS, then at full scale.
+        let mut samples: Vec<f32> = (0..1_920)
+            .flat_map(|frame| {
+                let amplitude = if frame < 960 { 0.2 } else { 1.0 };
+                let sample = amplitude * (2.0 * PI * 1_000.0 * frame as f32 / 48_000.0).sin();
+                [sample, sample]
+            })
+            .collect();
+        let input = samples.clone();
+        limiter.process(&mut samples);
+
+        let ceiling = 10.0_f32.powf(-6.0 / 20.0);
+        assert_eq!(samples[..latency * 2], vec![0.0; latency * 2]);
+        assert_eq!(samples[latency * 2..960 * 2], input[..(960 - latency) * 2]);
+        // Turned down before the loud part comes out, down to the ceiling
+        // rather than clipped to it once it does.
+        let onset = (960 + latency) * 2;
+        assert!(peak(&samples[onset - 40..onset]) < 0.15);
+        assert!((peak(&samples[onset + 960..]) - ceiling).abs() < 0.01);
+
+        let metrics = serde_json::to_value(limiter.take_metrics()).unwrap();
+        assert!((metrics["maxGainReductionDb"].as_f64().unwrap() - 6.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,603 @@
+//! Per-application audio capture on Linux through PulseAudio (or PipeWire via
+//! `pipewire-pulse`).
+//!
+//! Targets are the applications with a playback stream (a sink input), keyed
+//! by the client's process id so ids stay `pid:<pid>` like on Windows.
+//! Capturing one loads a private null sink, moves the application's streams
+//! onto it and loops the null sink's monitor back to the output device the
+//! streams were playing on, so the user keeps hearing the application while
+//! the sidecar records the monitor. Dropping the route undoes all of it.
+//! Streams are only captured from the output device the first one was playing
+//! on, since the loopback can only return the mix to a single device.
+//!
+//! A sidecar that crashed mid-capture leaves its modules loaded and the
+//! application's streams parked on a silent sink; [`remove_stale_routes`]
+//! cleans those up at startup.
+//!
+//! The `system` target records the default output device's monitor directly,
+//! or, when the desktop app's own playback is excluded, routes every other
+//! stream on that device through a capture sink the same way.
+
+use crate::linux_audio::{PulseIntrospection, PulseSink, PulseSinkInput};
+use crate::{AudioTarget, TARGET_CHANNELS, TARGET_SAMPLE_RATE};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Loopback latency from the capture sink back to the original output.
+const LOOPBACK_LATENCY_MS: u32 = 30;
+/// `/proc` walks stop after this many parents.
+const MAX_PROCESS_TREE_DEPTH: usize = 64;
+/// How often a [`RoutedCapture`] looks for new streams to route.
+const ROUTE_REFRESH_INTERVAL: Duration = Duration::from_millis(300);
+const APP_CAPTURE_SINK_PREFIX: &str = "sharkord_app_capture_";
+const SYSTEM_CAPTURE_SINK_PREFIX: &str = "sharkord_system_capture_";
+
+/// Whether a sound server is reachable. Probed once, at startup, so
+/// `capabilities.get` never waits on a connection attempt.
+pub(crate) fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| PulseIntrospection::connect().is_ok())
+}
+
+/// Parses the owning sidecar's pid out of a capture sink name.
+fn capture_sink_owner(sink_name: &str) -> Option<u32> {
+    let suffix = sink_name
+        .strip_prefix(APP_CAPTURE_SINK_PREFIX)
+        .or_else(|| sink_name.strip_prefix(SYSTEM_CAPTURE_SINK_PREFIX))?;
+    suffix.split('_').next()?.parse().ok()
+}
+
+/// The capture sink a `module-null-sink` argument creates, if it is one a
+/// sidecar that is no longer running left behind.
+fn stale_capture_sink(argument: &str, is_alive: impl Fn(u32) -> bool) -> Option<&str> {
+    let sink_name = argument
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix("sink_name="))?;
+    let owner = capture_sink_owner(sink_name)?;
+
+    (owner != std::process::id() && !is_alive(owner)).then_some(sink_name)
+}
+
+/// Moves streams off capture sinks left behind by a crashed sidecar onto the
+/// default output device and unloads their modules.
+pub(crate) fn remove_stale_routes() -> Result<(), String> {
+    let connection = PulseIntrospection::connect()?;
+    let modules = connection.modules()?;
+    let stale_sinks: Vec<(u32, &str)> = modules
+        .iter()
+        .filter(|module| module.name == "module-null-sink")
+        .filter_map(|module| {
+            let argument = module.argument.as_deref()?;
+            Some((
+                module.index,
+                stale_capture_sink(argument, process_is_alive)?,
+            ))
+        })
+        .collect();
+
+    if stale_sinks.is_empty() {
+        return Ok(());
+    }
+
+    let default_sink = connection.server_info()?.default_sink;
+    let sinks = connection.sinks()?;
+    let inputs = connection.sink_inputs()?;
+
+    for (null_sink_module, sink_name) in stale_sinks {
+        log_info!("capture", "removing stale capture sink", "sink" => sink_name);
+
+        let sink_index = sinks
+            .iter()
+            .find(|sink| sink.name == sink_name)
+            .map(|sink| sink.index);
+        if let (Some(sink_index), Some(default_sink)) = (sink_index, default_sink.as_deref()) {
+            for input in inputs.iter().filter(|input| input.sink == sink_index) {
+                if let Err(error) = connection.move_sink_input(input.index, default_sink) {
+                    log_warn!("capture", "failed to restore stream", "error" => &error);
+                }
+            }
+        }
+
+        let monitor_source = format!("source={sink_name}.monitor");
+        let loopbacks = modules.iter().filter(|module| {
+            module.name == "module-loopback"
+                && module.argument.as_deref().is_some_and(|argument| {
+                    argument
+                        .split_whitespace()
+                        .any(|part| part == monitor_source)
+                })
+        });
+        for module in loopbacks
+            .map(|module| module.index)
+            .chain([null_sink_module])
+        {
+            if let Err(error) = connection.unload_module(module) {
+                log_warn!("capture", "failed to unload capture module", "error" => &error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists targets, labelled with the title of the window belonging to the
+/// stream's process or its nearest ancestor when there is one.
+pub(crate) fn list_targets(
+    window_titles: &HashMap<u32, String>,
+) -> Result<Vec<AudioTarget>, String> {
+    let connection = PulseIntrospection::connect()?;
+    let inputs = connection.sink_inputs()?;
+
+    Ok(targets_from_sink_inputs(
+        &inputs,
+        std::process::id(),
+        process_name,
+        |pid| process_ancestors(pid).find_map(|ancestor| window_titles.get(&ancestor).cloned()),
+    ))
+}
+
+/// The id of the target playing audio for a window owned by `window_pid`.
+/// Browsers play from a child process rather than the one owning the window,
+/// so a target descending from the window's process counts too.
+pub(crate) fn target_for_window(targets: &[AudioTarget], window_pid: u32) -> Option<String> {
+    find_window_target(targets, window_pid, |pid| in_process_tree(pid, window_pid))
+}
+
+fn find_window_target(
+    targets: &[AudioTarget],
+    window_pid: u32,
+    in_window_tree: impl Fn(u32) -> bool,
+) -> Option<String> {
+    targets
+        .iter()
+        .find(|target| target.pid == window_pid)
+        .or_else(|| targets.iter().find(|target| in_window_tree(target.pid)))
+        .map(|target| target.id.clone())
+}
+
+/// Groups playback streams by process. Streams without a pid (module
+/// loopbacks, some sandboxed clients) and the sidecar's own are skipped.
+fn targets_from_sink_inputs(
+    inputs: &[PulseSinkInput],
+    own_pid: u32,
+    process_name: impl Fn(u32) -> Option<String>,
+    window_title: impl Fn(u32) -> Option<String>,
+) -> Vec<AudioTarget> {
+    let mut by_pid: BTreeMap<u32, Option<&str>> = BTreeMap::new();
+
+    for input in inputs {
+        let Some(pid) = input.pid.filter(|pid| *pid != own_pid) else {
+            continue;
+        };
+
+        let application_name = by_pid.entry(pid).or_default();
+        if application_name.is_none() {
+            *application_name = input.application_name.as_deref();
+        }
+    }
+
+    let mut targets: Vec<AudioTarget> = by_pid
+        .into_iter()
+        .map(|(pid, application_name)| {
+            let process_name = inputs
+                .iter()
+                .filter(|input| input.pid == Some(pid))
+                .find_map(|input| input.process_binary.clone())
+                .or_else(|| process_name(pid))
+                .unwrap_or_else(|| "unknown".to_string());
+            let title = window_title(pid)
+                .or_else(|| application_name.map(str::to_string))
+                .unwrap_or_else(|| process_name.clone());
+            let label = format!("{} - {} ({})", title.trim(), process_name, pid);
+
+            AudioTarget {
+                id: format!("pid:{pid}"),
+                label,
+                pid,
+                process_name,
+            }
+        })
+        .collect();
+
+    targets.sort_by(|left, right| left.label.cmp(&right.label));
+    targets
+}
+
+/// The `open_capture` device id for the default output device's monitor.
+pub(crate) fn default_monitor_device_id() -> Result<String, String> {
+    let connection = PulseIntrospection::connect()?;
+    let default_sink = connection
+        .server_info()?
+        .default_sink
+        .ok_or_else(|| "the sound server has no default output device".to_string())?;
+
+    connection
+        .sinks()?
+        .into_iter()
+        .find(|sink| sink.name == default_sink)
+        .and_then(|sink| sink.monitor_source_name)
+        .map(|monitor| format!("pulse:{monitor}"))
+        .ok_or_else(|| format!("output device {default_sink} has no monitor"))
+}
+
+/// Which playback streams a [`CaptureRoute`] moves onto its capture sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamSelection {
+    /// Streams from this process and its descendants.
+    ProcessTree(u32),
+    /// Streams on the default output device, except those from this process
+    /// tree.
+    DefaultOutputExcept(u32),
+}
+
+/// Routing for one capture session; see the module docs.
+pub(crate) struct CaptureRoute {
+    connection: PulseIntrospection,
+    selection: StreamSelection,
+    /// Captured when the route is created; later default changes are ignored.
+    default_sink: Option<String>,
+    sink_name: String,
+    null_sink_module: u32,
+    loopback_module: Option<u32>,
+    /// The output device the loopback plays into; streams on any other
+    /// device are left alone.
+    loopback_sink: Option<String>,
+    /// Streams moved onto the capture sink, with the sink each came from.
+    moved_inputs: HashMap<u32, String>,
+}
+
+impl CaptureRoute {
+    pub(crate) fn create(selection: StreamSelection) -> Result<Self, String> {
+        let connection = PulseIntrospection::connect()?;
+        let default_sink = connection.server_info()?.default_sink;
+        let sink_name = match selection {
+            StreamSelection::ProcessTree(pid) => {
+                format!("{APP_CAPTURE_SINK_PREFIX}{}_{pid}", std::process::id())
+            }
+            StreamSelection::DefaultOutputExcept(_) => {
+                format!("{SYSTEM_CAPTURE_SINK_PREFIX}{}", std::process::id())
+            }
+        };
+        let null_sink_module = connection.load_module(
+            "module-null-sink",
+            &format!(
+                "sink_name={sink_name} rate={TARGET_SAMPLE_RATE} channels={TARGET_CHANNELS} \
+                 sink_properties='device.description=\"Sharkord app capture\"'"
+            ),
+        )?;
+
+        let mut route = Self {
+            connection,
+            selection,
+            default_sink,
+            sink_name,
+            null_sink_module,
+            loopback_module: None,
+            loopback_sink: None,
+            moved_inputs: HashMap::new(),
+        };
+        route.refresh()?;
+
+        Ok(route)
+    }
+
+    /// The `open_capture` device id that records the target's audio.
+    pub(crate) fn monitor_device_id(&self) -> String {
+        format!("pulse:{}.monitor", self.sink_name)
+    }
+
+    fn selects(&self, input: &PulseSinkInput, input_sink: &PulseSink) -> bool {
+        // Never route the loopback back into the sink it drains.
+        if input.owner_module.is_some() && input.owner_module == self.loopback_module {
+            return false;
+        }
+
+        match self.selection {
+            StreamSelection::ProcessTree(pid) => input
+                .pid
+                .is_some_and(|input_pid| in_process_tree(input_pid, pid)),
+            StreamSelection::DefaultOutputExcept(pid) => {
+                self.default_sink.as_deref() == Some(input_sink.name.as_str())
+                    && !input
+                        .pid
+                        .is_some_and(|input_pid| in_process_tree(input_pid, pid))
+            }
+        }
+    }
+
+    /// Moves any selected streams that are not on the capture sink yet, e.g.
+    /// ones started after the session began.
+    pub(crate) fn refresh(&mut self) -> Result<(), String> {
+        let sinks = self.connection.sinks()?;
+        let Some(capture_sink) = sinks.iter().find(|sink| sink.name == self.sink_name) else {
+            return Err(format!("capture sink {} disappeared", self.sink_name));
+        };
+        let capture_sink_index = capture_sink.index;
+
+        for input in self.connection.sink_inputs()? {
+            if input.sink == capture_sink_index {
+                continue;
+            }
+
+            let Some(original_sink) = sinks.iter().find(|sink| sink.index == input.sink) else {
+                continue;
+            };
+
+            if !self.selects(&input, original_sink) {
+                continue;
+            }
+
+            match self.loopback_sink.as_deref() {
+                Some(loopback_sink) if loopback_sink != original_sink.name => continue,
+                Some(_) => {}
+                None => {
+                    self.loopback_module = Some(self.connection.load_module(
+                        "module-loopback",
+                        &format!(
+                            "source={}.monitor sink={} latency_msec={LOOPBACK_LATENCY_MS} \
+                             source_dont_move=true",
+                            self.sink_name, original_sink.name
+                        ),
+                    )?);
+                    self.loopback_sink = Some(original_sink.name.clone());
+                }
+            }
+
+            self.connection
+                .move_sink_input(input.index, &self.sink_name)?;
+            self.moved_inputs
+                .insert(input.index, original_sink.name.clone());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for CaptureRoute {
+    fn drop(&mut self) {
+        // Move streams back first; unloading the null sink would otherwise
+        // send them to the default output rather than where they were.
+        if let Ok(inputs) = self.connection.sink_inputs() {
+            for input in inputs {
+                let Some(original_sink) = self.moved_inputs.get(&input.index) else {
+                    continue;
+                };
+
+                if let Err(error) = self.connection.move_sink_input(input.index, original_sink) {
+                    log_warn!("capture", "failed to restore stream", "error" => &error);
+                }
+            }
+        }
+
+        let modules = self
+            .loopback_module
+            .into_iter()
+            .chain([self.null_sink_module]);
+        for module in modules {
+            if let Err(error) = self.connection.unload_module(module) {
+                log_warn!("capture", "failed to unload capture module", "error" => &error);
+            }
+        }
+    }
+}
+
+/// A [`CaptureRoute`] owned by a background thread that keeps routing new
+/// streams, so sound server round trips stay off the capture read loop.
+/// Dropping it stops the thread and waits for the route to be torn down.
+pub(crate) struct RoutedCapture {
+    monitor_device_id: String,
+    stop_flag: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RoutedCapture {
+    pub(crate) fn start(selection: StreamSelection) -> Result<Self, String> {
+        let (sender, receiver) = mpsc::channel();
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let thread_stop_flag = Arc::clone(&stop_flag);
+
+        let thread = thread::spawn(move || {
+            let mut route = match CaptureRoute::create(selection) {
+                Ok(route) => route,
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                    return;
+                }
+            };
+            let _ = sender.send(Ok(route.monitor_device_id()));
+
+            loop {
+                thread::park_timeout(ROUTE_REFRESH_INTERVAL);
+                if thread_stop_flag.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                if let Err(error) = route.refresh() {
+                    log_warn!("capture", "failed to route new streams", "error" => &error);
+                }
+            }
+        });
+
+        match receiver.recv() {
+            Ok(Ok(monitor_device_id)) => Ok(Self {
+                monitor_device_id,
+                stop_flag,
+                thread: Some(thread),
+            }),
+            Ok(Err(error)) => {
+                let _ = thread.join();
+                Err(error)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err("capture routing thread exited".to_string())
+            }
+        }
+    }
+
+    /// The `open_capture` device id that records the target's audio.
+    pub(crate) fn monitor_device_id(&self) -> &str {
+        &self.monitor_device_id
+    }
+}
+
+impl Drop for RoutedCapture {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+pub(crate) fn process_is_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+pub(crate) fn process_name(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{pid}/comm"))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    parse_stat_parent(&std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?)
+}
+
+/// Reads the parent pid from `/proc/<pid>/stat`. The command name in
+/// parentheses may itself contain spaces and `)`, so fields are counted from
+/// the last `)`.
+fn parse_stat_parent(stat: &str) -> Option<u32> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// `pid` followed by its parents, stopping before init.
+fn process_ancestors(pid: u32) -> impl Iterator<Item = u32> {
+    std::iter::successors(Some(pid), |current| {
+        parent_pid(*current).filter(|parent| *parent > 1 && parent != current)
+    })
+    .take(MAX_PROCESS_TREE_DEPTH)
+}
+
+/// Whether `pid` is `root` or one of its descendants, matching the
+/// process-tree loopback mode used on Windows (browsers play audio from
+/// child processes).
+fn in_process_tree(pid: u32, root: u32) -> bool {
+    process_ancestors(pid).any(|ancestor| ancestor == root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_window_target, parse_stat_parent, stale_capture_sink, targets_from_sink_inputs,
+    };
+    use crate::linux_audio::PulseSinkInput;
+
+    fn input(index: u32, pid: Option<u32>, application_name: Option<&str>) -> PulseSinkInput {
+        PulseSinkInput {
+            index,
+            sink: 0,
+            owner_module: None,
+            pid,
+            application_name: application_name.map(str::to_string),
+            process_binary: None,
+        }
+    }
+
+    #[test]
+    fn groups_sink_inputs_by_process() {
+        let inputs = [
+            input(1, Some(200), None),
+            input(2, Some(200), Some("Firefox")),
+            input(3, Some(100), Some("Steam")),
+            input(4, None, Some("Loopback")),
+            input(5, Some(42), Some("Sidecar")),
+        ];
+
+        let targets = targets_from_sink_inputs(
+            &inputs,
+            42,
+            |pid| Some(if pid == 200 { "firefox" } else { "steam" }.to_string()),
+            |pid| (pid == 100).then(|| "Steam Big Picture".to_string()),
+        );
+
+        let labels: Vec<_> = targets.iter().map(|target| target.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            ["Firefox - firefox (200)", "Steam Big Picture - steam (100)"]
+        );
+        assert_eq!(targets[0].id, "pid:200");
+        assert_eq!(targets[0].process_name, "firefox");
+    }
+
+    #[test]
+    fn suggests_the_target_playing_from_a_child_of_the_window_process() {
+        // Chromium owns the window from pid 500 but plays audio from its
+        // audio service, pid 512.
+        let inputs = [
+            input(1, Some(512), Some("Chromium")),
+            input(2, Some(700), Some("Spotify")),
+        ];
+        let targets = targets_from_sink_inputs(&inputs, 42, |_| None, |_| None);
+        let parents = |pid| match pid {
+            512 => Some(500),
+            _ => None,
+        };
+        let in_tree = |root: u32| {
+            move |pid: u32| std::iter::successors(Some(pid), |pid| parents(*pid)).any(|p| p == root)
+        };
+
+        assert_eq!(
+            find_window_target(&targets, 500, in_tree(500)),
+            Some("pid:512".to_string())
+        );
+        assert_eq!(
+            find_window_target(&targets, 700, in_tree(700)),
+            Some("pid:700".to_string())
+        );
+        assert_eq!(find_window_target(&targets, 900, in_tree(900)), None);
+    }
+
+    #[test]
+    fn parses_parent_pid_from_stat() {
+        assert_eq!(
+            parse_stat_parent("1234 (bash) S 987 1234 1234 0"),
+            Some(987)
+        );
+        assert_eq!(
+            parse_stat_parent("4321 (Web Content) (x)) S 1234 4321 0"),
+            Some(1234)
+        );
+        assert_eq!(parse_stat_parent("garbage"), None);
+    }
+
+    #[test]
+    fn finds_capture_sinks_of_dead_sidecars() {
+        let alive = |pid| pid == 300;
+
+        assert_eq!(
+            stale_capture_sink(
+                "sink_name=sharkord_app_capture_200_1234 rate=48000 channels=2",
+                alive
+            ),
+            Some("sharkord_app_capture_200_1234")
+        );
+        assert_eq!(
+            stale_capture_sink("sink_name=sharkord_system_capture_200 rate=48000", alive),
+            Some("sharkord_system_capture_200")
+        );
+        assert_eq!(
+            stale_capture_sink("sink_name=sharkord_system_capture_300", alive),
+            None
+        );
+        assert_eq!(stale_capture_sink("sink_name=other_sink", alive), None);
+        assert_eq!(stale_capture_sink("rate=48000", alive), None);
+    }
+}
@@ -0,0 +1,1389 @@
+//! Linux audio backends.
+//!
+//! PulseAudio (and PipeWire through `pipewire-pulse`) is preferred, with raw
+//! ALSA as the fallback for systems without a sound server. Both client
+//! libraries are loaded at runtime so the sidecar still starts, and reports a
+//! capture error instead of failing to link, on machines that lack one of them.
+//!
+//! Device ids are `pulse:<source name>` or `alsa:<pcm name>`; an id without a
+//! prefix is tried as a PulseAudio source first and as an ALSA PCM second.
+//! [`list_capture_devices`] returns ids in the prefixed form.
+
+use crate::MicDevice;
+use libloading::Library;
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr, CString};
+use std::ptr;
+
+const PULSE_SIMPLE_LIBRARY: &str = "libpulse-simple.so.0";
+const ALSA_LIBRARY: &str = "libasound.so.2";
+const CLIENT_NAME: &CStr = c"Sharkord";
+const PULSE_DEVICE_PREFIX: &str = "pulse:";
+const ALSA_DEVICE_PREFIX: &str = "alsa:";
+const ALSA_DEFAULT_DEVICE: &str = "default";
+/// Requested ALSA buffer latency; reads themselves are one frame at a time.
+const ALSA_LATENCY_US: c_uint = 40_000;
+
+/// A blocking capture stream delivering interleaved f32 samples at the rate
+/// and channel count it was opened with.
+pub(crate) trait CaptureStream: Send {
+    /// Fills `buffer` completely, blocking until enough audio is available.
+    fn read(&mut self, buffer: &mut [f32]) -> Result<(), String>;
+
+    fn backend(&self) -> &'static str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Pulse,
+    Alsa,
+}
+
+/// Splits a device id into the backends to try, in order, with the device name
+/// each should open (`None` meaning the backend default).
+fn capture_candidates(device_id: Option<&str>) -> Vec<(Backend, Option<&str>)> {
+    match device_id.map(str::trim).filter(|id| !id.is_empty()) {
+        None => vec![(Backend::Pulse, None), (Backend::Alsa, None)],
+        Some(id) => {
+            if let Some(name) = id.strip_prefix(PULSE_DEVICE_PREFIX) {
+                vec![(Backend::Pulse, Some(name))]
+            } else if let Some(name) = id.strip_prefix(ALSA_DEVICE_PREFIX) {
+                vec![(Backend::Alsa, Some(name))]
+            } else {
+                vec![(Backend::Pulse, Some(id)), (Backend::Alsa, Some(id))]
+            }
+        }
+    }
+}
+
+/// Opens a capture stream on the first backend that accepts the device. The
+/// sound server (or the ALSA `plug` layer) resamples and remixes the device to
+/// the requested float format.
+pub(crate) fn open_capture(
+    device_id: Option<&str>,
+    sample_rate: u32,
+    channels: usize,
+) -> Result<Box<dyn CaptureStream>, String> {
+    let mut errors = Vec::new();
+
+    for (backend, device) in capture_candidates(device_id) {
+        let opened = match backend {
+            Backend::Pulse => PulseCapture::open(device, sample_rate, channels)
+                .map(|stream| Box::new(stream) as Box<dyn CaptureStream>),
+            Backend::Alsa => AlsaCapture::open(device, sample_rate, channels)
+                .map(|stream| Box::new(stream) as Box<dyn CaptureStream>),
+        };
+
+        match opened {
+            Ok(stream) => return Ok(stream),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    Err(errors.join("; "))
+}
+
+/// Lists capture devices for `mic_devices.list`: the sound server's sources
+/// when one is running, otherwise the PCMs in the ALSA configuration.
+pub(crate) fn list_capture_devices(include_monitors: bool) -> Result<Vec<MicDevice>, String> {
+    let pulse_error = match list_pulse_sources(include_monitors) {
+        Ok(devices) => return Ok(devices),
+        Err(error) => error,
+    };
+
+    log_debug!("devices", "listing ALSA devices", "pulse_error" => &pulse_error);
+    list_alsa_capture_devices().map_err(|alsa_error| format!("{pulse_error}; {alsa_error}"))
+}
+
+fn load_library(name: &str) -> Result<Library, String> {
+    unsafe { Library::new(name) }.map_err(|error| format!("failed to load {name}: {error}"))
+}
+
+/// Copies a function pointer out of `library`. The caller keeps the library
+/// alive for as long as the pointer is used.
+unsafe fn load_symbol<T: Copy>(library: &Library, name: &[u8]) -> Result<T, String> {
+    library
+        .get::<T>(name)
+        .map(|symbol| *symbol)
+        .map_err(|error| {
+            let name = String::from_utf8_lossy(&name[..name.len().saturating_sub(1)]);
+            format!("missing symbol {name}: {error}")
+        })
+}
+
+fn c_string(value: &str) -> Result<CString, String> {
+    CString::new(value).map_err(|_| format!("invalid device name `{value}`"))
+}
+
+// --- PulseAudio -------------------------------------------------------------
+
+const PA_STREAM_RECORD: c_int = 2;
+const PA_SAMPLE_FLOAT32LE: c_int = 5;
+
+#[repr(C)]
+struct PaSampleSpec {
+    format: c_int,
+    rate: u32,
+    channels: u8,
+}
+
+#[repr(C)]
+struct PaBufferAttr {
+    maxlength: u32,
+    tlength: u32,
+    prebuf: u32,
+    minreq: u32,
+    fragsize: u32,
+}
+
+type PaSimpleNew = unsafe extern "C" fn(
+    server: *const c_char,
+    name: *const c_char,
+    direction: c_int,
+    device: *const c_char,
+    stream_name: *const c_char,
+    spec: *const PaSampleSpec,
+    channel_map: *const c_void,
+    attr: *const PaBufferAttr,
+    error: *mut c_int,
+) -> *mut c_void;
+type PaSimpleRead = unsafe extern "C" fn(
+    stream: *mut c_void,
+    data: *mut c_void,
+    bytes: usize,
+    error: *mut c_int,
+) -> c_int;
+type PaSimpleFree = unsafe extern "C" fn(stream: *mut c_void);
+type PaStrError = unsafe extern "C" fn(error: c_int) -> *const c_char;
+
+struct PulseSimpleApi {
+    new: PaSimpleNew,
+    read: PaSimpleRead,
+    free: PaSimpleFree,
+    strerror: PaStrError,
+    // Declared last so the function pointers above never outlive it.
+    _library: Library,
+}
+
+impl PulseSimpleApi {
+    fn load() -> Result<Self, String> {
+        let library = load_library(PULSE_SIMPLE_LIBRARY)?;
+        unsafe {
+            Ok(Self {
+                new: load_symbol(&library, b"pa_simple_new\0")?,
+                read: load_symbol(&library, b"pa_simple_read\0")?,
+                free: load_symbol(&library, b"pa_simple_free\0")?,
+                // Exported by libpulse, which libpulse-simple pulls in.
+                strerror: load_symbol(&library, b"pa_strerror\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    fn describe_error(&self, error: c_int) -> String {
+        let message = unsafe { (self.strerror)(error) };
+        if message.is_null() {
+            return format!("error {error}");
+        }
+
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+struct PulseCapture {
+    api: PulseSimpleApi,
+    stream: *mut c_void,
+}
+
+// The stream handle is only ever used from the thread that owns the capture.
+unsafe impl Send for PulseCapture {}
+
+impl PulseCapture {
+    fn open(device: Option<&str>, sample_rate: u32, channels: usize) -> Result<Self, String> {
+        let api = PulseSimpleApi::load()?;
+        let device_name = device.map(c_string).transpose()?;
+        let spec = PaSampleSpec {
+            format: PA_SAMPLE_FLOAT32LE,
+            rate: sample_rate,
+            channels: u8::try_from(channels)
+                .map_err(|_| format!("unsupported channel count {channels}"))?,
+        };
+        // Ask for ~10 ms fragments so frames are not batched up server-side.
+        let attr = PaBufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: sample_rate / 100 * channels as u32 * 4,
+        };
+        let mut error: c_int = 0;
+
+        let stream = unsafe {
+            (api.new)(
+                ptr::null(),
+                CLIENT_NAME.as_ptr(),
+                PA_STREAM_RECORD,
+                device_name
+                    .as_ref()
+                    .map_or(ptr::null(), |name| name.as_ptr()),
+                c"Voice filter microphone".as_ptr(),
+                &spec,
+                ptr::null(),
+                &attr,
+                &mut error,
+            )
+        };
+
+        if stream.is_null() {
+            return Err(format!(
+                "PulseAudio capture on {} failed: {}",
+                device.unwrap_or("default source"),
+                api.describe_error(error)
+            ));
+        }
+
+        Ok(Self { api, stream })
+    }
+}
+
+impl CaptureStream for PulseCapture {
+    fn read(&mut self, buffer: &mut [f32]) -> Result<(), String> {
+        let mut error: c_int = 0;
+        let status = unsafe {
+            (self.api.read)(
+                self.stream,
+                buffer.as_mut_ptr().cast(),
+                std::mem::size_of_val(buffer),
+                &mut error,
+            )
+        };
+
+        if status < 0 {
+            return Err(format!(
+                "PulseAudio read failed: {}",
+                self.api.describe_error(error)
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn backend(&self) -> &'static str {
+        "pulse"
+    }
+}
+
+impl Drop for PulseCapture {
+    fn drop(&mut self) {
+        unsafe { (self.api.free)(self.stream) };
+    }
+}
+
+// --- PulseAudio introspection ------------------------------------------------
+
+const PULSE_LIBRARY: &str = "libpulse.so.0";
+const PA_CONTEXT_NOAUTOSPAWN: c_int = 1;
+const PA_CONTEXT_READY: c_int = 4;
+const PA_CONTEXT_FAILED: c_int = 5;
+const PA_CONTEXT_TERMINATED: c_int = 6;
+const PA_OPERATION_RUNNING: c_int = 0;
+const PA_INVALID_INDEX: u32 = u32::MAX;
+const PA_CHANNELS_MAX: usize = 32;
+
+#[repr(C)]
+#[allow(dead_code)]
+struct PaChannelMap {
+    channels: u8,
+    map: [c_int; PA_CHANNELS_MAX],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct PaCVolume {
+    channels: u8,
+    values: [u32; PA_CHANNELS_MAX],
+}
+
+/// Leading fields of `pa_source_info`; only ever read through a pointer
+/// handed out by libpulse, so the rest of the struct can be left off.
+#[repr(C)]
+#[allow(dead_code)]
+struct PaSourceInfo {
+    name: *const c_char,
+    index: u32,
+    description: *const c_char,
+    sample_spec: PaSampleSpec,
+    channel_map: PaChannelMap,
+    owner_module: u32,
+    volume: PaCVolume,
+    mute: c_int,
+    monitor_of_sink: u32,
+}
+
+/// Leading fields of `pa_sink_info`.
+#[repr(C)]
+#[allow(dead_code)]
+struct PaSinkInfo {
+    name: *const c_char,
+    index: u32,
+    description: *const c_char,
+    sample_spec: PaSampleSpec,
+    channel_map: PaChannelMap,
+    owner_module: u32,
+    volume: PaCVolume,
+    mute: c_int,
+    monitor_source: u32,
+    monitor_source_name: *const c_char,
+}
+
+/// Leading fields of `pa_sink_input_info`.
+#[repr(C)]
+#[allow(dead_code)]
+struct PaSinkInputInfo {
+    index: u32,
+    name: *const c_char,
+    owner_module: u32,
+    client: u32,
+    sink: u32,
+    sample_spec: PaSampleSpec,
+    channel_map: PaChannelMap,
+    volume: PaCVolume,
+    buffer_usec: u64,
+    sink_usec: u64,
+    resample_method: *const c_char,
+    driver: *const c_char,
+    mute: c_int,
+    proplist: *const c_void,
+}
+
+/// Leading fields of `pa_module_info`.
+#[repr(C)]
+#[allow(dead_code)]
+struct PaModuleInfo {
+    index: u32,
+    name: *const c_char,
+    argument: *const c_char,
+}
+
+/// Leading fields of `pa_server_info`.
+#[repr(C)]
+#[allow(dead_code)]
+struct PaServerInfo {
+    user_name: *const c_char,
+    host_name: *const c_char,
+    server_version: *const c_char,
+    server_name: *const c_char,
+    sample_spec: PaSampleSpec,
+    default_sink_name: *const c_char,
+    default_source_name: *const c_char,
+}
+
+type PaMainloopNew = unsafe extern "C" fn() -> *mut c_void;
+type PaMainloopGetApi = unsafe extern "C" fn(mainloop: *mut c_void) -> *mut c_void;
+type PaMainloopIterate =
+    unsafe extern "C" fn(mainloop: *mut c_void, block: c_int, retval: *mut c_int) -> c_int;
+type PaMainloopFree = unsafe extern "C" fn(mainloop: *mut c_void);
+type PaContextNew = unsafe extern "C" fn(api: *mut c_void, name: *const c_char) -> *mut c_void;
+type PaContextConnect = unsafe extern "C" fn(
+    context: *mut c_void,
+    server: *const c_char,
+    flags: c_int,
+    spawn_api: *const c_void,
+) -> c_int;
+type PaContextGetState = unsafe extern "C" fn(context: *mut c_void) -> c_int;
+type PaContextErrno = unsafe extern "C" fn(context: *mut c_void) -> c_int;
+type PaContextDisconnect = unsafe extern "C" fn(context: *mut c_void);
+type PaContextUnref = unsafe extern "C" fn(context: *mut c_void);
+type PaSourceInfoCallback = unsafe extern "C" fn(
+    context: *mut c_void,
+    info: *const PaSourceInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+);
+type PaServerInfoCallback =
+    unsafe extern "C" fn(context: *mut c_void, info: *const PaServerInfo, userdata: *mut c_void);
+type PaContextGetSourceInfoList = unsafe extern "C" fn(
+    context: *mut c_void,
+    callback: PaSourceInfoCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextGetServerInfo = unsafe extern "C" fn(
+    context: *mut c_void,
+    callback: PaServerInfoCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaSinkInfoCallback = unsafe extern "C" fn(
+    context: *mut c_void,
+    info: *const PaSinkInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+);
+type PaSinkInputInfoCallback = unsafe extern "C" fn(
+    context: *mut c_void,
+    info: *const PaSinkInputInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+);
+type PaModuleInfoCallback = unsafe extern "C" fn(
+    context: *mut c_void,
+    info: *const PaModuleInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+);
+type PaIndexCallback =
+    unsafe extern "C" fn(context: *mut c_void, index: u32, userdata: *mut c_void);
+type PaSuccessCallback =
+    unsafe extern "C" fn(context: *mut c_void, success: c_int, userdata: *mut c_void);
+type PaContextGetSinkInfoList = unsafe extern "C" fn(
+    context: *mut c_void,
+    callback: PaSinkInfoCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextGetSinkInputInfoList = unsafe extern "C" fn(
+    context: *mut c_void,
+    callback: PaSinkInputInfoCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextGetModuleInfoList = unsafe extern "C" fn(
+    context: *mut c_void,
+    callback: PaModuleInfoCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextLoadModule = unsafe extern "C" fn(
+    context: *mut c_void,
+    name: *const c_char,
+    argument: *const c_char,
+    callback: PaIndexCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextUnloadModule = unsafe extern "C" fn(
+    context: *mut c_void,
+    index: u32,
+    callback: PaSuccessCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaContextMoveSinkInputByName = unsafe extern "C" fn(
+    context: *mut c_void,
+    index: u32,
+    sink_name: *const c_char,
+    callback: PaSuccessCallback,
+    userdata: *mut c_void,
+) -> *mut c_void;
+type PaProplistGets =
+    unsafe extern "C" fn(proplist: *const c_void, key: *const c_char) -> *const c_char;
+type PaOperationGetState = unsafe extern "C" fn(operation: *mut c_void) -> c_int;
+type PaOperationUnref = unsafe extern "C" fn(operation: *mut c_void);
+
+struct PulseApi {
+    mainloop_new: PaMainloopNew,
+    mainloop_get_api: PaMainloopGetApi,
+    mainloop_iterate: PaMainloopIterate,
+    mainloop_free: PaMainloopFree,
+    context_new: PaContextNew,
+    context_connect: PaContextConnect,
+    context_get_state: PaContextGetState,
+    context_errno: PaContextErrno,
+    context_disconnect: PaContextDisconnect,
+    context_unref: PaContextUnref,
+    get_source_info_list: PaContextGetSourceInfoList,
+    get_server_info: PaContextGetServerInfo,
+    get_sink_info_list: PaContextGetSinkInfoList,
+    get_sink_input_info_list: PaContextGetSinkInputInfoList,
+    get_module_info_list: PaContextGetModuleInfoList,
+    load_module: PaContextLoadModule,
+    unload_module: PaContextUnloadModule,
+    move_sink_input_by_name: PaContextMoveSinkInputByName,
+    proplist_gets: PaProplistGets,
+    operation_get_state: PaOperationGetState,
+    operation_unref: PaOperationUnref,
+    strerror: PaStrError,
+    _library: Library,
+}
+
+impl PulseApi {
+    fn load() -> Result<Self, String> {
+        let library = load_library(PULSE_LIBRARY)?;
+        unsafe {
+            Ok(Self {
+                mainloop_new: load_symbol(&library, b"pa_mainloop_new\0")?,
+                mainloop_get_api: load_symbol(&library, b"pa_mainloop_get_api\0")?,
+                mainloop_iterate: load_symbol(&library, b"pa_mainloop_iterate\0")?,
+                mainloop_free: load_symbol(&library, b"pa_mainloop_free\0")?,
+                context_new: load_symbol(&library, b"pa_context_new\0")?,
+                context_connect: load_symbol(&library, b"pa_context_connect\0")?,
+                context_get_state: load_symbol(&library, b"pa_context_get_state\0")?,
+                context_errno: load_symbol(&library, b"pa_context_errno\0")?,
+                context_disconnect: load_symbol(&library, b"pa_context_disconnect\0")?,
+                context_unref: load_symbol(&library, b"pa_context_unref\0")?,
+                get_source_info_list: load_symbol(&library, b"pa_context_get_source_info_list\0")?,
+                get_server_info: load_symbol(&library, b"pa_context_get_server_info\0")?,
+                get_sink_info_list: load_symbol(&library, b"pa_context_get_sink_info_list\0")?,
+                get_sink_input_info_list: load_symbol(
+                    &library,
+                    b"pa_context_get_sink_input_info_list\0",
+                )?,
+                get_module_info_list: load_symbol(&library, b"pa_context_get_module_info_list\0")?,
+                load_module: load_symbol(&library, b"pa_context_load_module\0")?,
+                unload_module: load_symbol(&library, b"pa_context_unload_module\0")?,
+                move_sink_input_by_name: load_symbol(
+                    &library,
+                    b"pa_context_move_sink_input_by_name\0",
+                )?,
+                proplist_gets: load_symbol(&library, b"pa_proplist_gets\0")?,
+                operation_get_state: load_symbol(&library, b"pa_operation_get_state\0")?,
+                operation_unref: load_symbol(&library, b"pa_operation_unref\0")?,
+                strerror: load_symbol(&library, b"pa_strerror\0")?,
+                _library: library,
+            })
+        }
+    }
+}
+
+/// A source as reported by the server, copied out of `pa_source_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PulseSource {
+    name: String,
+    description: String,
+    channels: u8,
+    sample_rate: u32,
+    is_monitor: bool,
+}
+
+/// An output device, copied out of `pa_sink_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PulseSink {
+    pub(crate) index: u32,
+    pub(crate) name: String,
+    pub(crate) monitor_source_name: Option<String>,
+}
+
+/// A playback stream, copied out of `pa_sink_input_info`. The application
+/// fields come from the client's property list and may be missing for
+/// sandboxed or unusual clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PulseSinkInput {
+    pub(crate) index: u32,
+    pub(crate) sink: u32,
+    /// Set for streams created by a server module, e.g. `module-loopback`.
+    pub(crate) owner_module: Option<u32>,
+    pub(crate) pid: Option<u32>,
+    pub(crate) application_name: Option<String>,
+    pub(crate) process_binary: Option<String>,
+}
+
+/// A loaded server module, copied out of `pa_module_info`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PulseModule {
+    pub(crate) index: u32,
+    pub(crate) name: String,
+    pub(crate) argument: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PulseServerInfo {
+    pub(crate) default_sink: Option<String>,
+    pub(crate) default_source: Option<String>,
+}
+
+struct SinkInputCollector {
+    proplist_gets: PaProplistGets,
+    inputs: Vec<PulseSinkInput>,
+}
+
+fn owned_c_str(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+
+    Some(
+        unsafe { CStr::from_ptr(value) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+unsafe extern "C" fn collect_source(
+    _context: *mut c_void,
+    info: *const PaSourceInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    if eol != 0 || info.is_null() {
+        return;
+    }
+
+    let info = &*info;
+    let Some(name) = owned_c_str(info.name) else {
+        return;
+    };
+
+    let sources = &mut *userdata.cast::<Vec<PulseSource>>();
+    sources.push(PulseSource {
+        description: owned_c_str(info.description).unwrap_or_else(|| name.clone()),
+        name,
+        channels: info.sample_spec.channels,
+        sample_rate: info.sample_spec.rate,
+        is_monitor: info.monitor_of_sink != PA_INVALID_INDEX,
+    });
+}
+
+unsafe extern "C" fn collect_server_info(
+    _context: *mut c_void,
+    info: *const PaServerInfo,
+    userdata: *mut c_void,
+) {
+    if info.is_null() {
+        return;
+    }
+
+    let server_info = &mut *userdata.cast::<PulseServerInfo>();
+    server_info.default_sink = owned_c_str((*info).default_sink_name);
+    server_info.default_source = owned_c_str((*info).default_source_name);
+}
+
+unsafe extern "C" fn collect_sink(
+    _context: *mut c_void,
+    info: *const PaSinkInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    if eol != 0 || info.is_null() {
+        return;
+    }
+
+    let info = &*info;
+    let Some(name) = owned_c_str(info.name) else {
+        return;
+    };
+
+    let sinks = &mut *userdata.cast::<Vec<PulseSink>>();
+    sinks.push(PulseSink {
+        index: info.index,
+        name,
+        monitor_source_name: owned_c_str(info.monitor_source_name),
+    });
+}
+
+unsafe extern "C" fn collect_sink_input(
+    _context: *mut c_void,
+    info: *const PaSinkInputInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    if eol != 0 || info.is_null() {
+        return;
+    }
+
+    let info = &*info;
+    let collector = &mut *userdata.cast::<SinkInputCollector>();
+    let property = |key: &CStr| {
+        if info.proplist.is_null() {
+            return None;
+        }
+        owned_c_str((collector.proplist_gets)(info.proplist, key.as_ptr()))
+    };
+
+    let input = PulseSinkInput {
+        index: info.index,
+        sink: info.sink,
+        owner_module: Some(info.owner_module).filter(|module| *module != PA_INVALID_INDEX),
+        pid: property(c"application.process.id").and_then(|pid| pid.parse().ok()),
+        application_name: property(c"application.name"),
+        process_binary: property(c"application.process.binary"),
+    };
+    collector.inputs.push(input);
+}
+
+unsafe extern "C" fn collect_module(
+    _context: *mut c_void,
+    info: *const PaModuleInfo,
+    eol: c_int,
+    userdata: *mut c_void,
+) {
+    if eol != 0 || info.is_null() {
+        return;
+    }
+
+    let info = &*info;
+    let Some(name) = owned_c_str(info.name) else {
+        return;
+    };
+
+    let modules = &mut *userdata.cast::<Vec<PulseModule>>();
+    modules.push(PulseModule {
+        index: info.index,
+        name,
+        argument: owned_c_str(info.argument),
+    });
+}
+
+unsafe extern "C" fn store_index(_context: *mut c_void, index: u32, userdata: *mut c_void) {
+    *userdata.cast::<u32>() = index;
+}
+
+unsafe extern "C" fn store_success(_context: *mut c_void, success: c_int, userdata: *mut c_void) {
+    *userdata.cast::<bool>() = success != 0;
+}
+
+/// A blocking connection to the sound server for queries and routing changes.
+/// Every call drives a private main loop until its operation completes, so
+/// the connection can live on whichever thread uses it.
+pub(crate) struct PulseIntrospection {
+    api: PulseApi,
+    mainloop: *mut c_void,
+    context: *mut c_void,
+}
+
+impl PulseIntrospection {
+    pub(crate) fn connect() -> Result<Self, String> {
+        let api = PulseApi::load()?;
+        let mainloop = unsafe { (api.mainloop_new)() };
+        if mainloop.is_null() {
+            return Err("failed to create PulseAudio main loop".to_string());
+        }
+
+        let context =
+            unsafe { (api.context_new)((api.mainloop_get_api)(mainloop), CLIENT_NAME.as_ptr()) };
+        if context.is_null() {
+            unsafe { (api.mainloop_free)(mainloop) };
+            return Err("failed to create PulseAudio context".to_string());
+        }
+
+        let connection = Self {
+            api,
+            mainloop,
+            context,
+        };
+
+        let status = unsafe {
+            (connection.api.context_connect)(
+                context,
+                ptr::null(),
+                PA_CONTEXT_NOAUTOSPAWN,
+                ptr::null(),
+            )
+        };
+        if status < 0 {
+            return Err(connection.context_error("PulseAudio connection failed"));
+        }
+
+        loop {
+            match unsafe { (connection.api.context_get_state)(context) } {
+                PA_CONTEXT_READY => return Ok(connection),
+                PA_CONTEXT_FAILED | PA_CONTEXT_TERMINATED => {
+                    return Err(connection.context_error("PulseAudio connection failed"));
+                }
+                _ => connection.iterate()?,
+            }
+        }
+    }
+
+    fn iterate(&self) -> Result<(), String> {
+        let status = unsafe { (self.api.mainloop_iterate)(self.mainloop, 1, ptr::null_mut()) };
+        if status < 0 {
+            return Err("PulseAudio main loop failed".to_string());
+        }
+
+        Ok(())
+    }
+
+    fn context_error(&self, prefix: &str) -> String {
+        let error = unsafe { (self.api.context_errno)(self.context) };
+        let message = unsafe { (self.api.strerror)(error) };
+        let message = owned_c_str(message).unwrap_or_else(|| format!("error {error}"));
+        format!("{prefix}: {message}")
+    }
+
+    /// Runs the main loop until `operation` finishes, then releases it.
+    fn wait(&self, operation: *mut c_void, what: &str) -> Result<(), String> {
+        if operation.is_null() {
+            return Err(self.context_error(what));
+        }
+
+        let mut result = Ok(());
+        while unsafe { (self.api.operation_get_state)(operation) } == PA_OPERATION_RUNNING {
+            if let Err(error) = self.iterate() {
+                result = Err(error);
+                break;
+            }
+        }
+
+        unsafe { (self.api.operation_unref)(operation) };
+        result
+    }
+
+    pub(crate) fn server_info(&self) -> Result<PulseServerInfo, String> {
+        let mut info = PulseServerInfo::default();
+        let operation = unsafe {
+            (self.api.get_server_info)(
+                self.context,
+                collect_server_info,
+                (&mut info as *mut PulseServerInfo).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio server info query failed")?;
+        Ok(info)
+    }
+
+    fn sources(&self) -> Result<Vec<PulseSource>, String> {
+        let mut sources: Vec<PulseSource> = Vec::new();
+        let operation = unsafe {
+            (self.api.get_source_info_list)(
+                self.context,
+                collect_source,
+                (&mut sources as *mut Vec<PulseSource>).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio source query failed")?;
+        Ok(sources)
+    }
+
+    pub(crate) fn sinks(&self) -> Result<Vec<PulseSink>, String> {
+        let mut sinks: Vec<PulseSink> = Vec::new();
+        let operation = unsafe {
+            (self.api.get_sink_info_list)(
+                self.context,
+                collect_sink,
+                (&mut sinks as *mut Vec<PulseSink>).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio sink query failed")?;
+        Ok(sinks)
+    }
+
+    pub(crate) fn sink_inputs(&self) -> Result<Vec<PulseSinkInput>, String> {
+        let mut collector = SinkInputCollector {
+            proplist_gets: self.api.proplist_gets,
+            inputs: Vec::new(),
+        };
+        let operation = unsafe {
+            (self.api.get_sink_input_info_list)(
+                self.context,
+                collect_sink_input,
+                (&mut collector as *mut SinkInputCollector).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio sink input query failed")?;
+        Ok(collector.inputs)
+    }
+
+    pub(crate) fn modules(&self) -> Result<Vec<PulseModule>, String> {
+        let mut modules: Vec<PulseModule> = Vec::new();
+        let operation = unsafe {
+            (self.api.get_module_info_list)(
+                self.context,
+                collect_module,
+                (&mut modules as *mut Vec<PulseModule>).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio module query failed")?;
+        Ok(modules)
+    }
+
+    /// Loads a server module and returns its index for [`Self::unload_module`].
+    pub(crate) fn load_module(&self, name: &str, argument: &str) -> Result<u32, String> {
+        let name_c = c_string(name)?;
+        let argument_c = c_string(argument)?;
+        let mut index = PA_INVALID_INDEX;
+        let operation = unsafe {
+            (self.api.load_module)(
+                self.context,
+                name_c.as_ptr(),
+                argument_c.as_ptr(),
+                store_index,
+                (&mut index as *mut u32).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio module load failed")?;
+
+        if index == PA_INVALID_INDEX {
+            return Err(self.context_error(&format!("failed to load {name}")));
+        }
+
+        Ok(index)
+    }
+
+    pub(crate) fn unload_module(&self, index: u32) -> Result<(), String> {
+        let mut success = false;
+        let operation = unsafe {
+            (self.api.unload_module)(
+                self.context,
+                index,
+                store_success,
+                (&mut success as *mut bool).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio module unload failed")?;
+
+        if !success {
+            return Err(self.context_error(&format!("failed to unload module {index}")));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn move_sink_input(&self, index: u32, sink_name: &str) -> Result<(), String> {
+        let sink_name_c = c_string(sink_name)?;
+        let mut success = false;
+        let operation = unsafe {
+            (self.api.move_sink_input_by_name)(
+                self.context,
+                index,
+                sink_name_c.as_ptr(),
+                store_success,
+                (&mut success as *mut bool).cast(),
+            )
+        };
+        self.wait(operation, "PulseAudio stream move failed")?;
+
+        if !success {
+            return Err(
+                self.context_error(&format!("failed to move stream {index} to {sink_name}"))
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PulseIntrospection {
+    fn drop(&mut self) {
+        unsafe {
+            (self.api.context_disconnect)(self.context);
+            (self.api.context_unref)(self.context);
+            (self.api.mainloop_free)(self.mainloop);
+        }
+    }
+}
+
+/// Maps server sources to devices. Monitor sources (the "what you hear" side
+/// of every sink) are left out unless asked for.
+fn pulse_source_devices(
+    sources: Vec<PulseSource>,
+    default_source: Option<&str>,
+    include_monitors: bool,
+) -> Vec<MicDevice> {
+    sources
+        .into_iter()
+        .filter(|source| include_monitors || !source.is_monitor)
+        .map(|source| MicDevice {
+            id: format!("{PULSE_DEVICE_PREFIX}{}", source.name),
+            is_default: default_source == Some(source.name.as_str()),
+            label: source.description,
+            channels: Some(u16::from(source.channels)),
+            sample_rate: Some(source.sample_rate),
+        })
+        .collect()
+}
+
+fn list_pulse_sources(include_monitors: bool) -> Result<Vec<MicDevice>, String> {
+    let connection = PulseIntrospection::connect()?;
+    let server_info = connection.server_info()?;
+    let sources = connection.sources()?;
+
+    Ok(pulse_source_devices(
+        sources,
+        server_info.default_source.as_deref(),
+        include_monitors,
+    ))
+}
+
+// --- ALSA -------------------------------------------------------------------
+
+const SND_PCM_STREAM_CAPTURE: c_int = 1;
+const SND_PCM_FORMAT_S16_LE: c_int = 2;
+const SND_PCM_FORMAT_FLOAT_LE: c_int = 14;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+type SndPcmOpen = unsafe extern "C" fn(
+    pcm: *mut *mut c_void,
+    name: *const c_char,
+    stream: c_int,
+    mode: c_int,
+) -> c_int;
+type SndPcmSetParams = unsafe extern "C" fn(
+    pcm: *mut c_void,
+    format: c_int,
+    access: c_int,
+    channels: c_uint,
+    rate: c_uint,
+    soft_resample: c_int,
+    latency_us: c_uint,
+) -> c_int;
+type SndPcmReadi =
+    unsafe extern "C" fn(pcm: *mut c_void, buffer: *mut c_void, frames: c_ulong) -> c_long;
+type SndPcmRecover = unsafe extern "C" fn(pcm: *mut c_void, error: c_int, silent: c_int) -> c_int;
+type SndPcmClose = unsafe extern "C" fn(pcm: *mut c_void) -> c_int;
+type SndStrError = unsafe extern "C" fn(error: c_int) -> *const c_char;
+type SndDeviceNameHint =
+    unsafe extern "C" fn(card: c_int, iface: *const c_char, hints: *mut *mut *mut c_void) -> c_int;
+type SndDeviceNameGetHint =
+    unsafe extern "C" fn(hint: *const c_void, id: *const c_char) -> *mut c_char;
+type SndDeviceNameFreeHint = unsafe extern "C" fn(hints: *mut *mut c_void) -> c_int;
+
+extern "C" {
+    // Strings returned by `snd_device_name_get_hint` are malloc'd.
+    fn free(pointer: *mut c_void);
+}
+
+struct AlsaApi {
+    open: SndPcmOpen,
+    set_params: SndPcmSetParams,
+    readi: SndPcmReadi,
+    recover: SndPcmRecover,
+    close: SndPcmClose,
+    strerror: SndStrError,
+    name_hint: SndDeviceNameHint,
+    name_get_hint: SndDeviceNameGetHint,
+    name_free_hint: SndDeviceNameFreeHint,
+    _library: Library,
+}
+
+impl AlsaApi {
+    fn load() -> Result<Self, String> {
+        let library = load_library(ALSA_LIBRARY)?;
+        unsafe {
+            Ok(Self {
+                open: load_symbol(&library, b"snd_pcm_open\0")?,
+                set_params: load_symbol(&library, b"snd_pcm_set_params\0")?,
+                readi: load_symbol(&library, b"snd_pcm_readi\0")?,
+                recover: load_symbol(&library, b"snd_pcm_recover\0")?,
+                close: load_symbol(&library, b"snd_pcm_close\0")?,
+                strerror: load_symbol(&library, b"snd_strerror\0")?,
+                name_hint: load_symbol(&library, b"snd_device_name_hint\0")?,
+                name_get_hint: load_symbol(&library, b"snd_device_name_get_hint\0")?,
+                name_free_hint: load_symbol(&library, b"snd_device_name_free_hint\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    fn describe_error(&self, error: c_int) -> String {
+        let message = unsafe { (self.strerror)(error) };
+        if message.is_null() {
+            return format!("error {error}");
+        }
+
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn hint_value(&self, hint: *const c_void, id: &CStr) -> Option<String> {
+        let value = unsafe { (self.name_get_hint)(hint, id.as_ptr()) };
+        let owned = owned_c_str(value);
+        if !value.is_null() {
+            unsafe { free(value.cast()) };
+        }
+        owned
+    }
+
+    /// Every PCM in the ALSA configuration, including plugins like `default`.
+    fn pcm_hints(&self) -> Result<Vec<AlsaPcmHint>, String> {
+        let mut hints = ptr::null_mut();
+        let status = unsafe { (self.name_hint)(-1, c"pcm".as_ptr(), &mut hints) };
+        if status < 0 {
+            return Err(format!(
+                "ALSA device enumeration failed: {}",
+                self.describe_error(status)
+            ));
+        }
+
+        let mut pcms = Vec::new();
+        let mut cursor = hints;
+        unsafe {
+            while !(*cursor).is_null() {
+                let hint = *cursor;
+                if let Some(name) = self.hint_value(hint, c"NAME") {
+                    pcms.push(AlsaPcmHint {
+                        name,
+                        description: self.hint_value(hint, c"DESC"),
+                        io: self.hint_value(hint, c"IOID"),
+                    });
+                }
+                cursor = cursor.add(1);
+            }
+            (self.name_free_hint)(hints);
+        }
+
+        Ok(pcms)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AlsaPcmHint {
+    name: String,
+    description: Option<String>,
+    /// `Input`, `Output`, or `None` for both directions.
+    io: Option<String>,
+}
+
+/// Maps ALSA PCM hints to capture devices. ALSA cannot report a device's
+/// native format without opening it, so channels and rate are left unset.
+fn alsa_capture_devices(hints: Vec<AlsaPcmHint>) -> Vec<MicDevice> {
+    hints
+        .into_iter()
+        .filter(|hint| hint.io.as_deref() != Some("Output") && hint.name != "null")
+        .map(|hint| MicDevice {
+            id: format!("{ALSA_DEVICE_PREFIX}{}", hint.name),
+            is_default: hint.name == ALSA_DEFAULT_DEVICE,
+            // Descriptions are multi-line ("card name\ndevice name").
+            label: hint
+                .description
+                .map(|description| description.lines().collect::<Vec<_>>().join(", "))
+                .unwrap_or_else(|| hint.name.clone()),
+            channels: None,
+            sample_rate: None,
+        })
+        .collect()
+}
+
+fn list_alsa_capture_devices() -> Result<Vec<MicDevice>, String> {
+    let api = AlsaApi::load()?;
+    Ok(alsa_capture_devices(api.pcm_hints()?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AlsaSampleFormat {
+    Float,
+    /// Fallback for `hw:` devices without float support; converted in
+    /// [`s16_to_f32`].
+    S16,
+}
+
+struct AlsaCapture {
+    api: AlsaApi,
+    pcm: *mut c_void,
+    channels: usize,
+    format: AlsaSampleFormat,
+    s16_buffer: Vec<i16>,
+}
+
+unsafe impl Send for AlsaCapture {}
+
+impl AlsaCapture {
+    fn open(device: Option<&str>, sample_rate: u32, channels: usize) -> Result<Self, String> {
+        let api = AlsaApi::load()?;
+        let device = device.unwrap_or(ALSA_DEFAULT_DEVICE);
+        let device_name = c_string(device)?;
+        let mut pcm = ptr::null_mut();
+
+        let status =
+            unsafe { (api.open)(&mut pcm, device_name.as_ptr(), SND_PCM_STREAM_CAPTURE, 0) };
+        if status < 0 {
+            return Err(format!(
+                "ALSA capture on {device} failed: {}",
+                api.describe_error(status)
+            ));
+        }
+
+        let mut capture = Self {
+            api,
+            pcm,
+            channels,
+            format: AlsaSampleFormat::Float,
+            s16_buffer: Vec::new(),
+        };
+
+        if capture.set_params(SND_PCM_FORMAT_FLOAT_LE, sample_rate) >= 0 {
+            return Ok(capture);
+        }
+
+        let status = capture.set_params(SND_PCM_FORMAT_S16_LE, sample_rate);
+        if status < 0 {
+            return Err(format!(
+                "ALSA device {device} does not support {sample_rate} Hz {channels}-channel capture: {}",
+                capture.api.describe_error(status)
+            ));
+        }
+
+        capture.format = AlsaSampleFormat::S16;
+        Ok(capture)
+    }
+
+    fn set_params(&self, format: c_int, sample_rate: u32) -> c_int {
+        unsafe {
+            (self.api.set_params)(
+                self.pcm,
+                format,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                self.channels as c_uint,
+                sample_rate,
+                1,
+                ALSA_LATENCY_US,
+            )
+        }
+    }
+
+    /// Reads up to `frames` frames into `data`, recovering from overruns.
+    fn read_frames(&mut self, data: *mut c_void, frames: usize) -> Result<usize, String> {
+        let read = unsafe { (self.api.readi)(self.pcm, data, frames as c_ulong) };
+        if read >= 0 {
+            return Ok(read as usize);
+        }
+
+        let status = unsafe { (self.api.recover)(self.pcm, read as c_int, 1) };
+        if status < 0 {
+            return Err(format!(
+                "ALSA read failed: {}",
+                self.api.describe_error(read as c_int)
+            ));
+        }
+
+        Ok(0)
+    }
+}
+
+impl CaptureStream for AlsaCapture {
+    fn read(&mut self, buffer: &mut [f32]) -> Result<(), String> {
+        let total_frames = buffer.len() / self.channels;
+        let mut filled = 0;
+
+        while filled < total_frames {
+            let offset = filled * self.channels;
+            let remaining = total_frames - filled;
+
+            let read = match self.format {
+                AlsaSampleFormat::Float => {
+                    let data = buffer[offset..].as_mut_ptr().cast();
+                    self.read_frames(data, remaining)?
+                }
+                AlsaSampleFormat::S16 => {
+                    let mut s16_buffer = std::mem::take(&mut self.s16_buffer);
+                    s16_buffer.resize(remaining * self.channels, 0);
+                    let read = self.read_frames(s16_buffer.as_mut_ptr().cast(), remaining);
+                    if let Ok(read) = read {
+                        s16_to_f32(
+                            &s16_buffer[..read * self.channels],
+                            &mut buffer[offset..offset + read * self.channels],
+                        );
+                    }
+                    self.s16_buffer = s16_buffer;
+                    read?
+                }
+            };
+
+            filled += read;
+        }
+
+        Ok(())
+    }
+
+    fn backend(&self) -> &'static str {
+        "alsa"
+    }
+}
+
+impl Drop for AlsaCapture {
+    fn drop(&mut self) {
+        unsafe { (self.api.close)(self.pcm) };
+    }
+}
+
+fn s16_to_f32(input: &[i16], output: &mut [f32]) {
+    for (sample, converted) in input.iter().zip(output.iter_mut()) {
+        *converted = f32::from(*sample) / 32_768.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        alsa_capture_devices, capture_candidates, open_capture, pulse_source_devices, s16_to_f32,
+        AlsaPcmHint, Backend, PulseSource,
+    };
+
+    #[test]
+    fn resolves_capture_candidates() {
+        assert_eq!(
+            capture_candidates(None),
+            vec![(Backend::Pulse, None), (Backend::Alsa, None)]
+        );
+        assert_eq!(
+            capture_candidates(Some("pulse:alsa_input.usb-mic")),
+            vec![(Backend::Pulse, Some("alsa_input.usb-mic"))]
+        );
+        assert_eq!(
+            capture_candidates(Some("alsa:plughw:1,0")),
+            vec![(Backend::Alsa, Some("plughw:1,0"))]
+        );
+        assert_eq!(
+            capture_candidates(Some("hw:2")),
+            vec![
+                (Backend::Pulse, Some("hw:2")),
+                (Backend::Alsa, Some("hw:2"))
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_sources_and_pcm_hints_to_devices() {
+        let source = |name: &str, is_monitor| PulseSource {
+            name: name.to_string(),
+            description: format!("{name} description"),
+            channels: 2,
+            sample_rate: 48_000,
+            is_monitor,
+        };
+        let sources = vec![
+            source("alsa_input.usb-mic", false),
+            source("alsa_output.speakers.monitor", true),
+        ];
+
+        let devices = pulse_source_devices(sources.clone(), Some("alsa_input.usb-mic"), false);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, "pulse:alsa_input.usb-mic");
+        assert_eq!(devices[0].label, "alsa_input.usb-mic description");
+        assert!(devices[0].is_default);
+        assert_eq!(devices[0].channels, Some(2));
+        assert_eq!(devices[0].sample_rate, Some(48_000));
+        assert_eq!(pulse_source_devices(sources, None, true).len(), 2);
+
+        let hint = |name: &str, description: Option<&str>, io: Option<&str>| AlsaPcmHint {
+            name: name.to_string(),
+            description: description.map(str::to_string),
+            io: io.map(str::to_string),
+        };
+        let devices = alsa_capture_devices(vec![
+            hint("null", Some("Discard all samples"), None),
+            hint("default", Some("Default ALSA Output"), None),
+            hint(
+                "hw:CARD=Mic,DEV=0",
+                Some("USB Mic\nUSB Audio"),
+                Some("Input"),
+            ),
+            hint("surround51", None, Some("Output")),
+        ]);
+        let ids: Vec<_> = devices.iter().map(|device| device.id.as_str()).collect();
+        assert_eq!(ids, ["alsa:default", "alsa:hw:CARD=Mic,DEV=0"]);
+        assert!(devices[0].is_default);
+        assert_eq!(devices[1].label, "USB Mic, USB Audio");
+    }
+
+    #[test]
+    fn converts_s16_samples() {
+        let mut output = [0.0; 3];
+        s16_to_f32(&[i16::MIN, 0, 16_384], &mut output);
+        assert_eq!(output, [-1.0, 0.0, 0.5]);
+    }
+
+    /// Needs a running PulseAudio or PipeWire server with a silent source, e.g.
+    /// `pulseaudio -D --exit-idle-time=-1 && pactl load-module module-null-sink
+    /// sink_name=sidecar_test`, then `cargo test -- --ignored`. Set
+    /// `SHARKORD_TEST_CAPTURE_DEVICE` to capture from another device.
+    #[test]
+    #[ignore]
+    fn captures_from_null_source() {
+        let device = std::env::var("SHARKORD_TEST_CAPTURE_DEVICE")
+            .unwrap_or_else(|_| "pulse:sidecar_test.monitor".to_string());
+        let mut stream = open_capture(Some(&device), 48_000, 2).unwrap();
+        let mut buffer = vec![0.0; 480 * 2];
+
+        for _ in 0..10 {
+            stream.read(&mut buffer).unwrap();
+        }
+
+        assert!(buffer.iter().all(|sample| sample.abs() < 1e-4));
+    }
+}
@@ -0,0 +1,287 @@
+//! Global keybind monitoring on X11, for push keybinds and hotkeys.
+//!
+//! Presses come from XInput2 raw key and button events on the root window,
+//! which are delivered whichever window has focus. Keys are identified by
+//! their evdev keycode (see [`Key::x11_keycode`]), so keybinds follow physical
+//! positions regardless of the keyboard layout.
+
+use crate::keybind::{Key, Keybind, MouseButton};
+use crate::transients;
+use crate::{KeybindChangeHandler, KeybindWatcher};
+use std::collections::HashSet;
+use std::os::fd::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use x11rb::connection::Connection;
+use x11rb::protocol::xinput::{ConnectionExt as _, Device, EventMask, XIEventMask};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+/// How long the watcher sleeps on the X connection before checking whether it
+/// was stopped.
+const STOP_POLL_TIMEOUT_MS: i32 = 100;
+
+/// Every key and mouse button a keybind can name is visible to XInput2.
+pub(crate) fn parse_global_keybind(keybind: Option<&str>) -> Result<Option<Keybind>, String> {
+    Keybind::parse_optional(keybind)
+}
+
+/// Pressed keys and buttons and the resulting keybind states, fed from raw
+/// input events.
+#[derive(Debug, Default)]
+struct KeybindTracker {
+    keybinds: Vec<Keybind>,
+    active: Vec<bool>,
+    /// Held X11 keycodes.
+    pressed_keys: HashSet<u32>,
+    /// Held X11 button numbers.
+    pressed_buttons: HashSet<u32>,
+}
+
+impl KeybindTracker {
+    fn new(keybinds: Vec<Keybind>) -> Self {
+        Self {
+            active: vec![false; keybinds.len()],
+            keybinds,
+            ..Self::default()
+        }
+    }
+
+    /// Applies a key press or release and returns the indices of keybinds
+    /// whose state changed, with their new state.
+    fn key_event(&mut self, keycode: u32, pressed: bool) -> Vec<(usize, bool)> {
+        if pressed {
+            self.pressed_keys.insert(keycode);
+        } else {
+            self.pressed_keys.remove(&keycode);
+        }
+
+        self.changes()
+    }
+
+    /// Like [`Self::key_event`], for mouse buttons.
+    fn button_event(&mut self, button: u32, pressed: bool) -> Vec<(usize, bool)> {
+        if pressed {
+            self.pressed_buttons.insert(button);
+        } else {
+            self.pressed_buttons.remove(&button);
+        }
+
+        self.changes()
+    }
+
+    fn changes(&mut self) -> Vec<(usize, bool)> {
+        let mut changes = Vec::new();
+
+        for (index, keybind) in self.keybinds.iter().enumerate() {
+            let active = keybind.is_active(
+                |key: Key| self.pressed_keys.contains(&u32::from(key.x11_keycode())),
+                |button: MouseButton| self.pressed_buttons.contains(&button.x11_button()),
+            );
+
+            if active != self.active[index] {
+                self.active[index] = active;
+                changes.push((index, active));
+            }
+        }
+
+        changes
+    }
+}
+
+fn wait_readable(connection: &RustConnection) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd: connection.stream().as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    unsafe { libc::poll(&mut poll_fd, 1, STOP_POLL_TIMEOUT_MS) > 0 }
+}
+
+/// Connects to the X server and starts watching `keybinds`. Fails without an
+/// X11 session or when the server lacks XInput 2.
+///
+/// Announcing XInput 2.2 makes the server deliver raw events even while
+/// another client has grabbed the keyboard or pointer, e.g. during a drag.
+pub(crate) fn start_keybind_watcher(
+    keybinds: Vec<Keybind>,
+    mut on_change: KeybindChangeHandler,
+) -> Result<KeybindWatcher, String> {
+    let (connection, screen) = x11rb::connect(None)
+        .map_err(|error| format!("Global keybinds need an X11 session: {error}"))?;
+    let root = connection.setup().roots[screen].root;
+
+    connection
+        .xinput_xi_query_version(2, 2)
+        .map_err(|error| error.to_string())
+        .and_then(|cookie| cookie.reply().map_err(|error| error.to_string()))
+        .map_err(|error| format!("XInput 2 is not available: {error}"))?;
+
+    connection
+        .xinput_xi_select_events(
+            root,
+            &[EventMask {
+                deviceid: Device::ALL_MASTER.into(),
+                mask: vec![
+                    XIEventMask::RAW_KEY_PRESS
+                        | XIEventMask::RAW_KEY_RELEASE
+                        | XIEventMask::RAW_BUTTON_PRESS
+                        | XIEventMask::RAW_BUTTON_RELEASE,
+                ],
+            }],
+        )
+        .map_err(|error| error.to_string())
+        .and_then(|cookie| cookie.check().map_err(|error| error.to_string()))
+        .map_err(|error| format!("Failed to select XInput 2 input events: {error}"))?;
+
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let thread_stop_flag = Arc::clone(&stop_flag);
+
+    let handle = thread::spawn(move || {
+        let mut tracker = KeybindTracker::new(keybinds);
+
+        'watch: while !thread_stop_flag.load(Ordering::Relaxed) {
+            if !wait_readable(&connection) {
+                continue;
+            }
+
+            loop {
+                let event = match connection.poll_for_event() {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(error) => {
+                        log_error!("keybinds", "X connection lost", "error" => error.to_string());
+                        break 'watch;
+                    }
+                };
+
+                let changes = match event {
+                    Event::XinputRawKeyPress(event) => {
+                        transients::note_input_press();
+                        tracker.key_event(event.detail, true)
+                    }
+                    Event::XinputRawKeyRelease(event) => tracker.key_event(event.detail, false),
+                    Event::XinputRawButtonPress(event) => {
+                        transients::note_input_press();
+                        tracker.button_event(event.detail, true)
+                    }
+                    Event::XinputRawButtonRelease(event) => {
+                        tracker.button_event(event.detail, false)
+                    }
+                    _ => continue,
+                };
+
+                for (index, active) in changes {
+                    on_change(index, active);
+                }
+            }
+        }
+
+        for (index, active) in tracker.active.into_iter().enumerate() {
+            if active {
+                on_change(index, false);
+            }
+        }
+    });
+
+    Ok(KeybindWatcher { stop_flag, handle })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_global_keybind, start_keybind_watcher, KeybindTracker};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{KEY_PRESS_EVENT, KEY_RELEASE_EVENT};
+    use x11rb::protocol::xtest::ConnectionExt as _;
+    use x11rb::wrapper::ConnectionExt as _;
+
+    fn keybinds(keybinds: &[&str]) -> Vec<crate::keybind::Keybind> {
+        keybinds
+            .iter()
+            .map(|keybind| parse_global_keybind(Some(keybind)).unwrap().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tracks_keybind_state_from_key_events() {
+        let mut tracker = KeybindTracker::new(keybinds(&["Control+KeyF"]));
+
+        assert!(tracker.key_event(41, true).is_empty());
+        assert!(tracker.key_event(41, false).is_empty());
+        assert!(tracker.key_event(105, true).is_empty()); // Right Control
+        assert_eq!(tracker.key_event(41, true), [(0, true)]);
+        // An extra modifier no longer matches the keybind exactly.
+        assert_eq!(tracker.key_event(50, true), [(0, false)]);
+        assert_eq!(tracker.key_event(50, false), [(0, true)]);
+        assert_eq!(tracker.key_event(105, false), [(0, false)]);
+    }
+
+    #[test]
+    fn tracks_mouse_buttons_with_modifiers() {
+        let mut tracker = KeybindTracker::new(keybinds(&["Mouse4", "Shift+Mouse3"]));
+
+        assert_eq!(tracker.button_event(8, true), [(0, true)]);
+        assert_eq!(tracker.button_event(8, false), [(0, false)]);
+        // X11 numbers the middle button 2.
+        assert!(tracker.button_event(2, true).is_empty());
+        assert_eq!(tracker.key_event(62, true), [(1, true)]);
+        assert_eq!(tracker.button_event(2, false), [(1, false)]);
+    }
+
+    /// Needs an X server, e.g. `xvfb-run cargo test`; skipped without one.
+    #[test]
+    fn reports_synthesized_key_presses() {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("skipping reports_synthesized_key_presses: DISPLAY is not set");
+            return;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let watcher = start_keybind_watcher(
+            keybinds(&["Control+KeyF", "Shift+F13"]),
+            Box::new(move |index, active| {
+                let _ = sender.send((index, active));
+            }),
+        )
+        .unwrap();
+
+        let (connection, screen) = x11rb::connect(None).unwrap();
+        let root = connection.setup().roots[screen].root;
+        let key = |keycode: u8, pressed: bool| {
+            let event_type = if pressed {
+                KEY_PRESS_EVENT
+            } else {
+                KEY_RELEASE_EVENT
+            };
+            connection
+                .xtest_fake_input(event_type, keycode, 0, root, 0, 0, 0)
+                .unwrap();
+        };
+
+        key(37, true); // ControlLeft
+        key(41, true); // KeyF
+        key(41, false);
+        key(62, true); // ShiftRight
+        key(191, true); // F13
+        connection.sync().unwrap();
+
+        assert_eq!(receiver.recv().unwrap(), (0, true));
+        assert_eq!(receiver.recv().unwrap(), (0, false));
+        // Control is still held, so Shift+F13 does not match exactly.
+        key(37, false);
+        connection.sync().unwrap();
+        assert_eq!(receiver.recv().unwrap(), (1, true));
+
+        watcher.stop_flag.store(true, Ordering::Relaxed);
+        watcher.handle.join().unwrap();
+        assert_eq!(receiver.recv().unwrap(), (1, false));
+
+        key(191, false);
+        key(62, false);
+        connection.sync().unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! X11 window lookups for mapping Electron `desktopCapturer` window sources to
+//! processes.
+//!
+//! On Linux, `window:<id>:…` source ids carry the X11 window id. The owning
+//! process comes from the window's `_NET_WM_PID` property, or, for clients
+//! that don't set it, from the X server itself via the X-Resource extension
+//! (which only knows pids of clients on the local machine). Under Wayland
+//! without XWayland every lookup simply finds nothing.
+
+use std::collections::HashMap;
+use x11rb::connection::Connection;
+use x11rb::protocol::res::{ClientIdMask, ClientIdSpec, ConnectionExt as _};
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt as _, Window};
+use x11rb::rust_connection::RustConnection;
+
+/// Longest title read from a window, in bytes.
+const MAX_TITLE_BYTES: u32 = 1024;
+
+pub(crate) struct X11Windows {
+    connection: RustConnection,
+    root: Window,
+    net_wm_pid: Atom,
+    net_wm_name: Atom,
+    net_client_list: Atom,
+    utf8_string: Atom,
+    has_resource_extension: bool,
+}
+
+fn x11_error(error: impl std::fmt::Display) -> String {
+    format!("X11 request failed: {error}")
+}
+
+impl X11Windows {
+    pub(crate) fn connect() -> Result<Self, String> {
+        let (connection, screen) = x11rb::connect(None)
+            .map_err(|error| format!("failed to connect to the X server: {error}"))?;
+        let root = connection.setup().roots[screen].root;
+
+        let intern = |name: &[u8]| -> Result<Atom, String> {
+            Ok(connection
+                .intern_atom(false, name)
+                .map_err(x11_error)?
+                .reply()
+                .map_err(x11_error)?
+                .atom)
+        };
+        let net_wm_pid = intern(b"_NET_WM_PID")?;
+        let net_wm_name = intern(b"_NET_WM_NAME")?;
+        let net_client_list = intern(b"_NET_CLIENT_LIST")?;
+        let utf8_string = intern(b"UTF8_STRING")?;
+
+        // QueryClientIds needs X-Resource 1.2.
+        let has_resource_extension = connection
+            .res_query_version(1, 2)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .is_some_and(|version| (version.server_major, version.server_minor) >= (1, 2));
+
+        Ok(Self {
+            connection,
+            root,
+            net_wm_pid,
+            net_wm_name,
+            net_client_list,
+            utf8_string,
+            has_resource_extension,
+        })
+    }
+
+    fn property_u32s(&self, window: Window, property: Atom, type_: AtomEnum) -> Vec<u32> {
+        self.connection
+            .get_property(false, window, property, type_, 0, u32::MAX / 4)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .and_then(|reply| reply.value32().map(Iterator::collect))
+            .unwrap_or_default()
+    }
+
+    fn property_string(&self, window: Window, property: Atom, type_: Atom) -> Option<String> {
+        let reply = self
+            .connection
+            .get_property(false, window, property, type_, 0, MAX_TITLE_BYTES / 4)
+            .ok()?
+            .reply()
+            .ok()?;
+        let value = String::from_utf8_lossy(&reply.value).trim().to_string();
+
+        (!value.is_empty()).then_some(value)
+    }
+
+    /// The pid of the process owning `window`, if it can be found.
+    pub(crate) fn window_pid(&self, window: Window) -> Option<u32> {
+        self.property_u32s(window, self.net_wm_pid, AtomEnum::CARDINAL)
+            .first()
+            .copied()
+            .filter(|pid| *pid != 0)
+            .or_else(|| self.client_pid(window))
+    }
+
+    /// Asks the server which local process created `window`.
+    fn client_pid(&self, window: Window) -> Option<u32> {
+        if !self.has_resource_extension {
+            return None;
+        }
+
+        let spec = ClientIdSpec {
+            client: window,
+            mask: ClientIdMask::LOCAL_CLIENT_PID,
+        };
+        let reply = self
+            .connection
+            .res_query_client_ids(&[spec])
+            .ok()?
+            .reply()
+            .ok()?;
+
+        reply
+            .ids
+            .iter()
+            .find(|id| id.spec.mask.contains(ClientIdMask::LOCAL_CLIENT_PID))
+            .and_then(|id| id.value.first().copied())
+            .filter(|pid| *pid != 0)
+    }
+
+    /// `_NET_WM_NAME`, falling back to the legacy `WM_NAME`.
+    pub(crate) fn window_title(&self, window: Window) -> Option<String> {
+        self.property_string(window, self.net_wm_name, self.utf8_string)
+            .or_else(|| {
+                self.property_string(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())
+            })
+    }
+
+    /// Top-level windows the window manager lists in `_NET_CLIENT_LIST`.
+    fn client_windows(&self) -> Vec<Window> {
+        self.property_u32s(self.root, self.net_client_list, AtomEnum::WINDOW)
+    }
+
+    /// The title of the first titled top-level window of every process.
+    pub(crate) fn window_titles_by_pid(&self) -> HashMap<u32, String> {
+        let mut titles = HashMap::new();
+
+        for window in self.client_windows() {
+            let Some(pid) = self.window_pid(window) else {
+                continue;
+            };
+
+            if titles.contains_key(&pid) {
+                continue;
+            }
+
+            if let Some(title) = self.window_title(window) {
+                titles.insert(pid, title);
+            }
+        }
+
+        titles
+    }
+}
+
+/// Resolves an X11 window id to its process, for `windows.resolve_source`.
+pub(crate) fn window_pid(window: Window) -> Option<u32> {
+    match X11Windows::connect() {
+        Ok(windows) => windows.window_pid(window),
+        Err(error) => {
+            log_debug!("x11", "window lookup unavailable", "error" => &error);
+            None
+        }
+    }
+}
+
+/// Window titles for labelling audio targets; empty without an X server.
+pub(crate) fn window_titles_by_pid() -> HashMap<u32, String> {
+    X11Windows::connect()
+        .map(|windows| windows.window_titles_by_pid())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::X11Windows;
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{
+        AtomEnum, ConnectionExt as _, CreateWindowAux, PropMode, WindowClass,
+    };
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::COPY_DEPTH_FROM_PARENT;
+
+    /// Needs an X server, e.g. `xvfb-run cargo test`; skipped without one.
+    #[test]
+    fn resolves_window_pid_and_title() {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("skipping resolves_window_pid_and_title: DISPLAY is not set");
+            return;
+        }
+
+        let (connection, screen) = x11rb::connect(None).unwrap();
+        let root = connection.setup().roots[screen].root;
+        let create_window = || {
+            let window = connection.generate_id().unwrap();
+            connection
+                .create_window(
+                    COPY_DEPTH_FROM_PARENT,
+                    window,
+                    root,
+                    0,
+                    0,
+                    16,
+                    16,
+                    0,
+                    WindowClass::INPUT_OUTPUT,
+                    0,
+                    &CreateWindowAux::new(),
+                )
+                .unwrap();
+            window
+        };
+
+        let titled = create_window();
+        let net_wm_pid = connection
+            .intern_atom(false, b"_NET_WM_PID")
+            .unwrap()
+            .reply()
+            .unwrap()
+            .atom;
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                titled,
+                net_wm_pid,
+                AtomEnum::CARDINAL,
+                &[std::process::id()],
+            )
+            .unwrap();
+        connection
+            .change_property8(
+                PropMode::REPLACE,
+                titled,
+                AtomEnum::WM_NAME,
+                AtomEnum::STRING,
+                b"Sidecar test",
+            )
+            .unwrap();
+        // No `_NET_WM_PID`, so only X-Resource can resolve this one.
+        let bare = create_window();
+        connection.sync().unwrap();
+
+        let windows = X11Windows::connect().unwrap();
+        assert_eq!(windows.window_pid(titled), Some(std::process::id()));
+        assert_eq!(
+            windows.window_title(titled).as_deref(),
+            Some("Sidecar test")
+        );
+        assert_eq!(windows.window_pid(bare), Some(std::process::id()));
+        assert_eq!(windows.window_title(bare), None);
+    }
+}
@@ -0,0 +1,442 @@
+//! Structured, level-filtered logging.
+//!
+//! Every record has a level, a target naming the subsystem that produced it
+//! (`voice_filter`, `capture`, ...) and optional key/value fields. Records are
+//! written to stderr either as `[capture-sidecar] LEVEL target: message k=v`
+//! text lines or as JSON lines, and can additionally be forwarded to the client
+//! as `log` events so the desktop app can fold them into its own log files.
+//!
+//! The initial configuration comes from `SHARKORD_SIDECAR_LOG`
+//! (`info,voice_filter=debug`) and `SHARKORD_SIDECAR_LOG_FORMAT` (`text` or
+//! `json`); `log.set_level` changes it at runtime.
+
+use crate::{now_unix_ms, result_value, write_event};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+const LOG_FILTER_ENV: &str = "SHARKORD_SIDECAR_LOG";
+const LOG_FORMAT_ENV: &str = "SHARKORD_SIDECAR_LOG_FORMAT";
+const LOG_PREFIX: &str = "[capture-sidecar]";
+
+/// `log_event!(level, "target", "message", "key" => value, ...)`. Field values
+/// are anything `serde_json::json!` accepts; the message is only formatted when
+/// the record passes the level filter.
+macro_rules! log_event {
+    ($level:expr, $target:expr, $message:expr $(, $key:literal => $value:expr)* $(,)?) => {{
+        let level = $level;
+        let target = $target;
+        if $crate::logging::enabled(level, target) {
+            $crate::logging::write_record(
+                level,
+                target,
+                &$message,
+                vec![$(($key, ::serde_json::json!($value))),*],
+            );
+        }
+    }};
+}
+
+macro_rules! log_error {
+    ($($arg:tt)+) => { log_event!($crate::logging::LogLevel::Error, $($arg)+) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => { log_event!($crate::logging::LogLevel::Warn, $($arg)+) };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)+) => { log_event!($crate::logging::LogLevel::Info, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! log_debug {
+    ($($arg:tt)+) => { log_event!($crate::logging::LogLevel::Debug, $($arg)+) };
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl LogLevel {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "warn" | "warning" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone)]
+struct LoggerConfig {
+    level: LogLevel,
+    target_levels: HashMap<String, LogLevel>,
+    format: LogFormat,
+    forward: bool,
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            target_levels: HashMap::new(),
+            format: LogFormat::Text,
+            forward: false,
+        }
+    }
+}
+
+impl LoggerConfig {
+    fn level_for(&self, target: &str) -> LogLevel {
+        // `voice_filter.binary` falls back to `voice_filter`, then the default.
+        let mut scope = target;
+        loop {
+            if let Some(level) = self.target_levels.get(scope) {
+                return *level;
+            }
+
+            match scope.rfind('.') {
+                Some(index) => scope = &scope[..index],
+                None => return self.level,
+            }
+        }
+    }
+
+    fn max_level(&self) -> LogLevel {
+        self.target_levels
+            .values()
+            .copied()
+            .fold(self.level, LogLevel::max)
+    }
+}
+
+/// Parses a `default,target=level,...` filter string. Unknown levels are
+/// reported instead of silently ignored so typos in the env var are visible.
+fn parse_filter(filter: &str, config: &mut LoggerConfig) -> Result<(), String> {
+    for directive in filter
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+    {
+        match directive.split_once('=') {
+            Some((target, level)) => {
+                let level = LogLevel::parse(level)
+                    .ok_or_else(|| format!("Unknown log level in `{directive}`"))?;
+                config
+                    .target_levels
+                    .insert(target.trim().to_string(), level);
+            }
+            None => {
+                config.level = LogLevel::parse(directive)
+                    .ok_or_else(|| format!("Unknown log level `{directive}`"))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+static CONFIG: OnceLock<RwLock<LoggerConfig>> = OnceLock::new();
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static EVENT_SINK: OnceLock<Arc<Mutex<io::Stdout>>> = OnceLock::new();
+
+fn config() -> &'static RwLock<LoggerConfig> {
+    CONFIG.get_or_init(|| RwLock::new(LoggerConfig::default()))
+}
+
+fn store_config(next: LoggerConfig) {
+    MAX_LEVEL.store(next.max_level() as u8, Ordering::Relaxed);
+    if let Ok(mut lock) = config().write() {
+        *lock = next;
+    }
+}
+
+/// Reads the environment configuration and registers stdout for forwarded
+/// `log` events. Called once at startup before anything is logged.
+pub(crate) fn init(stdout: Arc<Mutex<io::Stdout>>) {
+    let _ = EVENT_SINK.set(stdout);
+
+    let mut next = LoggerConfig::default();
+    let mut filter_error = None;
+
+    if let Ok(filter) = std::env::var(LOG_FILTER_ENV) {
+        if let Err(error) = parse_filter(&filter, &mut next) {
+            filter_error = Some(error);
+        }
+    }
+
+    if let Ok(format) = std::env::var(LOG_FORMAT_ENV) {
+        if format.trim().eq_ignore_ascii_case("json") {
+            next.format = LogFormat::Json;
+        }
+    }
+
+    store_config(next);
+
+    if let Some(error) = filter_error {
+        log_warn!("sidecar", "ignoring invalid log filter", "filter" => LOG_FILTER_ENV, "error" => error);
+    }
+}
+
+pub(crate) fn enabled(level: LogLevel, target: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    config()
+        .read()
+        .map(|lock| level <= lock.level_for(target))
+        .unwrap_or(true)
+}
+
+fn format_text_field(value: &Value) -> String {
+    match value {
+        Value::String(text) if !text.is_empty() && !text.contains(char::is_whitespace) => {
+            text.clone()
+        }
+        other => other.to_string(),
+    }
+}
+
+pub(crate) fn write_record(
+    level: LogLevel,
+    target: &str,
+    message: &str,
+    fields: Vec<(&'static str, Value)>,
+) {
+    let (format, forward) = config()
+        .read()
+        .map(|lock| (lock.format, lock.forward))
+        .unwrap_or((LogFormat::Text, false));
+
+    let timestamp_ms = now_unix_ms();
+    let fields: Map<String, Value> = fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+    write_stderr_line(format, timestamp_ms, level, target, message, &fields);
+
+    if forward {
+        if let Some(stdout) = EVENT_SINK.get() {
+            write_event(
+                stdout,
+                "log",
+                LogRecordEvent {
+                    timestamp_ms,
+                    level,
+                    target,
+                    message,
+                    fields,
+                },
+            );
+        }
+    }
+}
+
+/// Writes a record to stderr only, never forwarding it. The panic hook uses
+/// this because the panicking thread may hold the shared stdout writer.
+pub(crate) fn write_stderr_record(
+    level: LogLevel,
+    target: &str,
+    message: &str,
+    fields: Vec<(&'static str, Value)>,
+) {
+    let format = config()
+        .try_read()
+        .map(|lock| lock.format)
+        .unwrap_or(LogFormat::Text);
+    let fields: Map<String, Value> = fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+    write_stderr_line(format, now_unix_ms(), level, target, message, &fields);
+}
+
+fn write_stderr_line(
+    format: LogFormat,
+    timestamp_ms: u128,
+    level: LogLevel,
+    target: &str,
+    message: &str,
+    fields: &Map<String, Value>,
+) {
+    let line = match format {
+        LogFormat::Text => {
+            let mut line = format!(
+                "{LOG_PREFIX} {} {target}: {message}",
+                level.as_str().to_ascii_uppercase()
+            );
+            for (key, value) in fields {
+                line.push_str(&format!(" {key}={}", format_text_field(value)));
+            }
+            line
+        }
+        LogFormat::Json => json!({
+            "timestampMs": timestamp_ms,
+            "level": level.as_str(),
+            "target": target,
+            "message": message,
+            "fields": fields,
+        })
+        .to_string(),
+    };
+
+    let mut stderr = io::stderr().lock();
+    let _ = writeln!(stderr, "{line}");
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LogRecordEvent<'a> {
+    timestamp_ms: u128,
+    level: LogLevel,
+    target: &'a str,
+    message: &'a str,
+    fields: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SetLogLevelParams {
+    level: Option<LogLevel>,
+    targets: Option<HashMap<String, Option<LogLevel>>>,
+    format: Option<LogFormat>,
+    forward: Option<bool>,
+}
+
+/// Effective configuration, as returned by `log.set_level` and carried in
+/// `state.snapshot`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(crate) struct LogSettings {
+    level: LogLevel,
+    targets: BTreeMap<String, LogLevel>,
+    format: LogFormat,
+    forward: bool,
+}
+
+impl From<&LoggerConfig> for LogSettings {
+    fn from(config: &LoggerConfig) -> Self {
+        Self {
+            level: config.level,
+            targets: config
+                .target_levels
+                .iter()
+                .map(|(target, level)| (target.clone(), *level))
+                .collect(),
+            format: config.format,
+            forward: config.forward,
+        }
+    }
+}
+
+pub(crate) fn current_settings() -> LogSettings {
+    config()
+        .read()
+        .map(|lock| LogSettings::from(&*lock))
+        .unwrap_or_else(|_| LogSettings::from(&LoggerConfig::default()))
+}
+
+/// Replaces the whole configuration, dropping overrides the settings lack.
+pub(crate) fn apply_settings(settings: LogSettings) {
+    store_config(LoggerConfig {
+        level: settings.level,
+        target_levels: settings.targets.into_iter().collect(),
+        format: settings.format,
+        forward: settings.forward,
+    });
+}
+
+/// `log.set_level`: updates the default level, per-target overrides (a `null`
+/// level removes the override), the stderr format and client forwarding.
+pub(crate) fn handle_log_set_level(params: Value) -> Result<Value, String> {
+    let parsed: SetLogLevelParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    let mut next = config()
+        .read()
+        .map(|lock| lock.clone())
+        .map_err(|_| "Logger configuration lock poisoned".to_string())?;
+
+    if let Some(level) = parsed.level {
+        next.level = level;
+    }
+
+    for (target, level) in parsed.targets.unwrap_or_default() {
+        match level {
+            Some(level) => {
+                next.target_levels.insert(target, level);
+            }
+            None => {
+                next.target_levels.remove(&target);
+            }
+        }
+    }
+
+    if let Some(format) = parsed.format {
+        next.format = format;
+    }
+
+    if let Some(forward) = parsed.forward {
+        next.forward = forward;
+    }
+
+    let result = LogSettings::from(&next);
+    store_config(next);
+
+    result_value(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_filter, LogLevel, LoggerConfig};
+
+    #[test]
+    fn parses_filter_directives() {
+        let mut config = LoggerConfig::default();
+        parse_filter("debug, voice_filter=trace,capture=warn", &mut config).unwrap();
+
+        assert_eq!(config.level, LogLevel::Debug);
+        assert_eq!(config.level_for("voice_filter"), LogLevel::Trace);
+        assert_eq!(config.level_for("voice_filter.binary"), LogLevel::Trace);
+        assert_eq!(config.level_for("capture"), LogLevel::Warn);
+        assert_eq!(config.level_for("dispatch"), LogLevel::Debug);
+        assert_eq!(config.max_level(), LogLevel::Trace);
+
+        assert!(parse_filter("verbose", &mut config).is_err());
+    }
+}
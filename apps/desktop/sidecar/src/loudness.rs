@@ -0,0 +1,244 @@
+//! Loudness-based AGC for the voice filter chain.
+//!
+//! Unlike the block RMS AGC, it measures K-weighted short-term loudness
+//! (BS.1770, in LUFS) and only over blocks that contain speech, so breaths,
+//! keyboard noise and the room between words do not pull the gain around.
+//! Speech is taken from the LSNR of a denoise stage ahead of it, or from a
+//! simple energy detector when there is none.
+
+use crate::filters::{Biquad, BiquadFilter};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+pub(crate) const DEFAULT_TARGET_LUFS: f32 = -18.0;
+pub(crate) const TARGET_RANGE_LUFS: RangeInclusive<f32> = -36.0..=-10.0;
+const MAX_BOOST_DB: f32 = 18.0;
+const MAX_CUT_DB: f32 = 12.0;
+/// Time constant of the gain, counted in speech only.
+const ADAPTATION_MS: f32 = 3_000.0;
+const OUTPUT_LIMIT: f32 = 0.98;
+
+/// BS.1770 measures in 100 ms steps and short-term loudness over 3 s.
+const BLOCK_MS: usize = 100;
+const SHORT_TERM_BLOCKS: usize = 30;
+/// Mean LSNR of a block's hops above which it counts as speech.
+const SPEECH_LSNR_DB: f32 = 3.0;
+
+/// Without LSNR, a block is speech when it is this much louder than the
+/// noise floor...
+const VAD_MARGIN_LU: f32 = 10.0;
+/// ...and louder than this at all.
+const VAD_MIN_LUFS: f32 = -60.0;
+/// The floor falls to any quieter block at once and creeps up otherwise.
+const VAD_FLOOR_RISE_LU_PER_SECOND: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LoudnessMetrics {
+    /// Short-term loudness of the last 3 s of speech; `null` before any.
+    short_term_lufs: Option<f32>,
+    gain_db: f32,
+}
+
+fn loudness_lufs(mean_square: f64) -> f32 {
+    (-0.691 + 10.0 * mean_square.max(1e-12).log10()) as f32
+}
+
+/// Energy detector for chains without a denoise stage ahead of the AGC.
+struct EnergyVad {
+    noise_floor_lufs: f32,
+}
+
+impl EnergyVad {
+    fn is_speech(&mut self, loudness_lufs: f32, seconds: f32) -> bool {
+        if loudness_lufs < self.noise_floor_lufs {
+            self.noise_floor_lufs = loudness_lufs;
+        } else {
+            self.noise_floor_lufs += VAD_FLOOR_RISE_LU_PER_SECOND * seconds;
+        }
+
+        loudness_lufs > self.noise_floor_lufs + VAD_MARGIN_LU && loudness_lufs > VAD_MIN_LUFS
+    }
+}
+
+pub(crate) struct LoudnessAutoGainControl {
+    target_lufs: f32,
+    sample_rate: usize,
+    channels: usize,
+    k_weighting: [BiquadFilter; 2],
+    weighted: Vec<f32>,
+    vad: EnergyVad,
+    speech: bool,
+    /// Sum of squares and frame count of the speech block being collected.
+    block_sum: f64,
+    block_frames: usize,
+    /// Mean square of each of the last speech blocks.
+    blocks: VecDeque<f64>,
+    short_term_lufs: Option<f32>,
+    gain_db: f32,
+    gain: f32,
+}
+
+impl LoudnessAutoGainControl {
+    pub(crate) fn new(target_lufs: f32, sample_rate: usize, channels: usize) -> Self {
+        let channels = channels.max(1);
+        let k_weighting =
+            Biquad::k_weighting(sample_rate as f32).map(|stage| BiquadFilter::new(stage, channels));
+
+        Self {
+            target_lufs,
+            sample_rate,
+            channels,
+            k_weighting,
+            weighted: Vec::new(),
+            vad: EnergyVad {
+                noise_floor_lufs: f32::INFINITY,
+            },
+            speech: false,
+            block_sum: 0.0,
+            block_frames: 0,
+            blocks: VecDeque::with_capacity(SHORT_TERM_BLOCKS),
+            short_term_lufs: None,
+            gain_db: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// `hop_lsnr` is the LSNR of the hops a denoise stage ahead of this one
+    /// ran on for these samples, or `None` without such a stage.
+    pub(crate) fn process(&mut self, samples: &mut [f32], hop_lsnr: Option<&[f32]>) {
+        let frame_count = samples.len() / self.channels;
+        if frame_count == 0 {
+            return;
+        }
+
+        self.weighted.clear();
+        self.weighted.extend_from_slice(samples);
+        for filter in self.k_weighting.iter_mut() {
+            filter.process(&mut self.weighted);
+        }
+        // BS.1770 sums the mean squares of the channels.
+        let sum_squares: f64 = self
+            .weighted
+            .iter()
+            .map(|sample| f64::from(*sample) * f64::from(*sample))
+            .sum();
+        let seconds = frame_count as f32 / self.sample_rate as f32;
+
+        self.speech = match hop_lsnr {
+            Some([]) => self.speech,
+            Some(hop_lsnr) => hop_lsnr.iter().sum::<f32>() / hop_lsnr.len() as f32 > SPEECH_LSNR_DB,
+            None => self
+                .vad
+                .is_speech(loudness_lufs(sum_squares / frame_count as f64), seconds),
+        };
+
+        if self.speech {
+            self.measure(sum_squares, frame_count);
+            if let Some(short_term_lufs) = self.short_term_lufs {
+                let desired_db =
+                    (self.target_lufs - short_term_lufs).clamp(-MAX_CUT_DB, MAX_BOOST_DB);
+                let adaptation = 1.0 - (-seconds * 1_000.0 / ADAPTATION_MS).exp();
+                self.gain_db += (desired_db - self.gain_db) * adaptation;
+            }
+        }
+
+        // Ramp across the block so gain changes never step.
+        let gain = 10.0_f32.powf(self.gain_db / 20.0);
+        let step = (gain - self.gain) / frame_count as f32;
+        for frame in samples.chunks_mut(self.channels) {
+            self.gain += step;
+            for sample in frame {
+                *sample = (*sample * self.gain).clamp(-OUTPUT_LIMIT, OUTPUT_LIMIT);
+            }
+        }
+        self.gain = gain;
+    }
+
+    fn measure(&mut self, sum_squares: f64, frame_count: usize) {
+        self.block_sum += sum_squares;
+        self.block_frames += frame_count;
+        if self.block_frames < self.sample_rate * BLOCK_MS / 1_000 {
+            return;
+        }
+
+        if self.blocks.len() == SHORT_TERM_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks
+            .push_back(self.block_sum / self.block_frames as f64);
+        self.block_sum = 0.0;
+        self.block_frames = 0;
+
+        let mean_square = self.blocks.iter().sum::<f64>() / self.blocks.len() as f64;
+        self.short_term_lufs = Some(loudness_lufs(mean_square));
+    }
+
+    pub(crate) fn metrics(&self) -> LoudnessMetrics {
+        LoudnessMetrics {
+            short_term_lufs: self.short_term_lufs,
+            gain_db: self.gain_db,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoudnessAutoGainControl;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: usize = 48_000;
+    /// 10 ms, one DeepFilterNet hop.
+    const HOP: usize = 480;
+
+    fn tone(amplitude: f32, hop: usize) -> Vec<f32> {
+        (hop * HOP..(hop + 1) * HOP)
+            .map(|index| amplitude * (2.0 * PI * 997.0 * index as f32 / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn measures_k_weighted_loudness_of_speech() {
+        let mut agc = LoudnessAutoGainControl::new(-18.0, SAMPLE_RATE, 1);
+        for hop in 0..400 {
+            agc.process(&mut tone(0.1, hop), Some(&[10.0]));
+        }
+
+        // A full-scale 997 Hz sine reads -3.01 LUFS; this one is 20 dB down.
+        let lufs = agc.metrics().short_term_lufs.unwrap();
+        assert!((lufs + 23.01).abs() < 0.1, "{lufs}");
+    }
+
+    #[test]
+    fn adapts_only_while_speech_is_present() {
+        let mut agc = LoudnessAutoGainControl::new(-18.0, SAMPLE_RATE, 1);
+
+        // Background noise alone never moves the gain, however loud.
+        for hop in 0..500 {
+            agc.process(&mut tone(0.5, hop), Some(&[-10.0]));
+        }
+        assert_eq!(agc.metrics().gain_db, 0.0);
+        assert_eq!(agc.metrics().short_term_lufs, None);
+
+        // A quiet speaker at -29 LUFS is brought up to the target.
+        let mut output = Vec::new();
+        for hop in 0..3_000 {
+            output = tone(0.05, hop);
+            agc.process(&mut output, Some(&[10.0]));
+        }
+        assert!((agc.metrics().gain_db - 11.0).abs() < 0.5);
+        let peak = output
+            .iter()
+            .fold(0.0_f32, |peak, sample| peak.max(sample.abs()));
+        assert!((peak - 0.05 * 10.0_f32.powf(11.0 / 20.0)).abs() < 0.01);
+
+        // Pauses keep the gain where it is.
+        let gain_db = agc.metrics().gain_db;
+        for hop in 0..300 {
+            agc.process(&mut tone(0.001, hop), Some(&[-15.0]));
+        }
+        assert_eq!(agc.metrics().gain_db, gain_db);
+    }
+}
@@ -1,19 +1,63 @@
+#[macro_use]
+mod logging;
+mod comfort_noise;
+mod crash;
+mod dereverb;
+mod dispatch;
+mod dsp;
+mod dynamics;
+mod eq;
+mod filters;
+mod hotkeys;
+mod keybind;
+mod limiter;
+#[cfg(target_os = "linux")]
+mod linux_app_audio;
+#[cfg(target_os = "linux")]
+mod linux_audio;
+#[cfg(target_os = "linux")]
+mod linux_keybinds;
+#[cfg(target_os = "linux")]
+mod linux_x11;
+mod loudness;
+mod params;
+mod rpc;
+mod snapshot;
+#[cfg(test)]
+mod test_signals;
+mod transients;
+mod transmit;
+
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine;
+use comfort_noise::ComfortNoiseOptions;
 use deep_filter::tract::{DfParams, DfTract, ReduceMask, RuntimeParams};
+use dereverb::DereverbOptions;
+use dispatch::{Dispatcher, RequestContext};
+use dsp::{
+    AutoGainControlMode, ChannelMode, DeepFilterProcessor, DspChain, StageDescriptor, StageMetrics,
+};
+#[cfg(any(windows, target_os = "linux"))]
+use keybind::Keybind;
+#[cfg(windows)]
+use keybind::{Key, MouseButton, Trigger};
+use limiter::{LimiterOptions, TruePeakLimiter};
 use ndarray::Array2;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::Value;
 use std::collections::VecDeque;
 #[cfg(any(windows, test))]
 use std::collections::HashMap;
 use std::io::{self, BufRead, Read, Write};
 use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use transmit::{PushKeybindStates, TransmitGate, TransmitOptions, TransmitStateEvent};
 use uuid::Uuid;
 
 #[cfg(windows)]
@@ -24,7 +68,7 @@ use std::mem::size_of;
 use std::path::Path;
 #[cfg(windows)]
 use std::ptr;
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 use std::time::Instant;
 
 #[cfg(windows)]
@@ -75,36 +119,25 @@ use windows_core::implement;
 const TARGET_SAMPLE_RATE: u32 = 48_000;
 const TARGET_CHANNELS: usize = 2;
 const FRAME_SIZE: usize = 960;
-const PROTOCOL_VERSION: u32 = 1;
+/// Bumped whenever the `rpc.describe` schema changes; see `rpc-schema.json`.
+const PROTOCOL_VERSION: u32 = 17;
+/// Oldest protocol version still accepted on incoming PCM frames.
+const MIN_PROTOCOL_VERSION: u32 = 1;
 const PCM_ENCODING: &str = "f32le_base64";
+const SYSTEM_AUDIO_TARGET_ID: &str = "system";
 const APP_AUDIO_BINARY_EGRESS_FRAMING: &str = "length_prefixed_f32le_v1";
 const VOICE_FILTER_BINARY_FRAMING: &str = "length_prefixed_f32le_v1";
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 const MAX_APP_AUDIO_BINARY_FRAME_BYTES: usize = 4 * 1024 * 1024;
 const MAX_VOICE_FILTER_BINARY_FRAME_BYTES: usize = 4 * 1024 * 1024;
 const DEEP_FILTER_WARMUP_BLOCKS: usize = 20;
+const DEEP_FILTER_WARMUP_PROGRESS_INTERVAL: usize = 5;
 const ECHO_REFERENCE_MAX_BUFFER_MS: usize = 1_200;
 const ECHO_REFERENCE_DELAY_MS: usize = 80;
-const ECHO_REFERENCE_MIN_ENERGY: f32 = 1e-6;
-const ECHO_SUBTRACTION_MAX: f32 = 0.85;
-const ECHO_DUCKING_MIN_GAIN: f32 = 0.55;
-// Limiter: threshold just below full scale, ~1ms attack, ~100ms release at 48kHz
-#[cfg(windows)]
+/// Reference frames laid out unlike the session are downmixed; enough for 7.1
+/// output.
+const MAX_REFERENCE_CHANNELS: usize = 8;
 const MIC_CAPTURE_FRAME_SIZE: usize = 480; // 10ms at 48kHz — matches DeepFilterNet hop size
-const LIMITER_THRESHOLD: f32 = 0.95;
-const LIMITER_ATTACK_COEFF: f32 = 0.979_2; // exp(-1/48)
-const LIMITER_RELEASE_COEFF: f32 = 0.999_8; // exp(-1/4800)
-
-// Noise gate — applied per DeepFilterNet hop using the lsnr value returned by process().
-// lsnr > threshold → speech detected → gate opens; lsnr <= threshold → gate closes.
-// DeepFilterNet returns -15.0 for near-silence and positive values for clear speech.
-const GATE_LSNR_THRESHOLD: f32 = -3.0; // dB; tune upward to gate more aggressively
-// lsnr is smoothed before threshold comparison to prevent chattering during sustained
-// sounds (e.g. singing) where lsnr fluctuates briefly around the threshold.
-const GATE_LSNR_SMOOTH_COEFF: f32 = 0.904_8; // exp(-1/10): ~100 ms smoothing (10 hops)
-const GATE_ATTACK_COEFF: f32 = 0.606_5; // exp(-1/2): ~20 ms to open (2 × 10 ms hops)
-const GATE_RELEASE_COEFF: f32 = 0.980_2; // exp(-1/50): ~500 ms time constant to close
-
 #[derive(Debug, Deserialize)]
 struct SidecarRequest {
     #[serde(default)]
@@ -130,15 +163,23 @@ struct SidecarError {
 }
 
 #[derive(Debug, Serialize)]
-struct SidecarEvent<'a> {
+struct SidecarEvent<'a, P = Value> {
     event: &'a str,
-    params: Value,
+    params: P,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 struct MicDevice {
     id: String,
     label: String,
+    is_default: bool,
+    /// Native channel count, when the backend reports it without opening the
+    /// device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channels: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<u32>,
 }
 
 #[cfg(windows)]
@@ -152,7 +193,7 @@ const PKEY_DEVICE_FRIENDLY_NAME: PROPERTYKEY = PROPERTYKEY {
     pid: 14,
 };
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct AudioTarget {
     id: String,
@@ -161,39 +202,56 @@ struct AudioTarget {
     process_name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ResolveSourceParams {
     source_id: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct ListTargetsParams {
     source_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ListMicDevicesParams {
+    /// Also list monitor sources (the playback side of every output device).
+    /// Linux only.
+    #[serde(default)]
+    include_monitors: bool,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct StartAudioCaptureParams {
     source_id: Option<String>,
+    /// A `pid:<pid>` id from `audio_targets.list`, or `system` for the
+    /// default output device mix (Linux only).
     app_audio_target_id: Option<String>,
+    /// With the `system` target, leave out the desktop app's own playback so
+    /// remote participants don't hear themselves.
+    #[serde(default)]
+    exclude_own_playback: bool,
+    /// Runs the captured audio through a true-peak limiter.
+    limiter: Option<LimiterOptions>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct StopAudioCaptureParams {
     session_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct SetPushKeybindsParams {
     push_to_talk_keybind: Option<String>,
     push_to_mute_keybind: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 enum VoiceFilterStrength {
     Low,
@@ -202,36 +260,81 @@ enum VoiceFilterStrength {
     Aggressive,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct StartVoiceFilterParams {
     sample_rate: usize,
     channels: usize,
     suppression_level: VoiceFilterStrength,
+    /// How a stereo session is denoised; `linked` by default.
+    channel_mode: Option<ChannelMode>,
+    /// DC blocker, 80 Hz high-pass and hum notch ahead of AGC.
+    pre_filter: Option<bool>,
     noise_suppression: Option<bool>,
     auto_gain_control: Option<bool>,
+    /// Block RMS by default; `loudness` targets the short-term loudness of
+    /// speech instead.
+    auto_gain_control_mode: Option<AutoGainControlMode>,
+    /// Adds a dereverb stage right after denoising, for rooms that make the
+    /// voice sound distant.
+    dereverb: Option<DereverbOptions>,
+    /// Adds a comfort noise stage after the gate, so a closed gate leaves
+    /// low-level noise rather than silence.
+    comfort_noise: Option<ComfortNoiseOptions>,
     echo_cancellation: Option<bool>,
+    /// Lets the session gate transmission itself; the renderer keeps
+    /// muting its track when omitted.
+    transmit: Option<TransmitOptions>,
+    /// Ordered processing stages. Replaces the chain implied by
+    /// `noiseSuppression`, `autoGainControl` and `echoCancellation`. Stages
+    /// and their `bypass` flags are fixed for the session; restart it to
+    /// change them. Only EQ bands can be updated live, through
+    /// `voice_filter.eq.set`.
+    chain: Option<Vec<StageDescriptor>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct StartVoiceFilterWithCaptureParams {
     sample_rate: usize,
     channels: usize,
     suppression_level: VoiceFilterStrength,
+    channel_mode: Option<ChannelMode>,
+    /// DC blocker, 80 Hz high-pass and hum notch ahead of AGC.
+    pre_filter: Option<bool>,
     noise_suppression: Option<bool>,
     auto_gain_control: Option<bool>,
+    auto_gain_control_mode: Option<AutoGainControlMode>,
+    dereverb: Option<DereverbOptions>,
+    comfort_noise: Option<ComfortNoiseOptions>,
     echo_cancellation: Option<bool>,
     device_id: Option<String>,
+    transmit: Option<TransmitOptions>,
+    /// Same as `voice_filter.start`; fixed for the session.
+    chain: Option<Vec<StageDescriptor>>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct StopVoiceFilterParams {
     session_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct SetVoiceFilterTransmitParams {
+    session_id: String,
+    /// `null` hands transmit control back to the renderer.
+    transmit: Option<TransmitOptions>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct VoiceFilterMetricsParams {
+    session_id: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct VoiceFilterPushFrameParams {
     session_id: String,
@@ -244,7 +347,7 @@ struct VoiceFilterPushFrameParams {
     encoding: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 struct VoiceFilterPushReferenceFrameParams {
     session_id: String,
@@ -257,27 +360,212 @@ struct VoiceFilterPushReferenceFrameParams {
     encoding: Option<String>,
 }
 
+/// Params of methods that take none.
+#[derive(Debug, Deserialize, JsonSchema)]
+struct EmptyParams {}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct HealthPingResult {
+    status: &'static str,
+    timestamp_ms: u128,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct CapabilitiesResult {
+    platform: &'static str,
+    per_app_audio: &'static str,
+    voice_filter: &'static str,
+    crash_report_dir: PathBuf,
+    protocol_version: u32,
+    encoding: &'static str,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ResolveSourceResult {
+    source_id: String,
+    pid: Option<u32>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct ListTargetsResult {
+    targets: Vec<AudioTarget>,
+    suggested_target_id: Option<String>,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct BinaryTransportInfoResult {
+    port: u16,
+    framing: &'static str,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct StartAudioCaptureResult {
+    session_id: String,
+    target_id: String,
+    sample_rate: u32,
+    channels: usize,
+    frames_per_buffer: usize,
+    /// Delay the limiter adds to the captured audio; 0 without one.
+    latency_ms: f32,
+    protocol_version: u32,
+    encoding: &'static str,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct StoppedResult {
+    stopped: bool,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct SetPushKeybindsResult {
+    talk_registered: bool,
+    mute_registered: bool,
+    errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct MicDevicesResult {
+    devices: Vec<MicDevice>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct StartVoiceFilterResult {
+    session_id: String,
+    sample_rate: usize,
+    channels: usize,
+    frames_per_buffer: usize,
+    /// Delay the chain's stages add to the signal: the sum of the
+    /// non-bypassed stages' lookahead and frame delays.
+    latency_ms: f32,
+    protocol_version: u32,
+    encoding: &'static str,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct SetVoiceFilterTransmitResult {
+    /// Always true while the renderer controls transmission.
+    transmitting: bool,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct VoiceFilterMetricsResult {
+    /// Stages of the chain that report metrics, in chain order.
+    stages: Vec<StageMetrics>,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct FrameAcceptedResult {
+    accepted: bool,
+    protocol_version: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct AudioCaptureFrameEvent {
+    session_id: String,
+    target_id: String,
+    sequence: u64,
+    sample_rate: usize,
+    channels: usize,
+    frame_count: usize,
+    pcm_base64: String,
+    protocol_version: u32,
+    encoding: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropped_frame_count: Option<u64>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct AudioCaptureEndedEvent {
+    session_id: String,
+    target_id: String,
+    reason: &'static str,
+    protocol_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct VoiceFilterFrameEvent {
+    session_id: String,
+    sequence: u64,
+    sample_rate: usize,
+    channels: usize,
+    frame_count: usize,
+    pcm_base64: String,
+    protocol_version: u32,
+    encoding: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dropped_frame_count: Option<u64>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct VoiceFilterEndedEvent {
+    session_id: String,
+    reason: String,
+    protocol_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct PushKeybindStateEvent {
+    kind: &'static str,
+    active: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct MicCaptureStatusEvent {
+    session_id: String,
+    raw_mode_enabled: bool,
+    raw_mode_status: String,
+}
+
 #[derive(Debug, Clone, Copy)]
 enum CaptureEndReason {
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     CaptureStopped,
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     AppExited,
     CaptureError,
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     DeviceLost,
+    InternalError,
 }
 
 impl CaptureEndReason {
     fn as_str(self) -> &'static str {
         match self {
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             Self::CaptureStopped => "capture_stopped",
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             Self::AppExited => "app_exited",
             Self::CaptureError => "capture_error",
-            #[cfg(windows)]
+            #[cfg(any(windows, target_os = "linux"))]
             Self::DeviceLost => "device_lost",
+            Self::InternalError => "internal_error",
         }
     }
 }
@@ -289,7 +577,7 @@ struct CaptureOutcome {
 }
 
 impl CaptureOutcome {
-    #[cfg(windows)]
+    #[cfg(any(windows, target_os = "linux"))]
     fn from_reason(reason: CaptureEndReason) -> Self {
         Self {
             reason,
@@ -303,23 +591,51 @@ impl CaptureOutcome {
             error: Some(error),
         }
     }
+
+    fn internal_error(panic: &crash::PanicReport) -> Self {
+        Self {
+            reason: CaptureEndReason::InternalError,
+            error: Some(panic.describe()),
+        }
+    }
+}
+
+/// What an app-audio capture session records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CaptureTarget {
+    /// `pid:<pid>`: one process and its children.
+    Process(u32),
+    /// `system`: everything playing on the default output device.
+    System { exclude_own_playback: bool },
+}
+
+impl CaptureTarget {
+    fn pid(self) -> Option<u32> {
+        match self {
+            Self::Process(pid) => Some(pid),
+            Self::System { .. } => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct CaptureSession {
     session_id: String,
+    target_id: String,
+    target: CaptureTarget,
+    limiter: Option<LimiterOptions>,
     stop_flag: Arc<AtomicBool>,
     handle: JoinHandle<()>,
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum PushKeybindKind {
     Talk,
     Mute,
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 impl PushKeybindKind {
     fn as_str(self) -> &'static str {
         match self {
@@ -329,22 +645,25 @@ impl PushKeybindKind {
     }
 }
 
-#[cfg(windows)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct WindowsPushKeybind {
-    key_code: i32,
-    ctrl: bool,
-    alt: bool,
-    shift: bool,
-    meta: bool,
-}
+/// Called with a keybind's index and new state whenever one of a watcher's
+/// keybinds is pressed or released. Keybinds still held when the watcher
+/// stops are reported as released.
+#[cfg(any(windows, target_os = "linux"))]
+type KeybindChangeHandler = Box<dyn FnMut(usize, bool) + Send>;
 
 #[derive(Debug)]
-struct PushKeybindWatcher {
+struct KeybindWatcher {
     stop_flag: Arc<AtomicBool>,
     handle: JoinHandle<()>,
 }
 
+impl KeybindWatcher {
+    fn stop(self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let _ = self.handle.join();
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct VoiceFilterConfig {
     post_filter_beta: f32,
@@ -354,55 +673,63 @@ struct VoiceFilterConfig {
     max_db_df_thresh: f32,
 }
 
-struct DeepFilterProcessor {
-    model: DfTract,
-    hop_size: usize,
-    input_buffers: Vec<VecDeque<f32>>,
-    output_buffers: Vec<VecDeque<f32>>,
-}
-
-// SAFETY: `DeepFilterProcessor` is never accessed concurrently. It is always
-// stored inside `SidecarState`, which is guarded by `Mutex<SidecarState>`.
-// This guarantees serialized access when the state is touched from different
-// threads (command loop and binary-ingress worker).
-unsafe impl Send for DeepFilterProcessor {}
-
-struct AutoGainControlState {
-    current_gain: f32,
-    post_pause_hold_blocks_remaining: u32,
+#[derive(Debug, Clone, Copy)]
+struct VoiceFilterSessionOptions {
+    sample_rate: usize,
+    channels: usize,
+    suppression_level: VoiceFilterStrength,
+    channel_mode: ChannelMode,
+    pre_filter: bool,
+    noise_suppression: bool,
+    auto_gain_control: bool,
+    auto_gain_control_mode: AutoGainControlMode,
+    dereverb: Option<DereverbOptions>,
+    comfort_noise: Option<ComfortNoiseOptions>,
+    echo_cancellation: bool,
 }
 
-enum VoiceFilterProcessor {
-    DeepFilter(DeepFilterProcessor),
-    Passthrough,
+/// Native microphone feeding a `voice_filter.start_with_capture` session.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+struct MicCaptureSource {
+    device_id: Option<String>,
 }
 
 struct VoiceFilterSession {
     session_id: String,
+    options: VoiceFilterSessionOptions,
+    mic_capture: Option<MicCaptureSource>,
     sample_rate: usize,
     channels: usize,
-    processor: VoiceFilterProcessor,
-    auto_gain_control: bool,
-    auto_gain_state: AutoGainControlState,
-    agc_startup_bypass_ms_remaining: u32,
-    echo_cancellation: bool,
+    chain: DspChain,
     echo_reference_interleaved: VecDeque<f32>,
-    limiter_gain: f32,
-    gate_gain: f32,
-    gate_lsnr_smooth: f32,
+    transmit_gate: Option<TransmitGate>,
 }
 
 impl VoiceFilterSession {
+    fn new(session_id: String, options: VoiceFilterSessionOptions, chain: DspChain) -> Self {
+        Self {
+            session_id,
+            options,
+            mic_capture: None,
+            sample_rate: options.sample_rate,
+            channels: options.channels,
+            chain,
+            echo_reference_interleaved: VecDeque::new(),
+            transmit_gate: None,
+        }
+    }
+
     fn push_echo_reference_samples(
         &mut self,
         input_samples: &[f32],
         input_channels: usize,
     ) -> Result<(), String> {
-        if input_channels == 0 || input_channels > 2 {
+        if input_channels == 0 || input_channels > MAX_REFERENCE_CHANNELS {
             return Err("Unsupported reference frame channel count".to_string());
         }
 
-        if self.channels == 0 || self.channels > 2 {
+        if self.channels == 0 {
             return Err("Unsupported voice filter session channel count".to_string());
         }
 
@@ -423,28 +750,15 @@ impl VoiceFilterSession {
             return Ok(());
         }
 
-        for frame_index in 0..input_frame_count {
-            match (input_channels, self.channels) {
-                (1, 1) | (2, 2) => {
-                    for channel_index in 0..self.channels {
-                        let sample = input_samples[frame_index * input_channels + channel_index];
-                        self.echo_reference_interleaved.push_back(sample);
-                    }
-                }
-                (1, 2) => {
-                    let sample = input_samples[frame_index];
-                    self.echo_reference_interleaved.push_back(sample);
-                    self.echo_reference_interleaved.push_back(sample);
-                }
-                (2, 1) => {
-                    let left = input_samples[frame_index * 2];
-                    let right = input_samples[frame_index * 2 + 1];
-                    self.echo_reference_interleaved
-                        .push_back((left + right) * 0.5);
-                }
-                _ => {
-                    return Err("Unsupported reference channel conversion".to_string());
-                }
+        for frame in input_samples.chunks_exact(input_channels) {
+            if input_channels == self.channels {
+                self.echo_reference_interleaved.extend(frame);
+            } else {
+                // Differing layouts meet in mono: the downmix goes to every
+                // session channel.
+                let mono = frame.iter().sum::<f32>() / input_channels as f32;
+                self.echo_reference_interleaved
+                    .extend(std::iter::repeat_n(mono, self.channels));
             }
         }
 
@@ -512,7 +826,11 @@ struct VoiceFilterBinaryFrame {
 struct SidecarState {
     capture_session: Option<CaptureSession>,
     voice_filter_session: Option<VoiceFilterSession>,
-    push_keybind_watcher: Option<PushKeybindWatcher>,
+    push_keybinds: SetPushKeybindsParams,
+    push_keybind_watcher: Option<KeybindWatcher>,
+    push_keybind_states: Arc<PushKeybindStates>,
+    hotkeys: hotkeys::SetHotkeysParams,
+    hotkey_registration: Option<hotkeys::HotkeyRegistration>,
     mic_capture_stop_flag: Option<Arc<AtomicBool>>,
 }
 
@@ -634,11 +952,22 @@ fn write_response(stdout: &Arc<Mutex<io::Stdout>>, id: &str, result: Result<Valu
     }
 }
 
-fn write_event(stdout: &Arc<Mutex<io::Stdout>>, event: &str, params: Value) {
+fn write_event<P: Serialize>(stdout: &Arc<Mutex<io::Stdout>>, event: &str, params: P) {
     let envelope = SidecarEvent { event, params };
     write_json_line(stdout, &envelope);
 }
 
+/// Queues an event behind the frame writer so it stays ordered with frames.
+fn enqueue_event<P: Serialize>(queue: &Arc<FrameQueue>, event: &str, params: P) {
+    if let Ok(serialized) = serde_json::to_string(&SidecarEvent { event, params }) {
+        queue.push_line(serialized);
+    }
+}
+
+fn result_value<T: Serialize>(result: T) -> Result<Value, String> {
+    serde_json::to_value(result).map_err(|error| format!("failed to serialize result: {error}"))
+}
+
 fn start_frame_writer(stdout: Arc<Mutex<io::Stdout>>, queue: Arc<FrameQueue>) -> JoinHandle<()> {
     thread::spawn(move || {
         while let Some(line) = queue.pop_line() {
@@ -653,7 +982,7 @@ fn start_frame_writer(stdout: Arc<Mutex<io::Stdout>>, queue: Arc<FrameQueue>) ->
     })
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 fn enqueue_frame_event(
     queue: &Arc<FrameQueue>,
     session_id: &str,
@@ -665,31 +994,70 @@ fn enqueue_frame_event(
 ) {
     let dropped_count = queue.take_dropped_count();
 
-    let mut params = json!({
-        "sessionId": session_id,
-        "targetId": target_id,
-        "sequence": sequence,
-        "sampleRate": sample_rate,
-        "channels": TARGET_CHANNELS,
-        "frameCount": frame_count,
-        "pcmBase64": pcm_base64,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    });
-
-    if dropped_count > 0 {
-        params["droppedFrameCount"] = json!(dropped_count);
-    }
+    enqueue_event(
+        queue,
+        "audio_capture.frame",
+        AudioCaptureFrameEvent {
+            session_id: session_id.to_string(),
+            target_id: target_id.to_string(),
+            sequence,
+            sample_rate,
+            channels: TARGET_CHANNELS,
+            frame_count,
+            pcm_base64,
+            protocol_version: PROTOCOL_VERSION,
+            encoding: PCM_ENCODING,
+            dropped_frame_count: (dropped_count > 0).then_some(dropped_count),
+        },
+    );
+}
 
-    if let Ok(serialized) = serde_json::to_string(&SidecarEvent {
-        event: "audio_capture.frame",
-        params,
-    }) {
-        queue.push_line(serialized);
+/// Sends one captured frame over the binary egress socket when it is
+/// connected, falling back to an `audio_capture.frame` event.
+#[cfg(any(windows, target_os = "linux"))]
+fn emit_app_audio_frame(
+    frame_queue: &Arc<FrameQueue>,
+    app_audio_binary_stream: Option<&Arc<Mutex<Option<TcpStream>>>>,
+    session_id: &str,
+    target_id: &str,
+    sequence: u64,
+    frame_samples: &[f32],
+) {
+    let wrote_binary = app_audio_binary_stream
+        .map(|stream_slot| {
+            try_write_app_audio_binary_frame(
+                stream_slot,
+                session_id,
+                target_id,
+                sequence,
+                TARGET_SAMPLE_RATE as usize,
+                TARGET_CHANNELS,
+                FRAME_SIZE,
+                PROTOCOL_VERSION,
+                0,
+                frame_samples,
+            )
+        })
+        .unwrap_or(false);
+
+    if !wrote_binary {
+        let frame_bytes = bytemuck::cast_slice(frame_samples);
+        let pcm_base64 = BASE64.encode(frame_bytes);
+
+        enqueue_frame_event(
+            frame_queue,
+            session_id,
+            target_id,
+            sequence,
+            TARGET_SAMPLE_RATE as usize,
+            FRAME_SIZE,
+            pcm_base64,
+        );
     }
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
+#[allow(clippy::too_many_arguments)]
 fn try_write_app_audio_binary_frame(
     stream_slot: &Arc<Mutex<Option<TcpStream>>>,
     session_id: &str,
@@ -720,7 +1088,7 @@ fn try_write_app_audio_binary_frame(
     if frame_count == 0 || frame_count > u32::MAX as usize {
         return false;
     }
-    if frame_samples.is_empty() || frame_samples.len() % channels != 0 {
+    if frame_samples.is_empty() || !frame_samples.len().is_multiple_of(channels) {
         return false;
     }
 
@@ -774,7 +1142,11 @@ fn try_write_app_audio_binary_frame(
     match stream.write_all(&packet) {
         Ok(()) => true,
         Err(error) => {
-            eprintln!("[capture-sidecar] app-audio binary egress write failed: {error}");
+            log_warn!(
+                "capture.binary_egress",
+                "app-audio binary egress write failed",
+                "error" => error.to_string(),
+            );
             *lock = None;
             false
         }
@@ -792,27 +1164,21 @@ fn enqueue_voice_filter_frame_event(
 ) {
     let dropped_count = queue.take_dropped_count();
 
-    let mut params = json!({
-        "sessionId": session_id,
-        "sequence": sequence,
-        "sampleRate": sample_rate,
-        "channels": channels,
-        "frameCount": frame_count,
-        "pcmBase64": pcm_base64,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    });
-
-    if dropped_count > 0 {
-        params["droppedFrameCount"] = json!(dropped_count);
-    }
-
-    if let Ok(serialized) = serde_json::to_string(&SidecarEvent {
-        event: "voice_filter.frame",
-        params,
-    }) {
-        queue.push_line(serialized);
-    }
+    enqueue_event(
+        queue,
+        "voice_filter.frame",
+        VoiceFilterFrameEvent {
+            session_id: session_id.to_string(),
+            sequence,
+            sample_rate,
+            channels,
+            frame_count,
+            pcm_base64,
+            protocol_version: PROTOCOL_VERSION,
+            encoding: PCM_ENCODING,
+            dropped_frame_count: (dropped_count > 0).then_some(dropped_count),
+        },
+    );
 }
 
 fn enqueue_voice_filter_ended_event(
@@ -821,37 +1187,39 @@ fn enqueue_voice_filter_ended_event(
     reason: &str,
     error: Option<String>,
 ) {
-    let mut params = json!({
-        "sessionId": session_id,
-        "reason": reason,
-        "protocolVersion": PROTOCOL_VERSION,
-    });
-
-    if let Some(message) = error {
-        params["error"] = json!(message);
-    }
+    enqueue_event(
+        queue,
+        "voice_filter.ended",
+        VoiceFilterEndedEvent {
+            session_id: session_id.to_string(),
+            reason: reason.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            error,
+        },
+    );
+}
 
-    if let Ok(serialized) = serde_json::to_string(&SidecarEvent {
-        event: "voice_filter.ended",
-        params,
-    }) {
-        queue.push_line(serialized);
-    }
+fn enqueue_transmit_state_event(queue: &Arc<FrameQueue>, session_id: &str, transmitting: bool) {
+    enqueue_event(
+        queue,
+        "voice_filter.transmit_state",
+        TransmitStateEvent {
+            session_id,
+            transmitting,
+        },
+    );
 }
 
-#[cfg(windows)]
+#[cfg(any(windows, target_os = "linux"))]
 fn enqueue_push_keybind_state_event(queue: &Arc<FrameQueue>, kind: PushKeybindKind, active: bool) {
-    let params = json!({
-        "kind": kind.as_str(),
-        "active": active,
-    });
-
-    if let Ok(serialized) = serde_json::to_string(&SidecarEvent {
-        event: "push_keybind.state",
-        params,
-    }) {
-        queue.push_line(serialized);
-    }
+    enqueue_event(
+        queue,
+        "push_keybind.state",
+        PushKeybindStateEvent {
+            kind: kind.as_str(),
+            active,
+        },
+    );
 }
 
 fn voice_filter_config(strength: VoiceFilterStrength) -> VoiceFilterConfig {
@@ -887,96 +1255,111 @@ fn voice_filter_config(strength: VoiceFilterStrength) -> VoiceFilterConfig {
     }
 }
 
+/// How many models to build, the channel count of each and how each combines
+/// its per-channel masks. `independent` runs a single-channel model per
+/// channel, so no channel's mask depends on another's.
+fn deep_filter_layout(channels: usize, channel_mode: ChannelMode) -> (usize, usize, ReduceMask) {
+    match channel_mode {
+        _ if channels == 1 => (1, 1, ReduceMask::NONE),
+        ChannelMode::Linked => (1, channels, ReduceMask::MEAN),
+        ChannelMode::LinkedMax => (1, channels, ReduceMask::MAX),
+        ChannelMode::Independent => (channels, 1, ReduceMask::NONE),
+        ChannelMode::Downmix => (1, 1, ReduceMask::NONE),
+    }
+}
+
 fn create_deep_filter_processor(
     channels: usize,
+    channel_mode: ChannelMode,
     suppression_level: VoiceFilterStrength,
+    request: &RequestContext,
 ) -> Result<DeepFilterProcessor, String> {
     let config = voice_filter_config(suppression_level);
+    let (model_count, channels, reduce_mask) = deep_filter_layout(channels, channel_mode);
+    let warmup_blocks = DEEP_FILTER_WARMUP_BLOCKS * model_count;
+    let mut models = Vec::with_capacity(model_count);
+
+    for model_index in 0..model_count {
+        let runtime_params = RuntimeParams::default_with_ch(channels)
+            .with_mask_reduce(reduce_mask)
+            .with_post_filter(config.post_filter_beta)
+            .with_atten_lim(config.atten_lim_db)
+            .with_thresholds(
+                config.min_db_thresh,
+                config.max_db_erb_thresh,
+                config.max_db_df_thresh,
+            );
 
-    let reduce_mask = if channels > 1 {
-        ReduceMask::MEAN
-    } else {
-        ReduceMask::NONE
-    };
-
-    let runtime_params = RuntimeParams::default_with_ch(channels)
-        .with_mask_reduce(reduce_mask)
-        .with_post_filter(config.post_filter_beta)
-        .with_atten_lim(config.atten_lim_db)
-        .with_thresholds(
-            config.min_db_thresh,
-            config.max_db_erb_thresh,
-            config.max_db_df_thresh,
-        );
-
-    let df_params = DfParams::default();
-    let mut model = DfTract::new(df_params, &runtime_params)
-        .map_err(|error| format!("Failed to initialize DeepFilterNet runtime: {error}"))?;
-    let hop_size = model.hop_size;
+        request.report_progress("model_load", model_index, model_count);
+        let df_params = DfParams::default();
+        let mut model = DfTract::new(df_params, &runtime_params)
+            .map_err(|error| format!("Failed to initialize DeepFilterNet runtime: {error}"))?;
+        let hop_size = model.hop_size;
+        request.report_progress("model_load", model_index + 1, model_count);
+        request.check_cancelled()?;
 
-    // Warm the model upfront so first live frames don't pay cold-start inference cost.
-    if DEEP_FILTER_WARMUP_BLOCKS > 0 {
+        // Warm the model upfront so first live frames don't pay cold-start inference cost.
         let noisy = Array2::<f32>::zeros((channels, hop_size));
         let mut enhanced = Array2::<f32>::zeros((channels, hop_size));
-        for _ in 0..DEEP_FILTER_WARMUP_BLOCKS {
+        for block_index in 0..DEEP_FILTER_WARMUP_BLOCKS {
+            request.check_cancelled()?;
             model
                 .process(noisy.view(), enhanced.view_mut())
                 .map_err(|error| format!("Failed to warm DeepFilterNet runtime: {error}"))?;
             enhanced.fill(0.0);
+
+            let warmed = model_index * DEEP_FILTER_WARMUP_BLOCKS + block_index + 1;
+            if warmed % DEEP_FILTER_WARMUP_PROGRESS_INTERVAL == 0 {
+                request.report_progress("warmup", warmed, warmup_blocks);
+            }
         }
+
+        models.push(model);
     }
 
-    Ok(DeepFilterProcessor {
-        model,
-        hop_size,
-        input_buffers: (0..channels).map(|_| VecDeque::new()).collect(),
-        output_buffers: (0..channels).map(|_| VecDeque::new()).collect(),
-    })
+    Ok(DeepFilterProcessor::new(models, channels))
 }
 
 fn create_voice_filter_session(
     session_id: String,
-    sample_rate: usize,
-    channels: usize,
-    suppression_level: VoiceFilterStrength,
-    noise_suppression: bool,
-    auto_gain_control: bool,
-    echo_cancellation: bool,
+    options: VoiceFilterSessionOptions,
+    chain: Option<Vec<StageDescriptor>>,
+    request: &RequestContext,
 ) -> Result<VoiceFilterSession, String> {
-    if sample_rate != TARGET_SAMPLE_RATE as usize {
+    if options.sample_rate != TARGET_SAMPLE_RATE as usize {
         return Err("DeepFilterNet currently requires 48kHz input".to_string());
     }
 
-    if channels == 0 {
+    if options.channels == 0 {
         return Err("Unsupported voice filter channel count".to_string());
     }
 
-    let processor = if noise_suppression {
-        VoiceFilterProcessor::DeepFilter(create_deep_filter_processor(
-            channels,
-            suppression_level,
+    let chain = chain.unwrap_or_else(|| {
+        dsp::default_chain(
+            options.pre_filter,
+            options.noise_suppression,
+            options.auto_gain_control,
+            options.auto_gain_control_mode,
+            options.dereverb,
+            options.comfort_noise,
+            options.echo_cancellation,
+        )
+    });
+    dsp::validate_chain(&chain)?;
+
+    let denoiser = if dsp::uses_denoiser(&chain) {
+        Some(create_deep_filter_processor(
+            options.channels,
+            options.channel_mode,
+            options.suppression_level,
+            request,
         )?)
     } else {
-        VoiceFilterProcessor::Passthrough
+        None
     };
-
-    Ok(VoiceFilterSession {
-        session_id,
-        sample_rate,
-        channels,
-        processor,
-        auto_gain_control,
-        auto_gain_state: AutoGainControlState {
-            current_gain: 1.0,
-            post_pause_hold_blocks_remaining: 0,
-        },
-        agc_startup_bypass_ms_remaining: AGC_STARTUP_BYPASS_MS,
-        echo_cancellation,
-        echo_reference_interleaved: VecDeque::new(),
-        limiter_gain: 1.0,
-        gate_gain: 0.0,
-        gate_lsnr_smooth: -15.0,
-    })
+
+    let chain = DspChain::new(&chain, options.sample_rate, options.channels, denoiser);
+    Ok(VoiceFilterSession::new(session_id, options, chain))
 }
 
 fn decode_f32le_base64(pcm_base64: &str) -> Result<Vec<f32>, String> {
@@ -997,114 +1380,6 @@ fn decode_f32le_base64(pcm_base64: &str) -> Result<Vec<f32>, String> {
     Ok(samples)
 }
 
-const AGC_TARGET_RMS: f32 = 0.12;
-const AGC_MIN_RMS: f32 = 0.0005;
-const AGC_MIN_GAIN: f32 = 0.5;
-const AGC_MAX_GAIN: f32 = 3.0;
-const AGC_ATTACK_SMOOTHING: f32 = 0.3;
-const AGC_RELEASE_SMOOTHING: f32 = 0.08;
-const AGC_LIMITER: f32 = 0.98;
-const AGC_PAUSE_RMS_THRESHOLD: f32 = 0.006;
-const AGC_PAUSE_RECOVERY_SMOOTHING: f32 = 0.3;
-const AGC_POST_PAUSE_HOLD_BLOCKS: u32 = 20;
-const AGC_STARTUP_BYPASS_MS: u32 = 1_500;
-
-fn apply_auto_gain_control(samples: &mut [f32], state: &mut AutoGainControlState) {
-    if samples.is_empty() {
-        return;
-    }
-
-    let mut sum_squares = 0.0_f64;
-    for sample in samples.iter() {
-        let sample_f64 = f64::from(*sample);
-        sum_squares += sample_f64 * sample_f64;
-    }
-
-    let rms = (sum_squares / samples.len() as f64).sqrt() as f32;
-    let (desired_gain, smoothing) = if rms <= AGC_PAUSE_RMS_THRESHOLD {
-        state.post_pause_hold_blocks_remaining = AGC_POST_PAUSE_HOLD_BLOCKS;
-        // Avoid AGC ramp-up during pauses; it over-amplifies the next phrase onset.
-        (1.0, AGC_PAUSE_RECOVERY_SMOOTHING)
-    } else if state.post_pause_hold_blocks_remaining > 0 {
-        state.post_pause_hold_blocks_remaining =
-            state.post_pause_hold_blocks_remaining.saturating_sub(1);
-        // Hold AGC at unity briefly after silence so phrase onsets stay natural.
-        (1.0, AGC_PAUSE_RECOVERY_SMOOTHING)
-    } else {
-        let desired_gain = if rms <= AGC_MIN_RMS {
-            AGC_MAX_GAIN
-        } else {
-            (AGC_TARGET_RMS / rms).clamp(AGC_MIN_GAIN, AGC_MAX_GAIN)
-        };
-
-        let smoothing = if desired_gain < state.current_gain {
-            AGC_ATTACK_SMOOTHING
-        } else {
-            AGC_RELEASE_SMOOTHING
-        };
-
-        (desired_gain, smoothing)
-    };
-    state.current_gain = state.current_gain * (1.0 - smoothing) + desired_gain * smoothing;
-
-    for sample in samples.iter_mut() {
-        *sample = (*sample * state.current_gain).clamp(-AGC_LIMITER, AGC_LIMITER);
-    }
-}
-
-fn apply_reference_echo_cancellation(session: &VoiceFilterSession, samples: &mut [f32]) {
-    if samples.is_empty() {
-        return;
-    }
-
-    let Some(reference_samples) = session.get_echo_reference_window(samples.len()) else {
-        return;
-    };
-
-    let mut near_energy = 0.0_f64;
-    let mut reference_energy = 0.0_f64;
-    let mut near_reference_dot = 0.0_f64;
-
-    for index in 0..samples.len() {
-        let near = f64::from(samples[index]);
-        let reference = f64::from(reference_samples[index]);
-        near_energy += near * near;
-        reference_energy += reference * reference;
-        near_reference_dot += near * reference;
-    }
-
-    if reference_energy <= f64::from(ECHO_REFERENCE_MIN_ENERGY) {
-        return;
-    }
-
-    let near_rms = (near_energy / samples.len() as f64).sqrt() as f32;
-    let reference_rms = (reference_energy / samples.len() as f64).sqrt() as f32;
-
-    let mut subtraction_gain =
-        (near_reference_dot / reference_energy).clamp(0.0, f64::from(ECHO_SUBTRACTION_MAX)) as f32;
-    let near_dominance = near_rms / (reference_rms + 1e-6);
-    if near_dominance > 1.35 {
-        subtraction_gain *= 0.5;
-    } else if near_dominance > 1.1 {
-        subtraction_gain *= 0.75;
-    }
-
-    let ducking_gain = if reference_rms > near_rms * 0.8 {
-        ECHO_DUCKING_MIN_GAIN
-    } else if reference_rms > near_rms * 0.55 {
-        0.72
-    } else if reference_rms > near_rms * 0.35 {
-        0.86
-    } else {
-        1.0
-    };
-
-    for index in 0..samples.len() {
-        let canceled = samples[index] - reference_samples[index] * subtraction_gain;
-        samples[index] = canceled * ducking_gain;
-    }
-}
-
 fn process_voice_filter_frame(
     session: &mut VoiceFilterSession,
     samples: &mut [f32],
@@ -1124,336 +1399,113 @@ fn process_voice_filter_frame(
         return Err("Voice filter frame sample count mismatch".to_string());
     }
 
-    // AGC runs before DeepFilterNet so the model receives a level-normalised signal
-    if session.auto_gain_control {
-        if session.agc_startup_bypass_ms_remaining > 0 {
-            let input_ms = if session.sample_rate > 0 {
-                ((frame_count.saturating_mul(1000)) / session.sample_rate) as u32
-            } else {
-                0
-            }
-            .max(1);
-
-            session.agc_startup_bypass_ms_remaining = session
-                .agc_startup_bypass_ms_remaining
-                .saturating_sub(input_ms);
-
-            // Hold gain at unity during startup — pass samples through unmodified
-            // so raw mic audio isn't hard-clipped before DeepFilterNet warms up.
-            session.auto_gain_state.current_gain = 1.0;
-        } else {
-            apply_auto_gain_control(samples, &mut session.auto_gain_state);
-        }
-    }
-
-    // Pull gate state out before the match to avoid a partial-borrow conflict
-    // with session.processor.  Written back after the match.
-    let mut gate_gain = session.gate_gain;
-    let mut gate_lsnr_smooth = session.gate_lsnr_smooth;
-
-    match &mut session.processor {
-        VoiceFilterProcessor::DeepFilter(processor) => {
-            let hop_size = processor.hop_size;
-
-            for frame_index in 0..frame_count {
-                for channel_index in 0..channels {
-                    let sample = samples[frame_index * channels + channel_index];
-                    processor.input_buffers[channel_index].push_back(sample);
-                }
-            }
-
-            while processor
-                .input_buffers
-                .iter()
-                .all(|buffer| buffer.len() >= hop_size)
-            {
-                let mut noisy = Array2::<f32>::zeros((channels, hop_size));
-                let mut enhanced = Array2::<f32>::zeros((channels, hop_size));
-
-                for channel_index in 0..channels {
-                    for sample_index in 0..hop_size {
-                        noisy[(channel_index, sample_index)] = processor.input_buffers
-                            [channel_index]
-                            .pop_front()
-                            .unwrap_or(0.0);
-                    }
-                }
-
-                let lsnr = processor
-                    .model
-                    .process(noisy.view(), enhanced.view_mut())
-                    .map_err(|error| format!("DeepFilterNet processing failed: {error}"))?;
-
-                // Smooth lsnr before thresholding so brief fluctuations during
-                // sustained sounds (e.g. singing a held note) don't cause chattering.
-                gate_lsnr_smooth = gate_lsnr_smooth * GATE_LSNR_SMOOTH_COEFF
-                    + lsnr * (1.0 - GATE_LSNR_SMOOTH_COEFF);
-
-                // Noise gate: move gate gain toward open (1.0) when speech is detected,
-                // or toward closed (0.0) otherwise.  Attack is fast (~20 ms) so the
-                // start of speech isn't clipped; release is slow (~500 ms) so word
-                // endings and sustained tones trail off naturally.
-                let target_gain = if gate_lsnr_smooth > GATE_LSNR_THRESHOLD { 1.0_f32 } else { 0.0_f32 };
-                if target_gain > gate_gain {
-                    gate_gain = gate_gain * GATE_ATTACK_COEFF
-                        + target_gain * (1.0 - GATE_ATTACK_COEFF);
-                } else {
-                    gate_gain = gate_gain * GATE_RELEASE_COEFF
-                        + target_gain * (1.0 - GATE_RELEASE_COEFF);
-                }
-
-                for channel_index in 0..channels {
-                    for sample_index in 0..hop_size {
-                        processor.output_buffers[channel_index]
-                            .push_back(enhanced[(channel_index, sample_index)] * gate_gain);
-                    }
-                }
-            }
-
-            for frame_index in 0..frame_count {
-                for channel_index in 0..channels {
-                    let index = frame_index * channels + channel_index;
-                    if let Some(filtered_sample) =
-                        processor.output_buffers[channel_index].pop_front()
-                    {
-                        samples[index] = filtered_sample;
-                    }
-                }
-            }
-        }
-        VoiceFilterProcessor::Passthrough => {}
-    }
-
-    session.gate_gain = gate_gain;
-    session.gate_lsnr_smooth = gate_lsnr_smooth;
-
-    if session.echo_cancellation {
-        apply_reference_echo_cancellation(session, samples);
-    }
+    let echo_reference = if session.chain.uses_echo_reference() {
+        session.get_echo_reference_window(samples.len())
+    } else {
+        None
+    };
 
-    Ok(())
+    session
+        .chain
+        .process(samples, channels, echo_reference.as_deref())
 }
 
-fn voice_filter_frames_per_buffer(session: &VoiceFilterSession) -> usize {
-    match &session.processor {
-        VoiceFilterProcessor::DeepFilter(processor) => processor.hop_size,
-        VoiceFilterProcessor::Passthrough => FRAME_SIZE,
-    }
+fn frames_to_ms(frames: usize, sample_rate: usize) -> f32 {
+    (frames * 1_000) as f32 / sample_rate.max(1) as f32
 }
 
-#[cfg(windows)]
-const VK_LSHIFT: i32 = 0xA0;
-#[cfg(windows)]
-const VK_RSHIFT: i32 = 0xA1;
-#[cfg(windows)]
-const VK_LCONTROL: i32 = 0xA2;
-#[cfg(windows)]
-const VK_RCONTROL: i32 = 0xA3;
-#[cfg(windows)]
-const VK_LMENU: i32 = 0xA4;
-#[cfg(windows)]
-const VK_RMENU: i32 = 0xA5;
-#[cfg(windows)]
-const VK_LWIN: i32 = 0x5B;
-#[cfg(windows)]
-const VK_RWIN: i32 = 0x5C;
-
-#[cfg(windows)]
-fn map_key_code_to_virtual_key(key_code: &str) -> Option<i32> {
-    if key_code.starts_with("Key") && key_code.len() == 4 {
-        let key = key_code.chars().nth(3)?;
-        if key.is_ascii_alphabetic() {
-            return Some(key.to_ascii_uppercase() as i32);
-        }
-    }
-
-    if key_code.starts_with("Digit") && key_code.len() == 6 {
-        let key = key_code.chars().nth(5)?;
-        if key.is_ascii_digit() {
-            return Some(key as i32);
-        }
-    }
-
-    if let Some(function_key) = key_code.strip_prefix('F') {
-        if let Ok(function_number) = function_key.parse::<i32>() {
-            if (1..=24).contains(&function_number) {
-                return Some(0x6F + function_number);
-            }
-        }
-    }
-
-    if let Some(numpad_key) = key_code.strip_prefix("Numpad") {
-        if numpad_key.len() == 1 {
-            let key = numpad_key.chars().next()?;
-            if key.is_ascii_digit() {
-                return Some(0x60 + (key as i32 - '0' as i32));
-            }
-        }
-    }
-
-    match key_code {
-        "Space" => Some(0x20),
-        "Enter" => Some(0x0D),
-        "Escape" => Some(0x1B),
-        "Backspace" => Some(0x08),
-        "Tab" => Some(0x09),
-        "CapsLock" => Some(0x14),
-        "NumLock" => Some(0x90),
-        "ScrollLock" => Some(0x91),
-        "ArrowUp" => Some(0x26),
-        "ArrowDown" => Some(0x28),
-        "ArrowLeft" => Some(0x25),
-        "ArrowRight" => Some(0x27),
-        "Delete" => Some(0x2E),
-        "Insert" => Some(0x2D),
-        "Home" => Some(0x24),
-        "End" => Some(0x23),
-        "PageUp" => Some(0x21),
-        "PageDown" => Some(0x22),
-        "Minus" => Some(0xBD),
-        "Equal" => Some(0xBB),
-        "BracketLeft" => Some(0xDB),
-        "BracketRight" => Some(0xDD),
-        "Backslash" => Some(0xDC),
-        "Semicolon" => Some(0xBA),
-        "Quote" => Some(0xDE),
-        "Comma" => Some(0xBC),
-        "Period" => Some(0xBE),
-        "Slash" => Some(0xBF),
-        "Backquote" => Some(0xC0),
-        "NumpadMultiply" => Some(0x6A),
-        "NumpadAdd" => Some(0x6B),
-        "NumpadSubtract" => Some(0x6D),
-        "NumpadDecimal" => Some(0x6E),
-        "NumpadDivide" => Some(0x6F),
-        "NumpadEnter" => Some(0x0D),
-        _ => None,
-    }
+fn voice_filter_frames_per_buffer(session: &VoiceFilterSession) -> usize {
+    session.chain.hop_size().unwrap_or(FRAME_SIZE)
 }
 
 #[cfg(windows)]
-fn parse_push_keybind(keybind: Option<&str>) -> Result<Option<WindowsPushKeybind>, String> {
-    let Some(keybind) = keybind else {
-        return Ok(None);
-    };
-
-    if keybind.trim().is_empty() {
-        return Ok(None);
-    }
-
-    let tokens: Vec<&str> = keybind
-        .split('+')
-        .map(|token| token.trim())
-        .filter(|token| !token.is_empty())
-        .collect();
-
-    if tokens.is_empty() {
-        return Ok(None);
-    }
-
-    let mut ctrl = false;
-    let mut alt = false;
-    let mut shift = false;
-    let mut meta = false;
-    let mut key_code_token: Option<&str> = None;
-
-    for token in tokens {
-        match token {
-            "Control" | "Ctrl" => {
-                ctrl = true;
-            }
-            "Alt" => {
-                alt = true;
-            }
-            "Shift" => {
-                shift = true;
-            }
-            "Meta" | "Command" => {
-                meta = true;
-            }
-            _ => {
-                if key_code_token.is_some() {
-                    return Err("Invalid keybind format.".to_string());
-                }
+fn parse_global_keybind(keybind: Option<&str>) -> Result<Option<Keybind>, String> {
+    let keybind = Keybind::parse_optional(keybind)?;
 
-                key_code_token = Some(token);
-            }
-        }
+    if keybind.is_some_and(|keybind| {
+        matches!(keybind.trigger, Trigger::Mouse(button) if button.windows_virtual_key().is_none())
+    }) {
+        return Err("Unsupported mouse button for global keybind monitoring.".to_string());
     }
 
-    let key_code_name = key_code_token.ok_or_else(|| "Missing key code in keybind.".to_string())?;
-    let key_code = map_key_code_to_virtual_key(key_code_name)
-        .ok_or_else(|| "Unsupported key for global keybind monitoring.".to_string())?;
-
-    Ok(Some(WindowsPushKeybind {
-        key_code,
-        ctrl,
-        alt,
-        shift,
-        meta,
-    }))
+    Ok(keybind)
 }
 
 #[cfg(windows)]
-fn is_virtual_key_down(key_code: i32) -> bool {
-    (unsafe { GetAsyncKeyState(key_code) } as u16 & 0x8000) != 0
+fn is_virtual_key_down(key_code: u16) -> bool {
+    (unsafe { GetAsyncKeyState(i32::from(key_code)) } as u16 & 0x8000) != 0
 }
 
 #[cfg(windows)]
-fn current_modifiers_match(keybind: &WindowsPushKeybind) -> bool {
-    let ctrl = is_virtual_key_down(VK_LCONTROL) || is_virtual_key_down(VK_RCONTROL);
-    let alt = is_virtual_key_down(VK_LMENU) || is_virtual_key_down(VK_RMENU);
-    let shift = is_virtual_key_down(VK_LSHIFT) || is_virtual_key_down(VK_RSHIFT);
-    let meta = is_virtual_key_down(VK_LWIN) || is_virtual_key_down(VK_RWIN);
-
-    ctrl == keybind.ctrl && alt == keybind.alt && shift == keybind.shift && meta == keybind.meta
+fn is_keybind_active(keybind: &Keybind) -> bool {
+    keybind.is_active(
+        |key| is_virtual_key_down(key.windows_virtual_key()),
+        |button| {
+            button
+                .windows_virtual_key()
+                .is_some_and(is_virtual_key_down)
+        },
+    )
 }
 
+/// Virtual keys of every key and mouse button a keybind can name.
 #[cfg(windows)]
-fn is_push_keybind_active(keybind: &WindowsPushKeybind) -> bool {
-    is_virtual_key_down(keybind.key_code) && current_modifiers_match(keybind)
+fn input_virtual_keys() -> Vec<u16> {
+    Key::ALL
+        .iter()
+        .map(|key| key.windows_virtual_key())
+        .chain(
+            (1..=MouseButton::MAX)
+                .filter_map(|number| MouseButton::new(number)?.windows_virtual_key()),
+        )
+        .collect()
 }
 
 #[cfg(windows)]
-fn start_push_keybind_watcher(
-    frame_queue: Arc<FrameQueue>,
-    talk_keybind: Option<WindowsPushKeybind>,
-    mute_keybind: Option<WindowsPushKeybind>,
-) -> PushKeybindWatcher {
+fn start_keybind_watcher(
+    keybinds: Vec<Keybind>,
+    mut on_change: KeybindChangeHandler,
+) -> Result<KeybindWatcher, String> {
     let stop_flag = Arc::new(AtomicBool::new(false));
     let thread_stop_flag = Arc::clone(&stop_flag);
 
     let handle = thread::spawn(move || {
-        let mut talk_active = false;
-        let mut mute_active = false;
+        let mut active = vec![false; keybinds.len()];
+        // Every press is a hint for the transient suppressor, bound or not,
+        // but polling every key is only worth it while one is listening.
+        let input_keys = input_virtual_keys();
+        let mut input_down = vec![false; input_keys.len()];
 
         while !thread_stop_flag.load(Ordering::Relaxed) {
-            let next_talk_active = talk_keybind.as_ref().is_some_and(is_push_keybind_active);
-            let next_mute_active = mute_keybind.as_ref().is_some_and(is_push_keybind_active);
+            for (index, keybind) in keybinds.iter().enumerate() {
+                let next_active = is_keybind_active(keybind);
 
-            if next_talk_active != talk_active {
-                talk_active = next_talk_active;
-                enqueue_push_keybind_state_event(&frame_queue, PushKeybindKind::Talk, talk_active);
+                if next_active != active[index] {
+                    active[index] = next_active;
+                    on_change(index, next_active);
+                }
             }
 
-            if next_mute_active != mute_active {
-                mute_active = next_mute_active;
-                enqueue_push_keybind_state_event(&frame_queue, PushKeybindKind::Mute, mute_active);
+            let poll_input_keys = transients::key_hints_wanted();
+            for (key_code, was_down) in input_keys.iter().zip(input_down.iter_mut()) {
+                let down = poll_input_keys && is_virtual_key_down(*key_code);
+                if down && !*was_down {
+                    transients::note_input_press();
+                }
+                *was_down = down;
             }
 
             thread::sleep(Duration::from_millis(8));
         }
 
-        if talk_active {
-            enqueue_push_keybind_state_event(&frame_queue, PushKeybindKind::Talk, false);
-        }
-
-        if mute_active {
-            enqueue_push_keybind_state_event(&frame_queue, PushKeybindKind::Mute, false);
+        for (index, was_active) in active.into_iter().enumerate() {
+            if was_active {
+                on_change(index, false);
+            }
         }
     });
 
-    PushKeybindWatcher { stop_flag, handle }
+    Ok(KeybindWatcher { stop_flag, handle })
 }
 
 fn parse_target_pid(target_id: &str) -> Option<u32> {
@@ -1473,7 +1525,7 @@ fn dedupe_window_entries_by_pid(entries: Vec<(u32, String)>) -> HashMap<u32, Str
     deduped
 }
 
-#[cfg(any(windows, test))]
+#[cfg(any(windows, target_os = "linux", test))]
 fn parse_window_source_id(source_id: &str) -> Option<isize> {
     let mut parts = source_id.split(':');
 
@@ -1562,7 +1614,12 @@ fn process_name_from_pid(pid: u32) -> Option<String> {
     Some(file_name)
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+fn process_name_from_pid(pid: u32) -> Option<String> {
+    linux_app_audio::process_name(pid)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 fn process_name_from_pid(_pid: u32) -> Option<String> {
     None
 }
@@ -1624,7 +1681,17 @@ fn get_audio_targets() -> Vec<AudioTarget> {
     targets
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+fn get_audio_targets() -> Vec<AudioTarget> {
+    let window_titles = linux_x11::window_titles_by_pid();
+
+    linux_app_audio::list_targets(&window_titles).unwrap_or_else(|error| {
+        log_warn!("capture", "failed to list audio targets", "error" => &error);
+        Vec::new()
+    })
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 fn get_audio_targets() -> Vec<AudioTarget> {
     Vec::new()
 }
@@ -1650,11 +1717,32 @@ fn resolve_source_to_pid(source_id: &str) -> Option<u32> {
     Some(pid)
 }
 
-#[cfg(not(windows))]
+/// Electron window source ids carry the X11 window id on Linux.
+#[cfg(target_os = "linux")]
+fn resolve_source_to_pid(source_id: &str) -> Option<u32> {
+    let window = u32::try_from(parse_window_source_id(source_id)?).ok()?;
+    linux_x11::window_pid(window)
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 fn resolve_source_to_pid(_source_id: &str) -> Option<u32> {
     None
 }
 
+/// Process loopback on Windows captures the whole process tree, so the
+/// window's own pid is the target.
+#[cfg(not(target_os = "linux"))]
+fn suggested_target_id(_targets: &[AudioTarget], window_pid: u32) -> Option<String> {
+    Some(format!("pid:{window_pid}"))
+}
+
+/// Targets are keyed by the pid playing the audio, which may be a child of
+/// the process owning the window.
+#[cfg(target_os = "linux")]
+fn suggested_target_id(targets: &[AudioTarget], window_pid: u32) -> Option<String> {
+    linux_app_audio::target_for_window(targets, window_pid)
+}
+
 #[cfg(windows)]
 fn process_is_alive(process_handle: HANDLE) -> bool {
     unsafe { WaitForSingleObject(process_handle, 0) == WAIT_TIMEOUT }
@@ -1789,11 +1877,18 @@ fn activate_process_loopback_client(target_pid: u32) -> Result<IAudioClient, Str
 fn capture_loopback_audio(
     session_id: &str,
     target_id: &str,
-    target_pid: u32,
+    target: CaptureTarget,
+    limiter: Option<LimiterOptions>,
     stop_flag: Arc<AtomicBool>,
     frame_queue: Arc<FrameQueue>,
     app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
 ) -> CaptureOutcome {
+    let CaptureTarget::Process(target_pid) = target else {
+        return CaptureOutcome::capture_error(
+            "System audio capture is only available on Linux.".to_string(),
+        );
+    };
+
     let process_handle = match open_process_for_liveness(target_pid) {
         Some(handle) => handle,
         None => return CaptureOutcome::from_reason(CaptureEndReason::AppExited),
@@ -1845,6 +1940,9 @@ fn capture_loopback_audio(
             return Err(format!("Failed to start audio client: {error}"));
         }
 
+        let mut limiter = limiter.map(|options| {
+            TruePeakLimiter::new(options, TARGET_SAMPLE_RATE as usize, TARGET_CHANNELS)
+        });
         let mut pending = Vec::<f32>::new();
         let mut sequence: u64 = 0;
         let mut last_liveness_check = Instant::now();
@@ -1910,40 +2008,19 @@ fn capture_loopback_audio(
                 let _ = unsafe { capture_client.ReleaseBuffer(frame_count) };
 
                 while pending.len() >= FRAME_SIZE * TARGET_CHANNELS {
-                    let frame_samples: Vec<f32> =
+                    let mut frame_samples: Vec<f32> =
                         pending.drain(..FRAME_SIZE * TARGET_CHANNELS).collect();
-                    let wrote_binary = app_audio_binary_stream
-                        .as_ref()
-                        .map(|stream_slot| {
-                            try_write_app_audio_binary_frame(
-                                stream_slot,
-                                session_id,
-                                target_id,
-                                sequence,
-                                TARGET_SAMPLE_RATE as usize,
-                                TARGET_CHANNELS,
-                                FRAME_SIZE,
-                                PROTOCOL_VERSION,
-                                0,
-                                &frame_samples,
-                            )
-                        })
-                        .unwrap_or(false);
-
-                    if !wrote_binary {
-                        let frame_bytes = bytemuck::cast_slice(&frame_samples);
-                        let pcm_base64 = BASE64.encode(frame_bytes);
-
-                        enqueue_frame_event(
-                            &frame_queue,
-                            session_id,
-                            target_id,
-                            sequence,
-                            TARGET_SAMPLE_RATE as usize,
-                            FRAME_SIZE,
-                            pcm_base64,
-                        );
+                    if let Some(limiter) = limiter.as_mut() {
+                        limiter.process(&mut frame_samples);
                     }
+                    emit_app_audio_frame(
+                        &frame_queue,
+                        app_audio_binary_stream.as_ref(),
+                        session_id,
+                        target_id,
+                        sequence,
+                        &frame_samples,
+                    );
 
                     sequence = sequence.saturating_add(1);
                 }
@@ -1967,85 +2044,202 @@ fn capture_loopback_audio(
     match reason {
         Ok(value) => CaptureOutcome::from_reason(value),
         Err(error) => {
-            eprintln!(
-                "[capture-sidecar] capture error targetId={} targetPid={}: {}",
-                target_id, target_pid, error
+            log_error!(
+                "capture",
+                "capture error",
+                "targetId" => target_id,
+                "targetPid" => target_pid,
+                "error" => &error,
+            );
+            CaptureOutcome::capture_error(error)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn capture_loopback_audio(
+    session_id: &str,
+    target_id: &str,
+    target: CaptureTarget,
+    limiter: Option<LimiterOptions>,
+    stop_flag: Arc<AtomicBool>,
+    frame_queue: Arc<FrameQueue>,
+    app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
+) -> CaptureOutcome {
+    use linux_app_audio::{RoutedCapture, StreamSelection};
+
+    let target_exited = || {
+        target
+            .pid()
+            .is_some_and(|pid| !linux_app_audio::process_is_alive(pid))
+    };
+
+    if target_exited() {
+        return CaptureOutcome::from_reason(CaptureEndReason::AppExited);
+    }
+
+    let reason = (|| {
+        let route = match target {
+            CaptureTarget::Process(pid) => {
+                Some(RoutedCapture::start(StreamSelection::ProcessTree(pid))?)
+            }
+            // The sidecar's parent is the desktop app.
+            CaptureTarget::System {
+                exclude_own_playback: true,
+            } => Some(RoutedCapture::start(StreamSelection::DefaultOutputExcept(
+                std::os::unix::process::parent_id(),
+            ))?),
+            CaptureTarget::System {
+                exclude_own_playback: false,
+            } => None,
+        };
+        let device_id = match &route {
+            Some(route) => route.monitor_device_id().to_string(),
+            None => linux_app_audio::default_monitor_device_id()?,
+        };
+        let mut stream =
+            linux_audio::open_capture(Some(&device_id), TARGET_SAMPLE_RATE, TARGET_CHANNELS)?;
+
+        let mut limiter = limiter.map(|options| {
+            TruePeakLimiter::new(options, TARGET_SAMPLE_RATE as usize, TARGET_CHANNELS)
+        });
+        let mut frame_samples = vec![0.0f32; FRAME_SIZE * TARGET_CHANNELS];
+        let mut sequence: u64 = 0;
+        let mut last_liveness_check = Instant::now();
+
+        loop {
+            if stop_flag.load(Ordering::Relaxed) {
+                return Ok(CaptureEndReason::CaptureStopped);
+            }
+
+            if last_liveness_check.elapsed() >= Duration::from_millis(300) {
+                if target_exited() {
+                    return Ok(CaptureEndReason::AppExited);
+                }
+
+                last_liveness_check = Instant::now();
+            }
+
+            if let Err(error) = stream.read(&mut frame_samples) {
+                log_warn!("capture", "capture stream lost", "error" => &error);
+                return Ok(CaptureEndReason::DeviceLost);
+            }
+
+            if let Some(limiter) = limiter.as_mut() {
+                limiter.process(&mut frame_samples);
+            }
+            emit_app_audio_frame(
+                &frame_queue,
+                app_audio_binary_stream.as_ref(),
+                session_id,
+                target_id,
+                sequence,
+                &frame_samples,
+            );
+
+            sequence = sequence.saturating_add(1);
+        }
+    })();
+
+    match reason {
+        Ok(value) => CaptureOutcome::from_reason(value),
+        Err(error) => {
+            log_error!(
+                "capture",
+                "capture error",
+                "targetId" => target_id,
+                "targetPid" => target.pid(),
+                "error" => &error,
             );
             CaptureOutcome::capture_error(error)
         }
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(not(any(windows, target_os = "linux")))]
 fn capture_loopback_audio(
     _session_id: &str,
     _target_id: &str,
-    _target_pid: u32,
+    _target: CaptureTarget,
+    _limiter: Option<LimiterOptions>,
     _stop_flag: Arc<AtomicBool>,
     _frame_queue: Arc<FrameQueue>,
     _app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
 ) -> CaptureOutcome {
-    CaptureOutcome::capture_error("Per-app audio capture is only available on Windows.".to_string())
+    CaptureOutcome::capture_error(
+        "Per-app audio capture is not available on this platform.".to_string(),
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn start_capture_thread(
     stdout: Arc<Mutex<io::Stdout>>,
     frame_queue: Arc<FrameQueue>,
     app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
     session_id: String,
     target_id: String,
-    target_pid: u32,
+    target: CaptureTarget,
+    limiter: Option<LimiterOptions>,
     stop_flag: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
-        let outcome = capture_loopback_audio(
-            &session_id,
-            &target_id,
-            target_pid,
-            Arc::clone(&stop_flag),
-            Arc::clone(&frame_queue),
-            app_audio_binary_stream.clone(),
+        let outcome = crash::catch_panics(|| {
+            capture_loopback_audio(
+                &session_id,
+                &target_id,
+                target,
+                limiter,
+                Arc::clone(&stop_flag),
+                Arc::clone(&frame_queue),
+                app_audio_binary_stream.clone(),
+            )
+        })
+        .unwrap_or_else(|panic| CaptureOutcome::internal_error(&panic));
+
+        write_event(
+            &stdout,
+            "audio_capture.ended",
+            AudioCaptureEndedEvent {
+                session_id,
+                target_id,
+                reason: outcome.reason.as_str(),
+                protocol_version: PROTOCOL_VERSION,
+                error: outcome.error,
+            },
         );
-
-        let mut ended_params = json!({
-            "sessionId": session_id,
-            "targetId": target_id,
-            "reason": outcome.reason.as_str(),
-            "protocolVersion": PROTOCOL_VERSION,
-        });
-
-        if let Some(error) = outcome.error {
-            ended_params["error"] = json!(error);
-        }
-
-        write_event(&stdout, "audio_capture.ended", ended_params);
     })
 }
 
 fn handle_health_ping() -> Result<Value, String> {
-    Ok(json!({
-        "status": "ok",
-        "timestampMs": now_unix_ms(),
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    result_value(HealthPingResult {
+        status: "ok",
+        timestamp_ms: now_unix_ms(),
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn handle_capabilities_get() -> Result<Value, String> {
     let platform = std::env::consts::OS;
-    let per_app_audio = if cfg!(windows) {
+    #[cfg(windows)]
+    let per_app_audio = "supported";
+    #[cfg(target_os = "linux")]
+    let per_app_audio = if linux_app_audio::is_available() {
         "supported"
     } else {
         "unsupported"
     };
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let per_app_audio = "unsupported";
     let voice_filter = "supported";
 
-    Ok(json!({
-        "platform": platform,
-        "perAppAudio": per_app_audio,
-        "voiceFilter": voice_filter,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    }))
+    result_value(CapabilitiesResult {
+        platform,
+        per_app_audio,
+        voice_filter,
+        crash_report_dir: crash::crash_report_dir(),
+        protocol_version: PROTOCOL_VERSION,
+        encoding: PCM_ENCODING,
+    })
 }
 
 fn handle_windows_resolve_source(params: Value) -> Result<Value, String> {
@@ -2054,10 +2248,10 @@ fn handle_windows_resolve_source(params: Value) -> Result<Value, String> {
 
     let pid = resolve_source_to_pid(&parsed.source_id);
 
-    Ok(json!({
-        "sourceId": parsed.source_id,
-        "pid": pid,
-    }))
+    result_value(ResolveSourceResult {
+        source_id: parsed.source_id,
+        pid,
+    })
 }
 
 fn handle_audio_targets_list(params: Value) -> Result<Value, String> {
@@ -2070,13 +2264,19 @@ fn handle_audio_targets_list(params: Value) -> Result<Value, String> {
         .source_id
         .as_deref()
         .and_then(resolve_source_to_pid)
-        .map(|pid| format!("pid:{pid}"));
+        .and_then(|pid| suggested_target_id(&targets, pid));
+
+    result_value(ListTargetsResult {
+        targets,
+        suggested_target_id,
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
 
-    Ok(json!({
-        "targets": targets,
-        "suggestedTargetId": suggested_target_id,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+fn lock_state(state: &Arc<Mutex<SidecarState>>) -> Result<MutexGuard<'_, SidecarState>, String> {
+    state
+        .lock()
+        .map_err(|_| "Sidecar state lock poisoned".to_string())
 }
 
 fn stop_capture_session(state: &mut SidecarState, requested_session_id: Option<&str>) {
@@ -2098,12 +2298,9 @@ fn stop_capture_session(state: &mut SidecarState, requested_session_id: Option<&
 }
 
 fn stop_push_keybind_watcher(state: &mut SidecarState) {
-    let Some(active_watcher) = state.push_keybind_watcher.take() else {
-        return;
-    };
-
-    active_watcher.stop_flag.store(true, Ordering::Relaxed);
-    let _ = active_watcher.handle.join();
+    if let Some(active_watcher) = state.push_keybind_watcher.take() {
+        active_watcher.stop();
+    }
 }
 
 fn stop_mic_capture(state: &mut SidecarState) {
@@ -2143,13 +2340,33 @@ fn handle_audio_capture_start(
     state: &mut SidecarState,
     params: Value,
 ) -> Result<Value, String> {
-    if !cfg!(windows) {
-        return Err("Per-app audio capture is only available on Windows.".to_string());
-    }
-
     let parsed: StartAudioCaptureParams =
         serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
 
+    result_value(start_audio_capture(
+        stdout,
+        frame_queue,
+        app_audio_binary_stream,
+        state,
+        parsed,
+    )?)
+}
+
+fn start_audio_capture(
+    stdout: Arc<Mutex<io::Stdout>>,
+    frame_queue: Arc<FrameQueue>,
+    app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
+    state: &mut SidecarState,
+    parsed: StartAudioCaptureParams,
+) -> Result<StartAudioCaptureResult, String> {
+    if !cfg!(any(windows, target_os = "linux")) {
+        return Err("Per-app audio capture is not available on this platform.".to_string());
+    }
+
+    if let Some(options) = parsed.limiter {
+        options.validate()?;
+    }
+
     stop_capture_session(state, None);
 
     let source_pid = parsed
@@ -2163,25 +2380,43 @@ fn handle_audio_capture_start(
         .or(source_pid)
         .ok_or_else(|| "No app audio target was provided and source mapping failed".to_string())?;
 
-    let target_pid =
-        parse_target_pid(&target_id).ok_or_else(|| "Invalid app audio target id".to_string())?;
+    let target = if target_id == SYSTEM_AUDIO_TARGET_ID {
+        if !cfg!(target_os = "linux") {
+            return Err("System audio capture is only available on Linux.".to_string());
+        }
+
+        CaptureTarget::System {
+            exclude_own_playback: parsed.exclude_own_playback,
+        }
+    } else {
+        let target_pid = parse_target_pid(&target_id)
+            .ok_or_else(|| "Invalid app audio target id".to_string())?;
 
-    let target_exists = get_audio_targets()
-        .iter()
-        .any(|target| target.id == target_id);
+        let target_exists = get_audio_targets()
+            .iter()
+            .any(|target| target.id == target_id);
 
-    if !target_exists {
-        return Err(format!(
-            "Target process with pid {target_pid} is not available"
-        ));
-    }
+        if !target_exists {
+            return Err(format!(
+                "Target process with pid {target_pid} is not available"
+            ));
+        }
+
+        CaptureTarget::Process(target_pid)
+    };
 
     let session_id = Uuid::new_v4().to_string();
-    let target_process_name =
-        process_name_from_pid(target_pid).unwrap_or_else(|| "unknown.exe".to_string());
-    eprintln!(
-        "[capture-sidecar] start session={} targetId={} targetPid={} targetProcess={}",
-        session_id, target_id, target_pid, target_process_name
+    let target_process_name = target
+        .pid()
+        .map(|pid| process_name_from_pid(pid).unwrap_or_else(|| "unknown.exe".to_string()));
+    log_info!(
+        "capture",
+        "start session",
+        "sessionId" => &session_id,
+        "targetId" => &target_id,
+        "targetPid" => target.pid(),
+        "targetProcess" => &target_process_name,
+        "excludeOwnPlayback" => parsed.exclude_own_playback,
     );
     let stop_flag = Arc::new(AtomicBool::new(false));
     let handle = start_capture_thread(
@@ -2190,25 +2425,34 @@ fn handle_audio_capture_start(
         app_audio_binary_stream,
         session_id.clone(),
         target_id.clone(),
-        target_pid,
+        target,
+        parsed.limiter,
         Arc::clone(&stop_flag),
     );
 
     state.capture_session = Some(CaptureSession {
         session_id: session_id.clone(),
+        target_id: target_id.clone(),
+        target,
+        limiter: parsed.limiter,
         stop_flag,
         handle,
     });
 
-    Ok(json!({
-        "sessionId": session_id,
-        "targetId": target_id,
-        "sampleRate": TARGET_SAMPLE_RATE,
-        "channels": TARGET_CHANNELS,
-        "framesPerBuffer": FRAME_SIZE,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    }))
+    let latency_frames = parsed.limiter.map_or(0, |options| {
+        options.latency_frames(TARGET_SAMPLE_RATE as usize)
+    });
+
+    Ok(StartAudioCaptureResult {
+        session_id,
+        target_id,
+        sample_rate: TARGET_SAMPLE_RATE,
+        channels: TARGET_CHANNELS,
+        frames_per_buffer: FRAME_SIZE,
+        latency_ms: frames_to_ms(latency_frames, TARGET_SAMPLE_RATE as usize),
+        protocol_version: PROTOCOL_VERSION,
+        encoding: PCM_ENCODING,
+    })
 }
 
 fn handle_audio_capture_stop(state: &mut SidecarState, params: Value) -> Result<Value, String> {
@@ -2217,10 +2461,10 @@ fn handle_audio_capture_stop(state: &mut SidecarState, params: Value) -> Result<
 
     stop_capture_session(state, parsed.session_id.as_deref());
 
-    Ok(json!({
-        "stopped": true,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    result_value(StoppedResult {
+        stopped: true,
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn handle_push_keybinds_set(
@@ -2231,16 +2475,26 @@ fn handle_push_keybinds_set(
     let parsed: SetPushKeybindsParams =
         serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
 
+    result_value(set_push_keybinds(frame_queue, state, parsed))
+}
+
+fn set_push_keybinds(
+    frame_queue: Arc<FrameQueue>,
+    state: &mut SidecarState,
+    parsed: SetPushKeybindsParams,
+) -> SetPushKeybindsResult {
     stop_push_keybind_watcher(state);
+    state.push_keybind_states.reset();
+    state.push_keybinds = parsed.clone();
 
-    #[cfg(not(windows))]
-    let _ = &frame_queue;
+    #[cfg(any(windows, target_os = "linux"))]
+    let result = {
+        #[cfg(target_os = "linux")]
+        use linux_keybinds::{parse_global_keybind, start_keybind_watcher};
 
-    #[cfg(windows)]
-    {
         let mut errors: Vec<String> = Vec::new();
 
-        let talk_keybind = match parse_push_keybind(parsed.push_to_talk_keybind.as_deref()) {
+        let mut talk_keybind = match parse_global_keybind(parsed.push_to_talk_keybind.as_deref()) {
             Ok(parsed_keybind) => parsed_keybind,
             Err(error) => {
                 errors.push(format!("Push-to-talk keybind is invalid: {error}"));
@@ -2248,7 +2502,7 @@ fn handle_push_keybinds_set(
             }
         };
 
-        let mut mute_keybind = match parse_push_keybind(parsed.push_to_mute_keybind.as_deref()) {
+        let mut mute_keybind = match parse_global_keybind(parsed.push_to_mute_keybind.as_deref()) {
             Ok(parsed_keybind) => parsed_keybind,
             Err(error) => {
                 errors.push(format!("Push-to-mute keybind is invalid: {error}"));
@@ -2256,45 +2510,69 @@ fn handle_push_keybinds_set(
             }
         };
 
-        if talk_keybind.is_some() && mute_keybind.is_some() && talk_keybind == mute_keybind {
-            mute_keybind = None;
-            errors.push("Push-to-mute keybind matches push-to-talk and was ignored.".to_string());
+        if let (Some(talk), Some(mute)) = (talk_keybind, mute_keybind) {
+            if talk.overlaps(&mute) {
+                mute_keybind = None;
+                errors
+                    .push("Push-to-mute keybind matches push-to-talk and was ignored.".to_string());
+            }
         }
 
-        if talk_keybind.is_some() || mute_keybind.is_some() {
-            state.push_keybind_watcher = Some(start_push_keybind_watcher(
-                frame_queue,
-                talk_keybind,
-                mute_keybind,
-            ));
-        }
+        let (kinds, keybinds): (Vec<PushKeybindKind>, Vec<Keybind>) = [
+            (PushKeybindKind::Talk, talk_keybind),
+            (PushKeybindKind::Mute, mute_keybind),
+        ]
+        .into_iter()
+        .filter_map(|(kind, keybind)| Some((kind, keybind?)))
+        .unzip();
+
+        if !keybinds.is_empty() {
+            let push_keybind_states = Arc::clone(&state.push_keybind_states);
+            let on_change = move |index: usize, active| {
+                let held = match kinds[index] {
+                    PushKeybindKind::Talk => &push_keybind_states.talk,
+                    PushKeybindKind::Mute => &push_keybind_states.mute,
+                };
+                held.store(active, Ordering::Relaxed);
+                enqueue_push_keybind_state_event(&frame_queue, kinds[index], active);
+            };
 
-        let talk_registered = talk_keybind.is_some();
-        let mute_registered = mute_keybind.is_some();
+            match start_keybind_watcher(keybinds, Box::new(on_change)) {
+                Ok(watcher) => state.push_keybind_watcher = Some(watcher),
+                Err(error) => {
+                    errors.push(error);
+                    talk_keybind = None;
+                    mute_keybind = None;
+                }
+            }
+        }
 
-        return Ok(json!({
-            "talkRegistered": talk_registered,
-            "muteRegistered": mute_registered,
-            "errors": errors,
-        }));
-    }
+        SetPushKeybindsResult {
+            talk_registered: talk_keybind.is_some(),
+            mute_registered: mute_keybind.is_some(),
+            errors,
+        }
+    };
 
-    #[cfg(not(windows))]
-    {
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let result = {
+        let _ = &frame_queue;
         let mut errors = Vec::new();
         if parsed.push_to_talk_keybind.is_some() || parsed.push_to_mute_keybind.is_some() {
             errors.push(
-                "Global push keybind monitoring via sidecar is only available on Windows."
+                "Global push keybind monitoring via sidecar is only available on Windows and Linux."
                     .to_string(),
             );
         }
 
-        Ok(json!({
-            "talkRegistered": false,
-            "muteRegistered": false,
-            "errors": errors,
-        }))
-    }
+        SetPushKeybindsResult {
+            talk_registered: false,
+            mute_registered: false,
+            errors,
+        }
+    };
+
+    result
 }
 
 /// Read a `VT_LPWSTR` string value out of a `PROPVARIANT` by inspecting
@@ -2333,6 +2611,15 @@ fn list_mic_devices_windows() -> Vec<MicDevice> {
             CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?
         };
 
+        let default_id = unsafe { enumerator.GetDefaultAudioEndpoint(eCapture, eConsole) }
+            .ok()
+            .and_then(|device| unsafe { device.GetId() }.ok())
+            .and_then(|id_pwstr| {
+                let id = unsafe { id_pwstr.to_string() }.ok();
+                unsafe { CoTaskMemFree(Some(id_pwstr.0 as *const c_void)) };
+                id
+            });
+
         let collection = unsafe { enumerator.EnumAudioEndpoints(eCapture, DEVICE_STATE_ACTIVE)? };
         let count = unsafe { collection.GetCount()? };
 
@@ -2366,14 +2653,20 @@ fn list_mic_devices_windows() -> Vec<MicDevice> {
             })()
             .unwrap_or_default();
 
-            devices.push(MicDevice { id, label });
+            devices.push(MicDevice {
+                is_default: default_id.as_deref() == Some(id.as_str()),
+                id,
+                label,
+                channels: None,
+                sample_rate: None,
+            });
         }
 
         Ok(())
     })();
 
     if let Err(error) = result {
-        eprintln!("[capture-sidecar] list_mic_devices error: {error}");
+        log_warn!("devices", "failed to list mic devices", "error" => error.to_string());
     }
 
     if com_initialized {
@@ -2383,18 +2676,23 @@ fn list_mic_devices_windows() -> Vec<MicDevice> {
     devices
 }
 
-fn handle_mic_devices_list() -> Result<Value, String> {
+fn handle_mic_devices_list(params: Value) -> Result<Value, String> {
+    let parsed: ListMicDevicesParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    // Monitor sources are a PulseAudio concept; Windows lists capture
+    // endpoints only.
+    #[cfg(not(target_os = "linux"))]
+    let _ = parsed;
+
     #[cfg(windows)]
-    {
-        let devices = list_mic_devices_windows();
-        return Ok(json!({ "devices": devices }));
-    }
+    let devices = list_mic_devices_windows();
+    #[cfg(target_os = "linux")]
+    let devices = linux_audio::list_capture_devices(parsed.include_monitors)?;
+    #[cfg(not(any(windows, target_os = "linux")))]
+    let devices = Vec::new();
 
-    #[cfg(not(windows))]
-    {
-        let empty: Vec<MicDevice> = Vec::new();
-        Ok(json!({ "devices": empty }))
-    }
+    result_value(MicDevicesResult { devices })
 }
 
 #[cfg(windows)]
@@ -2493,17 +2791,16 @@ fn capture_mic_audio(
             Ok(()) => "enabled".to_string(),
             Err(reason) => format!("failed: {reason}"),
         };
-        eprintln!("[sidecar] mic capture raw mode: {raw_mode_status}");
-        if let Ok(event_json) = serde_json::to_string(&SidecarEvent {
-            event: "mic_capture.status",
-            params: json!({
-                "sessionId": session_id,
-                "rawModeEnabled": raw_mode_result.is_ok(),
-                "rawModeStatus": raw_mode_status,
-            }),
-        }) {
-            frame_queue.push_line(event_json);
-        }
+        log_info!("mic_capture", "mic capture raw mode", "status" => &raw_mode_status);
+        enqueue_event(
+            &frame_queue,
+            "mic_capture.status",
+            MicCaptureStatusEvent {
+                session_id: session_id.clone(),
+                raw_mode_enabled: raw_mode_result.is_ok(),
+                raw_mode_status,
+            },
+        );
 
         let mut pending = Vec::<f32>::new();
         let mut sequence: u64 = 0;
@@ -2587,7 +2884,7 @@ fn capture_mic_audio(
                 };
 
                 if let Err(error) = processed {
-                    eprintln!("[capture-sidecar] mic capture process error: {error}");
+                    log_warn!("mic_capture", "mic capture process error", "error" => error);
                 }
 
                 sequence = sequence.saturating_add(1);
@@ -2600,12 +2897,98 @@ fn capture_mic_audio(
     }
 
     if let Err(error) = result {
-        eprintln!("[capture-sidecar] mic capture thread error: {error}");
+        log_error!(
+            "mic_capture",
+            "mic capture thread error",
+            "sessionId" => &session_id,
+            "error" => &error,
+        );
         enqueue_voice_filter_ended_event(&frame_queue, &session_id, "capture_error", Some(error));
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+fn capture_mic_audio(
+    session_id: String,
+    device_id: Option<String>,
+    stop_flag: Arc<AtomicBool>,
+    state: Arc<Mutex<SidecarState>>,
+    frame_queue: Arc<FrameQueue>,
+) {
+    let result: Result<(), String> = (|| {
+        let mut stream =
+            linux_audio::open_capture(device_id.as_deref(), TARGET_SAMPLE_RATE, TARGET_CHANNELS)?;
+        log_info!(
+            "mic_capture",
+            "mic capture opened",
+            "sessionId" => &session_id,
+            "backend" => stream.backend(),
+            "deviceId" => &device_id,
+        );
+
+        let mut samples = vec![0.0f32; MIC_CAPTURE_FRAME_SIZE * TARGET_CHANNELS];
+        let mut sequence: u64 = 0;
+
+        loop {
+            // Reads block for one frame (10 ms), which bounds how long a stop
+            // request waits.
+            if stop_flag.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+
+            stream.read(&mut samples)?;
+
+            let mut state_lock = state
+                .lock()
+                .map_err(|_| "State lock poisoned in capture thread".to_string())?;
+
+            match state_lock.voice_filter_session {
+                Some(ref vf_session) if vf_session.session_id == session_id => {}
+                _ => return Ok(()),
+            }
+
+            let processed = process_voice_filter_samples(
+                &frame_queue,
+                &mut state_lock,
+                &session_id,
+                sequence,
+                TARGET_SAMPLE_RATE as usize,
+                TARGET_CHANNELS,
+                MIC_CAPTURE_FRAME_SIZE,
+                None,
+                samples.clone(),
+            );
+            drop(state_lock);
+
+            if let Err(error) = processed {
+                log_warn!("mic_capture", "mic capture process error", "error" => error);
+            }
+
+            sequence = sequence.saturating_add(1);
+        }
+    })();
+
+    if let Err(error) = result {
+        log_error!(
+            "mic_capture",
+            "mic capture thread error",
+            "sessionId" => &session_id,
+            "error" => &error,
+        );
+        // Ends the session too, so it is not reported again as stopped later.
+        if let Ok(mut state_lock) = lock_state(&state) {
+            stop_voice_filter_session(
+                &mut state_lock,
+                &frame_queue,
+                Some(&session_id),
+                "capture_error",
+                Some(error),
+            );
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
 fn capture_mic_audio(
     _session_id: String,
     _device_id: Option<String>,
@@ -2616,14 +2999,28 @@ fn capture_mic_audio(
 }
 
 fn handle_voice_filter_start_with_capture(
-    state_arc: Arc<Mutex<SidecarState>>,
+    state: &Arc<Mutex<SidecarState>>,
     frame_queue: Arc<FrameQueue>,
-    state: &mut SidecarState,
     params: Value,
+    request: &RequestContext,
 ) -> Result<Value, String> {
     let parsed: StartVoiceFilterWithCaptureParams =
         serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
 
+    result_value(start_voice_filter_with_capture(
+        state,
+        frame_queue,
+        parsed,
+        request,
+    )?)
+}
+
+fn start_voice_filter_with_capture(
+    state: &Arc<Mutex<SidecarState>>,
+    frame_queue: Arc<FrameQueue>,
+    parsed: StartVoiceFilterWithCaptureParams,
+    request: &RequestContext,
+) -> Result<StartVoiceFilterResult, String> {
     if parsed.sample_rate != TARGET_SAMPLE_RATE as usize {
         return Err("DeepFilterNet currently supports only 48kHz input".to_string());
     }
@@ -2632,73 +3029,134 @@ fn handle_voice_filter_start_with_capture(
         return Err("Unsupported voice filter channel count".to_string());
     }
 
+    if let Some(transmit) = &parsed.transmit {
+        transmit.validate()?;
+    }
+
+    if !cfg!(any(windows, target_os = "linux")) {
+        return Err("Native microphone capture is not available on this platform".to_string());
+    }
+
+    let pre_filter = parsed.pre_filter.unwrap_or(false);
     let noise_suppression = parsed.noise_suppression.unwrap_or(true);
     let auto_gain_control = parsed.auto_gain_control.unwrap_or(false);
     let echo_cancellation = parsed.echo_cancellation.unwrap_or(false);
 
-    stop_voice_filter_session(state, &frame_queue, None, "capture_stopped", None);
+    stop_voice_filter_session(
+        &mut *lock_state(state)?,
+        &frame_queue,
+        None,
+        "capture_stopped",
+        None,
+    );
 
     let session_id = Uuid::new_v4().to_string();
-    let session = create_voice_filter_session(
+    let mut session = create_voice_filter_session(
         session_id.clone(),
-        parsed.sample_rate,
-        parsed.channels,
-        parsed.suppression_level,
-        noise_suppression,
-        auto_gain_control,
-        echo_cancellation,
+        VoiceFilterSessionOptions {
+            sample_rate: parsed.sample_rate,
+            channels: parsed.channels,
+            suppression_level: parsed.suppression_level,
+            channel_mode: parsed.channel_mode.unwrap_or_default(),
+            pre_filter,
+            noise_suppression,
+            auto_gain_control,
+            auto_gain_control_mode: parsed.auto_gain_control_mode.unwrap_or_default(),
+            dereverb: parsed.dereverb,
+            comfort_noise: parsed.comfort_noise,
+            echo_cancellation,
+        },
+        parsed.chain.clone(),
+        request,
     )?;
+    session.mic_capture = Some(MicCaptureSource {
+        device_id: parsed.device_id.clone(),
+    });
+    session.transmit_gate = parsed
+        .transmit
+        .map(|options| TransmitGate::new(options, parsed.sample_rate));
+    request.check_cancelled()?;
     // Native capture always sends MIC_CAPTURE_FRAME_SIZE frames per buffer,
     // regardless of whether DeepFilterNet is active.  Report the actual size
     // so the client pipeline can size its buffers correctly.
-    #[cfg(windows)]
     let frames_per_buffer = MIC_CAPTURE_FRAME_SIZE;
-    #[cfg(not(windows))]
-    let frames_per_buffer = voice_filter_frames_per_buffer(&session);
-
-    state.voice_filter_session = Some(session);
+    let latency_ms = frames_to_ms(session.chain.latency_frames(), parsed.sample_rate);
 
     let stop_flag = Arc::new(AtomicBool::new(false));
-    state.mic_capture_stop_flag = Some(Arc::clone(&stop_flag));
+    {
+        let mut state_lock = lock_state(state)?;
+        state_lock.voice_filter_session = Some(session);
+        state_lock.mic_capture_stop_flag = Some(Arc::clone(&stop_flag));
+    }
 
     let thread_session_id = session_id.clone();
     let thread_device_id = parsed.device_id.clone();
-    let thread_state = Arc::clone(&state_arc);
+    let thread_state = Arc::clone(state);
     let thread_queue = Arc::clone(&frame_queue);
 
-    eprintln!(
-        "[capture-sidecar] voice_filter.start_with_capture deviceId={:?}",
-        parsed.device_id
+    log_info!(
+        "voice_filter",
+        "voice_filter.start_with_capture",
+        "sessionId" => &session_id,
+        "deviceId" => &parsed.device_id,
     );
 
     thread::spawn(move || {
-        capture_mic_audio(
-            thread_session_id,
-            thread_device_id,
-            stop_flag,
-            thread_state,
-            thread_queue,
-        );
+        let captured = crash::catch_panics(|| {
+            capture_mic_audio(
+                thread_session_id.clone(),
+                thread_device_id,
+                stop_flag,
+                Arc::clone(&thread_state),
+                Arc::clone(&thread_queue),
+            )
+        });
+
+        if let Err(panic) = captured {
+            // The capture thread may have panicked while holding the state
+            // lock; clear the poison so the session still ends cleanly.
+            thread_state.clear_poison();
+            if let Ok(mut state_lock) = lock_state(&thread_state) {
+                stop_voice_filter_session(
+                    &mut state_lock,
+                    &thread_queue,
+                    Some(&thread_session_id),
+                    "internal_error",
+                    Some(panic.describe()),
+                );
+            }
+        }
     });
 
-    Ok(json!({
-        "sessionId": session_id,
-        "sampleRate": parsed.sample_rate,
-        "channels": parsed.channels,
-        "framesPerBuffer": frames_per_buffer,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    }))
+    Ok(StartVoiceFilterResult {
+        session_id,
+        sample_rate: parsed.sample_rate,
+        channels: parsed.channels,
+        frames_per_buffer,
+        latency_ms,
+        protocol_version: PROTOCOL_VERSION,
+        encoding: PCM_ENCODING,
+    })
 }
 
 fn handle_voice_filter_start(
     frame_queue: Arc<FrameQueue>,
-    state: &mut SidecarState,
+    state: &Arc<Mutex<SidecarState>>,
     params: Value,
+    request: &RequestContext,
 ) -> Result<Value, String> {
     let parsed: StartVoiceFilterParams =
         serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
 
+    result_value(start_voice_filter(frame_queue, state, parsed, request)?)
+}
+
+fn start_voice_filter(
+    frame_queue: Arc<FrameQueue>,
+    state: &Arc<Mutex<SidecarState>>,
+    parsed: StartVoiceFilterParams,
+    request: &RequestContext,
+) -> Result<StartVoiceFilterResult, String> {
     if parsed.sample_rate != TARGET_SAMPLE_RATE as usize {
         return Err("DeepFilterNet currently supports only 48kHz input".to_string());
     }
@@ -2707,59 +3165,66 @@ fn handle_voice_filter_start(
         return Err("Unsupported voice filter channel count".to_string());
     }
 
+    if let Some(transmit) = &parsed.transmit {
+        transmit.validate()?;
+    }
+
+    let pre_filter = parsed.pre_filter.unwrap_or(false);
     let noise_suppression = parsed.noise_suppression.unwrap_or(true);
     let auto_gain_control = parsed.auto_gain_control.unwrap_or(false);
     let echo_cancellation = parsed.echo_cancellation.unwrap_or(false);
 
     if echo_cancellation {
-        eprintln!("[capture-sidecar] Voice filter echo cancellation enabled");
+        log_info!("voice_filter", "voice filter echo cancellation enabled");
     }
 
-    stop_voice_filter_session(state, &frame_queue, None, "capture_stopped", None);
+    stop_voice_filter_session(
+        &mut *lock_state(state)?,
+        &frame_queue,
+        None,
+        "capture_stopped",
+        None,
+    );
 
+    // The state lock is not held while the model loads so other lanes (and the
+    // binary ingress worker) keep running during DeepFilterNet init and warmup.
     let session_id = Uuid::new_v4().to_string();
-    let session = create_voice_filter_session(
+    let mut session = create_voice_filter_session(
         session_id.clone(),
-        parsed.sample_rate,
-        parsed.channels,
-        parsed.suppression_level,
-        noise_suppression,
-        auto_gain_control,
-        echo_cancellation,
+        VoiceFilterSessionOptions {
+            sample_rate: parsed.sample_rate,
+            channels: parsed.channels,
+            suppression_level: parsed.suppression_level,
+            channel_mode: parsed.channel_mode.unwrap_or_default(),
+            pre_filter,
+            noise_suppression,
+            auto_gain_control,
+            auto_gain_control_mode: parsed.auto_gain_control_mode.unwrap_or_default(),
+            dereverb: parsed.dereverb,
+            comfort_noise: parsed.comfort_noise,
+            echo_cancellation,
+        },
+        parsed.chain.clone(),
+        request,
     )?;
+    session.transmit_gate = parsed
+        .transmit
+        .map(|options| TransmitGate::new(options, parsed.sample_rate));
     let frames_per_buffer = voice_filter_frames_per_buffer(&session);
+    let latency_ms = frames_to_ms(session.chain.latency_frames(), parsed.sample_rate);
+    request.check_cancelled()?;
 
-    state.voice_filter_session = Some(session);
-
-    Ok(json!({
-        "sessionId": session_id,
-        "sampleRate": parsed.sample_rate,
-        "channels": parsed.channels,
-        "framesPerBuffer": frames_per_buffer,
-        "protocolVersion": PROTOCOL_VERSION,
-        "encoding": PCM_ENCODING,
-    }))
-}
-
-fn apply_limiter(samples: &mut [f32], gain: &mut f32) {
-    for sample in samples.iter_mut() {
-        let abs_val = sample.abs();
-        let target_gain = if abs_val > LIMITER_THRESHOLD {
-            LIMITER_THRESHOLD / abs_val
-        } else {
-            1.0
-        };
-
-        if target_gain < *gain {
-            // Attack: fast gain reduction
-            *gain = *gain * LIMITER_ATTACK_COEFF + target_gain * (1.0 - LIMITER_ATTACK_COEFF);
-        } else {
-            // Release: slow restore toward 1.0
-            *gain = (*gain + (1.0 - *gain) * (1.0 - LIMITER_RELEASE_COEFF)).min(1.0);
-        }
+    lock_state(state)?.voice_filter_session = Some(session);
 
-        *sample *= *gain;
-    }
+    Ok(StartVoiceFilterResult {
+        session_id,
+        sample_rate: parsed.sample_rate,
+        channels: parsed.channels,
+        frames_per_buffer,
+        latency_ms,
+        protocol_version: PROTOCOL_VERSION,
+        encoding: PCM_ENCODING,
+    })
 }
 
 fn process_voice_filter_samples(
@@ -2773,6 +3238,7 @@ fn process_voice_filter_samples(
     protocol_version: Option<u32>,
     mut samples: Vec<f32>,
 ) -> Result<(), String> {
+    let push_keys = state.push_keybind_states.load();
     let Some(session) = state.voice_filter_session.as_mut() else {
         return Err("No active voice filter session".to_string());
     };
@@ -2782,7 +3248,7 @@ fn process_voice_filter_samples(
     }
 
     if let Some(protocol_version) = protocol_version {
-        if protocol_version != PROTOCOL_VERSION {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
             return Err("Unsupported voice filter protocol version".to_string());
         }
     }
@@ -2799,16 +3265,31 @@ fn process_voice_filter_samples(
         return Err("Unsupported voice filter frame channel count".to_string());
     }
 
-    process_voice_filter_frame(session, &mut samples, channels)?;
+    // A panic inside the model leaves it in an unknown state, so the session is
+    // torn down rather than fed further frames.
+    match crash::catch_panics(|| process_voice_filter_frame(session, &mut samples, channels)) {
+        Ok(processed) => processed?,
+        Err(panic) => {
+            let session_id = session.session_id.clone();
+            stop_voice_filter_session(
+                state,
+                frame_queue,
+                Some(&session_id),
+                "internal_error",
+                Some(panic.describe()),
+            );
+            return Err(panic.describe());
+        }
+    }
 
     if samples.len() != frame_count * channels {
         return Err("Voice filter frame sample count mismatch".to_string());
     }
 
-    // Limiter is only needed after DeepFilterNet to guard against model output peaks.
-    // In passthrough mode the raw signal should not be modified.
-    if matches!(session.processor, VoiceFilterProcessor::DeepFilter(_)) {
-        apply_limiter(&mut samples, &mut session.limiter_gain);
+    if let Some(gate) = session.transmit_gate.as_mut() {
+        if let Some(transmitting) = gate.process(&mut samples, channels, push_keys) {
+            enqueue_transmit_state_event(frame_queue, &session.session_id, transmitting);
+        }
     }
 
     let frame_bytes = bytemuck::cast_slice(&samples);
@@ -2845,7 +3326,7 @@ fn process_voice_filter_reference_samples(
     }
 
     if let Some(protocol_version) = protocol_version {
-        if protocol_version != PROTOCOL_VERSION {
+        if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
             return Err("Unsupported voice filter protocol version".to_string());
         }
     }
@@ -2854,7 +3335,7 @@ fn process_voice_filter_reference_samples(
         return Err("Voice filter sample rate mismatch".to_string());
     }
 
-    if channels == 0 || channels > 2 {
+    if channels == 0 || channels > MAX_REFERENCE_CHANNELS {
         return Err("Unsupported voice filter reference channel count".to_string());
     }
 
@@ -2894,10 +3375,10 @@ fn handle_voice_filter_push_frame(
         samples,
     )?;
 
-    Ok(json!({
-        "accepted": true,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    result_value(FrameAcceptedResult {
+        accepted: true,
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn handle_voice_filter_push_reference_frame(
@@ -2926,10 +3407,64 @@ fn handle_voice_filter_push_reference_frame(
         samples,
     )?;
 
-    Ok(json!({
-        "accepted": true,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    result_value(FrameAcceptedResult {
+        accepted: true,
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+fn handle_voice_filter_set_transmit(
+    state: &mut SidecarState,
+    params: Value,
+) -> Result<Value, String> {
+    let parsed: SetVoiceFilterTransmitParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    let Some(session) = state.voice_filter_session.as_mut() else {
+        return Err("No active voice filter session".to_string());
+    };
+
+    if session.session_id != parsed.session_id {
+        return Err("Voice filter session mismatch".to_string());
+    }
+
+    if let Some(transmit) = &parsed.transmit {
+        transmit.validate()?;
+    }
+
+    match (session.transmit_gate.as_mut(), parsed.transmit) {
+        (Some(gate), Some(options)) => gate.set_options(options),
+        (_, transmit) => {
+            session.transmit_gate =
+                transmit.map(|options| TransmitGate::new(options, session.sample_rate));
+        }
+    }
+
+    result_value(SetVoiceFilterTransmitResult {
+        transmitting: session
+            .transmit_gate
+            .as_ref()
+            .is_none_or(TransmitGate::is_transmitting),
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+fn handle_voice_filter_metrics(state: &mut SidecarState, params: Value) -> Result<Value, String> {
+    let parsed: VoiceFilterMetricsParams =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    let Some(session) = state.voice_filter_session.as_mut() else {
+        return Err("No active voice filter session".to_string());
+    };
+
+    if session.session_id != parsed.session_id {
+        return Err("Voice filter session mismatch".to_string());
+    }
+
+    result_value(VoiceFilterMetricsResult {
+        stages: session.chain.take_metrics(),
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn handle_voice_filter_stop(
@@ -2948,10 +3483,10 @@ fn handle_voice_filter_stop(
         None,
     );
 
-    Ok(json!({
-        "stopped": true,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    result_value(StoppedResult {
+        stopped: true,
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn start_app_audio_binary_egress() -> Result<AppAudioBinaryEgress, String> {
@@ -2986,7 +3521,11 @@ fn start_app_audio_binary_egress() -> Result<AppAudioBinaryEgress, String> {
                     thread::sleep(Duration::from_millis(25));
                 }
                 Err(error) => {
-                    eprintln!("[capture-sidecar] app-audio binary egress accept error: {error}");
+                    log_warn!(
+                        "capture.binary_egress",
+                        "app-audio binary egress accept error",
+                        "error" => error.to_string(),
+                    );
                     thread::sleep(Duration::from_millis(100));
                 }
             }
@@ -3005,14 +3544,12 @@ fn start_app_audio_binary_egress() -> Result<AppAudioBinaryEgress, String> {
     })
 }
 
-fn handle_audio_capture_binary_egress_info(
-    app_audio_binary_egress: &AppAudioBinaryEgress,
-) -> Result<Value, String> {
-    Ok(json!({
-        "port": app_audio_binary_egress.port,
-        "framing": APP_AUDIO_BINARY_EGRESS_FRAMING,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+fn handle_audio_capture_binary_egress_info(port: u16) -> Result<Value, String> {
+    result_value(BinaryTransportInfoResult {
+        port,
+        framing: APP_AUDIO_BINARY_EGRESS_FRAMING,
+        protocol_version: PROTOCOL_VERSION,
+    })
 }
 
 fn read_exact_with_stop(
@@ -3169,16 +3706,21 @@ fn handle_voice_filter_binary_stream(
             Ok(true) => {}
             Ok(false) => return,
             Err(error) => {
-                eprintln!("[capture-sidecar] binary ingress read error: {error}");
+                log_warn!(
+                    "voice_filter.binary_ingress",
+                    "binary ingress read error",
+                    "error" => error.to_string(),
+                );
                 return;
             }
         }
 
         let frame_length = u32::from_le_bytes(frame_length_bytes) as usize;
         if frame_length == 0 || frame_length > MAX_VOICE_FILTER_BINARY_FRAME_BYTES {
-            eprintln!(
-                "[capture-sidecar] binary ingress rejected frame with invalid size {}",
-                frame_length
+            log_warn!(
+                "voice_filter.binary_ingress",
+                "binary ingress rejected frame with invalid size",
+                "frameLength" => frame_length,
             );
             return;
         }
@@ -3188,7 +3730,11 @@ fn handle_voice_filter_binary_stream(
             Ok(true) => {}
             Ok(false) => return,
             Err(error) => {
-                eprintln!("[capture-sidecar] binary ingress payload read error: {error}");
+                log_warn!(
+                    "voice_filter.binary_ingress",
+                    "binary ingress payload read error",
+                    "error" => error.to_string(),
+                );
                 return;
             }
         }
@@ -3196,7 +3742,11 @@ fn handle_voice_filter_binary_stream(
         let frame = match parse_voice_filter_binary_frame(&payload) {
             Ok(frame) => frame,
             Err(error) => {
-                eprintln!("[capture-sidecar] invalid binary voice filter frame: {error}");
+                log_warn!(
+                    "voice_filter.binary_ingress",
+                    "invalid binary voice filter frame",
+                    "error" => error,
+                );
                 continue;
             }
         };
@@ -3204,7 +3754,7 @@ fn handle_voice_filter_binary_stream(
         let mut state_lock = match state.lock() {
             Ok(state_lock) => state_lock,
             Err(_) => {
-                eprintln!("[capture-sidecar] sidecar state lock poisoned");
+                log_error!("voice_filter.binary_ingress", "sidecar state lock poisoned");
                 return;
             }
         };
@@ -3220,7 +3770,13 @@ fn handle_voice_filter_binary_stream(
             Some(frame.protocol_version),
             frame.samples,
         ) {
-            eprintln!("[capture-sidecar] binary voice filter frame rejected: {error}");
+            log_warn!(
+                "voice_filter.binary_ingress",
+                "binary voice filter frame rejected",
+                "sessionId" => &frame.session_id,
+                "error" => error,
+                "sequence" => frame.sequence,
+            );
         }
     }
 }
@@ -3260,7 +3816,11 @@ fn start_voice_filter_binary_ingress(
                     thread::sleep(Duration::from_millis(25));
                 }
                 Err(error) => {
-                    eprintln!("[capture-sidecar] binary ingress accept error: {error}");
+                    log_warn!(
+                        "voice_filter.binary_ingress",
+                        "binary ingress accept error",
+                        "error" => error.to_string(),
+                    );
                     thread::sleep(Duration::from_millis(100));
                 }
             }
@@ -3274,53 +3834,199 @@ fn start_voice_filter_binary_ingress(
     })
 }
 
-fn handle_voice_filter_binary_ingress_info(
-    binary_ingress: &VoiceFilterBinaryIngress,
+fn handle_voice_filter_binary_ingress_info(port: u16) -> Result<Value, String> {
+    result_value(BinaryTransportInfoResult {
+        port,
+        framing: VOICE_FILTER_BINARY_FRAMING,
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+/// Shared handles every request handler may need, independent of which
+/// dispatch lane it runs on.
+struct SidecarContext {
+    stdout: Arc<Mutex<io::Stdout>>,
+    frame_queue: Arc<FrameQueue>,
+    state: Arc<Mutex<SidecarState>>,
+    app_audio_binary_egress_port: Option<u16>,
+    app_audio_binary_stream: Option<Arc<Mutex<Option<TcpStream>>>>,
+    voice_filter_binary_ingress_port: Option<u16>,
+}
+
+fn handle_request(
+    context: &SidecarContext,
+    method: &str,
+    params: Value,
+    request: &RequestContext,
 ) -> Result<Value, String> {
-    Ok(json!({
-        "port": binary_ingress.port,
-        "framing": VOICE_FILTER_BINARY_FRAMING,
-        "protocolVersion": PROTOCOL_VERSION,
-    }))
+    match method {
+        "health.ping" => handle_health_ping(),
+        "log.set_level" => logging::handle_log_set_level(params),
+        "capabilities.get" => handle_capabilities_get(),
+        "rpc.describe" => Ok(rpc::describe()),
+        "state.snapshot" => snapshot::handle_state_snapshot(&*lock_state(&context.state)?),
+        "state.restore" => snapshot::handle_state_restore(context, params, request),
+        "windows.resolve_source" => handle_windows_resolve_source(params),
+        "audio_targets.list" => handle_audio_targets_list(params),
+        "audio_capture.binary_egress_info" => match context.app_audio_binary_egress_port {
+            Some(port) => handle_audio_capture_binary_egress_info(port),
+            None => Err("Binary app-audio egress is unavailable".to_string()),
+        },
+        "voice_filter.binary_ingress_info" => match context.voice_filter_binary_ingress_port {
+            Some(port) => handle_voice_filter_binary_ingress_info(port),
+            None => Err("Binary voice filter ingress is unavailable".to_string()),
+        },
+        "audio_capture.start" => handle_audio_capture_start(
+            Arc::clone(&context.stdout),
+            Arc::clone(&context.frame_queue),
+            context.app_audio_binary_stream.clone(),
+            &mut *lock_state(&context.state)?,
+            params,
+        ),
+        "audio_capture.stop" => {
+            handle_audio_capture_stop(&mut *lock_state(&context.state)?, params)
+        }
+        "push_keybinds.set" => handle_push_keybinds_set(
+            Arc::clone(&context.frame_queue),
+            &mut *lock_state(&context.state)?,
+            params,
+        ),
+        "hotkeys.set" => hotkeys::handle_hotkeys_set(
+            Arc::clone(&context.frame_queue),
+            &mut *lock_state(&context.state)?,
+            params,
+        ),
+        "mic_devices.list" => handle_mic_devices_list(params),
+        "voice_filter.start_with_capture" => handle_voice_filter_start_with_capture(
+            &context.state,
+            Arc::clone(&context.frame_queue),
+            params,
+            request,
+        ),
+        "voice_filter.start" => handle_voice_filter_start(
+            Arc::clone(&context.frame_queue),
+            &context.state,
+            params,
+            request,
+        ),
+        "voice_filter.push_frame" => handle_voice_filter_push_frame(
+            Arc::clone(&context.frame_queue),
+            &mut *lock_state(&context.state)?,
+            params,
+        ),
+        "voice_filter.push_reference_frame" => {
+            handle_voice_filter_push_reference_frame(&mut *lock_state(&context.state)?, params)
+        }
+        "voice_filter.eq.set" => eq::handle_eq_set(&mut *lock_state(&context.state)?, params),
+        "voice_filter.eq.response" => eq::handle_eq_response(params),
+        "voice_filter.metrics" => {
+            handle_voice_filter_metrics(&mut *lock_state(&context.state)?, params)
+        }
+        "voice_filter.set_transmit" => {
+            handle_voice_filter_set_transmit(&mut *lock_state(&context.state)?, params)
+        }
+        "voice_filter.stop" => handle_voice_filter_stop(
+            Arc::clone(&context.frame_queue),
+            &mut *lock_state(&context.state)?,
+            params,
+        ),
+        _ => Err(format!("Unknown method: {method}")),
+    }
 }
 
 fn main() {
-    eprintln!("[capture-sidecar] starting");
-
     let stdin = io::stdin();
     let stdout = Arc::new(Mutex::new(io::stdout()));
+    logging::init(Arc::clone(&stdout));
+    crash::install_panic_hook();
+    log_info!(
+        "sidecar",
+        "starting",
+        "version" => env!("CARGO_PKG_VERSION"),
+        "protocolVersion" => PROTOCOL_VERSION,
+    );
+
+    // Probes the sound server off the request path and undoes routing a
+    // crashed sidecar left behind.
+    #[cfg(target_os = "linux")]
+    thread::spawn(|| {
+        if linux_app_audio::is_available() {
+            if let Err(error) = linux_app_audio::remove_stale_routes() {
+                log_warn!("capture", "failed to remove stale capture sinks", "error" => error);
+            }
+        }
+    });
+
     let frame_queue = Arc::new(FrameQueue::new(50));
     let frame_writer = start_frame_writer(Arc::clone(&stdout), Arc::clone(&frame_queue));
     let state = Arc::new(Mutex::new(SidecarState::default()));
     let app_audio_binary_egress = match start_app_audio_binary_egress() {
         Ok(app_audio_binary_egress) => {
-            eprintln!(
-                "[capture-sidecar] app-audio binary egress listening on 127.0.0.1:{}",
-                app_audio_binary_egress.port
+            log_info!(
+                "capture.binary_egress",
+                "app-audio binary egress listening",
+                "address" => format!("127.0.0.1:{}", app_audio_binary_egress.port),
             );
             Some(app_audio_binary_egress)
         }
         Err(error) => {
-            eprintln!("[capture-sidecar] app-audio binary egress unavailable: {error}");
-            None
-        }
-    };
-    let binary_ingress = match start_voice_filter_binary_ingress(
-        Arc::clone(&frame_queue),
-        Arc::clone(&state),
-    ) {
-        Ok(binary_ingress) => {
-            eprintln!(
-                "[capture-sidecar] voice filter binary ingress listening on 127.0.0.1:{}",
-                binary_ingress.port
+            log_warn!(
+                "capture.binary_egress",
+                "app-audio binary egress unavailable",
+                "error" => error,
             );
-            Some(binary_ingress)
-        }
-        Err(error) => {
-            eprintln!("[capture-sidecar] voice filter binary ingress unavailable: {error}");
             None
         }
     };
+    let binary_ingress =
+        match start_voice_filter_binary_ingress(Arc::clone(&frame_queue), Arc::clone(&state)) {
+            Ok(binary_ingress) => {
+                log_info!(
+                    "voice_filter.binary_ingress",
+                    "voice filter binary ingress listening",
+                    "address" => format!("127.0.0.1:{}", binary_ingress.port),
+                );
+                Some(binary_ingress)
+            }
+            Err(error) => {
+                log_warn!(
+                    "voice_filter.binary_ingress",
+                    "voice filter binary ingress unavailable",
+                    "error" => error,
+                );
+                None
+            }
+        };
+
+    let context = Arc::new(SidecarContext {
+        stdout: Arc::clone(&stdout),
+        frame_queue: Arc::clone(&frame_queue),
+        state: Arc::clone(&state),
+        app_audio_binary_egress_port: app_audio_binary_egress
+            .as_ref()
+            .map(|binary_egress| binary_egress.port),
+        app_audio_binary_stream: app_audio_binary_egress
+            .as_ref()
+            .map(|binary_egress| Arc::clone(&binary_egress.stream)),
+        voice_filter_binary_ingress_port: binary_ingress
+            .as_ref()
+            .map(|binary_ingress| binary_ingress.port),
+    });
+    let dispatcher = Dispatcher::new(
+        Arc::clone(&stdout),
+        Arc::new(
+            move |method: &str, params: Value, request: &RequestContext| {
+                crash::catch_panics(|| handle_request(&context, method, params, request))
+                    .unwrap_or_else(|panic| {
+                        // The handler may have been holding the state lock; the
+                        // sessions it guards remain usable, so clear the poison
+                        // instead of failing every later request.
+                        context.state.clear_poison();
+                        Err(panic.describe())
+                    })
+            },
+        ),
+    );
 
     for line in stdin.lock().lines() {
         let Ok(line) = line else {
@@ -3334,100 +4040,16 @@ fn main() {
         let request: SidecarRequest = match serde_json::from_str(&line) {
             Ok(parsed) => parsed,
             Err(error) => {
-                eprintln!("[capture-sidecar] invalid request json: {error}");
+                log_warn!("dispatch", "invalid request json", "error" => error.to_string());
                 continue;
             }
         };
 
-        let request_stdout = Arc::clone(&stdout);
-        let request_frame_queue = Arc::clone(&frame_queue);
-
-        let result = match request.method.as_str() {
-            "health.ping" => handle_health_ping(),
-            "capabilities.get" => handle_capabilities_get(),
-            "windows.resolve_source" => handle_windows_resolve_source(request.params),
-            "audio_targets.list" => handle_audio_targets_list(request.params),
-            "audio_capture.binary_egress_info" => match app_audio_binary_egress.as_ref() {
-                Some(app_audio_binary_egress) => {
-                    handle_audio_capture_binary_egress_info(app_audio_binary_egress)
-                }
-                None => Err("Binary app-audio egress is unavailable".to_string()),
-            },
-            "voice_filter.binary_ingress_info" => match binary_ingress.as_ref() {
-                Some(binary_ingress) => handle_voice_filter_binary_ingress_info(binary_ingress),
-                None => Err("Binary voice filter ingress is unavailable".to_string()),
-            },
-            "audio_capture.start" => match state.lock() {
-                Ok(mut state_lock) => handle_audio_capture_start(
-                    Arc::clone(&request_stdout),
-                    request_frame_queue,
-                    app_audio_binary_egress
-                        .as_ref()
-                        .map(|binary_egress| Arc::clone(&binary_egress.stream)),
-                    &mut state_lock,
-                    request.params,
-                ),
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "audio_capture.stop" => match state.lock() {
-                Ok(mut state_lock) => handle_audio_capture_stop(&mut state_lock, request.params),
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "push_keybinds.set" => match state.lock() {
-                Ok(mut state_lock) => {
-                    handle_push_keybinds_set(request_frame_queue.clone(), &mut state_lock, request.params)
-                }
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "mic_devices.list" => handle_mic_devices_list(),
-            "voice_filter.start_with_capture" => match state.lock() {
-                Ok(mut state_lock) => handle_voice_filter_start_with_capture(
-                    Arc::clone(&state),
-                    request_frame_queue.clone(),
-                    &mut state_lock,
-                    request.params,
-                ),
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "voice_filter.start" => match state.lock() {
-                Ok(mut state_lock) => {
-                    handle_voice_filter_start(request_frame_queue.clone(), &mut state_lock, request.params)
-                }
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "voice_filter.push_frame" => match state.lock() {
-                Ok(mut state_lock) => handle_voice_filter_push_frame(
-                    request_frame_queue.clone(),
-                    &mut state_lock,
-                    request.params,
-                ),
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "voice_filter.push_reference_frame" => match state.lock() {
-                Ok(mut state_lock) => {
-                    handle_voice_filter_push_reference_frame(&mut state_lock, request.params)
-                }
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            "voice_filter.stop" => match state.lock() {
-                Ok(mut state_lock) => {
-                    handle_voice_filter_stop(request_frame_queue.clone(), &mut state_lock, request.params)
-                }
-                Err(_) => Err("Sidecar state lock poisoned".to_string()),
-            },
-            _ => Err(format!("Unknown method: {}", request.method)),
-        };
-
-        if let Some(id) = request.id.as_deref() {
-            write_response(&request_stdout, id, result);
-        } else if let Err(error) = result {
-            eprintln!(
-                "[capture-sidecar] notification method={} failed: {}",
-                request.method, error
-            );
-        }
+        dispatcher.dispatch(request.id, request.method, request.params);
     }
 
+    dispatcher.shutdown();
+
     if let Some(app_audio_binary_egress) = app_audio_binary_egress {
         app_audio_binary_egress
             .stop_flag
@@ -3443,20 +4065,23 @@ fn main() {
     if let Ok(mut state_lock) = state.lock() {
         stop_capture_session(&mut state_lock, None);
         stop_push_keybind_watcher(&mut state_lock);
+        hotkeys::stop_hotkeys(&mut state_lock);
         stop_voice_filter_session(&mut state_lock, &frame_queue, None, "capture_stopped", None);
     } else {
-        eprintln!("[capture-sidecar] sidecar state lock poisoned during shutdown");
+        log_error!("sidecar", "sidecar state lock poisoned during shutdown");
     }
     frame_queue.close();
     let _ = frame_writer.join();
 
-    eprintln!("[capture-sidecar] stopping");
+    log_info!("sidecar", "stopping");
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        dedupe_window_entries_by_pid, parse_target_pid, parse_window_source_id, CaptureEndReason,
+        dedupe_window_entries_by_pid, deep_filter_layout, parse_target_pid, parse_window_source_id,
+        AutoGainControlMode, CaptureEndReason, ChannelMode, DspChain, ReduceMask,
+        VoiceFilterSession, VoiceFilterSessionOptions, VoiceFilterStrength,
     };
 
     #[test]
@@ -3485,9 +4110,72 @@ mod tests {
         assert_eq!(deduped.get(&200).map(String::as_str), Some("Other"));
     }
 
+    /// A session with an empty chain, so no model is loaded.
+    fn session_without_denoiser(channels: usize) -> VoiceFilterSession {
+        let options = VoiceFilterSessionOptions {
+            sample_rate: 48_000,
+            channels,
+            suppression_level: VoiceFilterStrength::Balanced,
+            channel_mode: ChannelMode::default(),
+            pre_filter: false,
+            noise_suppression: false,
+            auto_gain_control: false,
+            auto_gain_control_mode: AutoGainControlMode::Rms,
+            dereverb: None,
+            comfort_noise: None,
+            echo_cancellation: true,
+        };
+        let chain = DspChain::new(&[], options.sample_rate, channels, None);
+        VoiceFilterSession::new("session".to_string(), options, chain)
+    }
+
+    #[test]
+    fn maps_channel_modes_to_model_layouts() {
+        let layout = |channels, channel_mode| {
+            let (models, model_channels, mask) = deep_filter_layout(channels, channel_mode);
+            let mask = match mask {
+                ReduceMask::NONE => "none",
+                ReduceMask::MAX => "max",
+                ReduceMask::MEAN => "mean",
+            };
+            (models, model_channels, mask)
+        };
+
+        assert_eq!(layout(2, ChannelMode::Linked), (1, 2, "mean"));
+        assert_eq!(layout(2, ChannelMode::LinkedMax), (1, 2, "max"));
+        assert_eq!(layout(2, ChannelMode::Independent), (2, 1, "none"));
+        assert_eq!(layout(2, ChannelMode::Downmix), (1, 1, "none"));
+        for channel_mode in [
+            ChannelMode::Linked,
+            ChannelMode::LinkedMax,
+            ChannelMode::Independent,
+            ChannelMode::Downmix,
+        ] {
+            assert_eq!(layout(1, channel_mode), (1, 1, "none"));
+        }
+    }
+
+    #[test]
+    fn converts_echo_reference_layouts() {
+        let mut session = session_without_denoiser(2);
+
+        session.push_echo_reference_samples(&[0.1, 0.2], 2).unwrap();
+        session.push_echo_reference_samples(&[0.3], 1).unwrap();
+        // 5.1 output is downmixed.
+        session
+            .push_echo_reference_samples(&[0.5, 0.5, 0.5, 0.5, 0.5, 0.5], 6)
+            .unwrap();
+        assert_eq!(
+            session.echo_reference_interleaved,
+            [0.1, 0.2, 0.3, 0.3, 0.5, 0.5]
+        );
+        assert!(session.push_echo_reference_samples(&[0.0; 9], 9).is_err());
+    }
+
     #[test]
     fn maps_capture_end_reasons() {
         assert_eq!(CaptureEndReason::CaptureError.as_str(), "capture_error");
+        assert_eq!(CaptureEndReason::InternalError.as_str(), "internal_error");
         #[cfg(windows)]
         assert_eq!(CaptureEndReason::CaptureStopped.as_str(), "capture_stopped");
         #[cfg(windows)]
@@ -0,0 +1,43 @@
+//! Range checks shared by the option structs' `validate` methods, so every
+//! stage words its out-of-range errors the same way.
+
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+/// Fails with `<name> must be between <start> and <end><unit>.`; `unit`
+/// carries its own leading space (`" dB"`) or is empty for unitless params.
+pub(crate) fn check_range<T: PartialOrd + Display>(
+    name: &str,
+    value: T,
+    range: RangeInclusive<T>,
+    unit: &str,
+) -> Result<(), String> {
+    if range.contains(&value) {
+        return Ok(());
+    }
+
+    Err(format!(
+        "{name} must be between {} and {}{unit}.",
+        range.start(),
+        range.end()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_range;
+
+    #[test]
+    fn reports_the_range_and_unit() {
+        assert!(check_range("Ratio", 4.0, 1.0..=20.0, "").is_ok());
+        assert_eq!(
+            check_range("Threshold", 6.0, -60.0..=0.0, " dB").unwrap_err(),
+            "Threshold must be between -60 and 0 dB."
+        );
+        assert_eq!(
+            check_range("Harmonics", 9, 1..=8, "").unwrap_err(),
+            "Harmonics must be between 1 and 8."
+        );
+        assert!(check_range("Level", f32::NAN, -90.0..=-40.0, " dB").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Machine-readable description of the stdin/stdout protocol.
+//!
+//! `rpc.describe` returns JSON Schema for every method's params and result and
+//! for every event payload, generated from the same Rust types the handlers
+//! serialize, so the desktop app can check its TypeScript types against it.
+//! `rpc-schema.json` at the crate root is the checked-in copy for the current
+//! [`PROTOCOL_VERSION`]; a test fails when the schema changes without a bump.
+
+use crate::crash::SidecarFatalEvent;
+use crate::dispatch::{
+    CancelRequestParams, CancelRequestResult, RequestProgressEvent, CANCEL_REQUEST_METHOD,
+};
+use crate::eq::{EqResponseParams, EqResponseResult, SetEqParams, SetEqResult};
+use crate::hotkeys::{HotkeyStateEvent, HotkeyTriggeredEvent, SetHotkeysParams, SetHotkeysResult};
+use crate::logging::{LogRecordEvent, LogSettings, SetLogLevelParams};
+use crate::snapshot::{RestoreStateResult, StateSnapshot};
+use crate::transmit::TransmitStateEvent;
+use crate::{
+    AudioCaptureEndedEvent, AudioCaptureFrameEvent, BinaryTransportInfoResult, CapabilitiesResult,
+    EmptyParams, FrameAcceptedResult, HealthPingResult, ListMicDevicesParams, ListTargetsParams,
+    ListTargetsResult, MicCaptureStatusEvent, MicDevicesResult, PushKeybindStateEvent,
+    ResolveSourceParams, ResolveSourceResult, SetPushKeybindsParams, SetPushKeybindsResult,
+    SetVoiceFilterTransmitParams, SetVoiceFilterTransmitResult, StartAudioCaptureParams,
+    StartAudioCaptureResult, StartVoiceFilterParams, StartVoiceFilterResult,
+    StartVoiceFilterWithCaptureParams, StopAudioCaptureParams, StopVoiceFilterParams,
+    StoppedResult, VoiceFilterEndedEvent, VoiceFilterFrameEvent, VoiceFilterMetricsParams,
+    VoiceFilterMetricsResult, VoiceFilterPushFrameParams, VoiceFilterPushReferenceFrameParams,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+struct Describer {
+    generator: SchemaGenerator,
+    methods: Map<String, Value>,
+    events: Map<String, Value>,
+}
+
+impl Describer {
+    fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>()).unwrap_or(Value::Bool(true))
+    }
+
+    fn method<P: JsonSchema, R: JsonSchema>(&mut self, name: &str) {
+        let params = self.schema::<P>();
+        let result = self.schema::<R>();
+        self.methods.insert(
+            name.to_string(),
+            json!({ "params": params, "result": result }),
+        );
+    }
+
+    fn event<P: JsonSchema>(&mut self, name: &str) {
+        let params = self.schema::<P>();
+        self.events.insert(name.to_string(), params);
+    }
+}
+
+/// `rpc.describe`: the full protocol schema. Shared types are emitted once
+/// under `definitions` and referenced from the method and event entries.
+pub(crate) fn describe() -> Value {
+    let mut describer = Describer {
+        generator: SchemaSettings::draft07().into_generator(),
+        methods: Map::new(),
+        events: Map::new(),
+    };
+
+    describer.method::<EmptyParams, HealthPingResult>("health.ping");
+    describer.method::<EmptyParams, CapabilitiesResult>("capabilities.get");
+    describer.method::<EmptyParams, Value>("rpc.describe");
+    describer.method::<SetLogLevelParams, LogSettings>("log.set_level");
+    describer.method::<CancelRequestParams, CancelRequestResult>(CANCEL_REQUEST_METHOD);
+    describer.method::<ResolveSourceParams, ResolveSourceResult>("windows.resolve_source");
+    describer.method::<ListTargetsParams, ListTargetsResult>("audio_targets.list");
+    describer.method::<EmptyParams, BinaryTransportInfoResult>("audio_capture.binary_egress_info");
+    describer.method::<EmptyParams, BinaryTransportInfoResult>("voice_filter.binary_ingress_info");
+    describer.method::<StartAudioCaptureParams, StartAudioCaptureResult>("audio_capture.start");
+    describer.method::<StopAudioCaptureParams, StoppedResult>("audio_capture.stop");
+    describer.method::<SetPushKeybindsParams, SetPushKeybindsResult>("push_keybinds.set");
+    describer.method::<SetHotkeysParams, SetHotkeysResult>("hotkeys.set");
+    describer.method::<ListMicDevicesParams, MicDevicesResult>("mic_devices.list");
+    describer.method::<StartVoiceFilterWithCaptureParams, StartVoiceFilterResult>(
+        "voice_filter.start_with_capture",
+    );
+    describer.method::<StartVoiceFilterParams, StartVoiceFilterResult>("voice_filter.start");
+    describer.method::<VoiceFilterPushFrameParams, FrameAcceptedResult>("voice_filter.push_frame");
+    describer.method::<VoiceFilterPushReferenceFrameParams, FrameAcceptedResult>(
+        "voice_filter.push_reference_frame",
+    );
+    describer.method::<SetVoiceFilterTransmitParams, SetVoiceFilterTransmitResult>(
+        "voice_filter.set_transmit",
+    );
+    describer.method::<SetEqParams, SetEqResult>("voice_filter.eq.set");
+    describer.method::<EqResponseParams, EqResponseResult>("voice_filter.eq.response");
+    describer.method::<VoiceFilterMetricsParams, VoiceFilterMetricsResult>("voice_filter.metrics");
+    describer.method::<StopVoiceFilterParams, StoppedResult>("voice_filter.stop");
+    describer.method::<EmptyParams, StateSnapshot>("state.snapshot");
+    describer.method::<StateSnapshot, RestoreStateResult>("state.restore");
+
+    describer.event::<AudioCaptureFrameEvent>("audio_capture.frame");
+    describer.event::<AudioCaptureEndedEvent>("audio_capture.ended");
+    describer.event::<VoiceFilterFrameEvent>("voice_filter.frame");
+    describer.event::<VoiceFilterEndedEvent>("voice_filter.ended");
+    describer.event::<TransmitStateEvent<'static>>("voice_filter.transmit_state");
+    describer.event::<PushKeybindStateEvent>("push_keybind.state");
+    describer.event::<HotkeyTriggeredEvent<'static>>("hotkey.triggered");
+    describer.event::<HotkeyStateEvent<'static>>("hotkey.state");
+    describer.event::<MicCaptureStatusEvent>("mic_capture.status");
+    describer.event::<RequestProgressEvent<'static>>("request.progress");
+    describer.event::<LogRecordEvent<'static>>("log");
+    describer.event::<SidecarFatalEvent>("sidecar.fatal");
+
+    let definitions = describer.generator.take_definitions();
+
+    json!({
+        "protocolVersion": PROTOCOL_VERSION,
+        "minProtocolVersion": MIN_PROTOCOL_VERSION,
+        "methods": describer.methods,
+        "events": describer.events,
+        "definitions": definitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe;
+    use crate::PROTOCOL_VERSION;
+    use serde_json::Value;
+    use std::path::Path;
+
+    const SNAPSHOT_FILE: &str = "rpc-schema.json";
+    const UPDATE_ENV: &str = "UPDATE_RPC_SCHEMA";
+
+    fn without_version(schema: &Value) -> Value {
+        let mut schema = schema.clone();
+        if let Some(object) = schema.as_object_mut() {
+            object.remove("protocolVersion");
+        }
+        schema
+    }
+
+    #[test]
+    fn schema_changes_require_protocol_version_bump() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(SNAPSHOT_FILE);
+        let current = describe();
+        let snapshot = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+            .unwrap_or(Value::Null);
+
+        if snapshot == current {
+            return;
+        }
+
+        let snapshot_version = snapshot.get("protocolVersion").and_then(Value::as_u64);
+        assert!(
+            snapshot_version != Some(u64::from(PROTOCOL_VERSION))
+                || without_version(&snapshot) == without_version(&current),
+            "the RPC schema changed but PROTOCOL_VERSION is still {PROTOCOL_VERSION}; bump it \
+             in src/main.rs and regenerate {SNAPSHOT_FILE} with `{UPDATE_ENV}=1 cargo test`"
+        );
+
+        if std::env::var_os(UPDATE_ENV).is_some() {
+            let serialized = serde_json::to_string_pretty(&current).unwrap();
+            std::fs::write(&path, format!("{serialized}\n")).unwrap();
+            return;
+        }
+
+        panic!(
+            "{SNAPSHOT_FILE} is out of date for protocol version {PROTOCOL_VERSION}; regenerate \
+             it with `{UPDATE_ENV}=1 cargo test`"
+        );
+    }
+}
@@ -0,0 +1,333 @@
+//! Session snapshot and restore across sidecar restarts.
+//!
+//! `state.snapshot` describes every active session, the push keybinds, the
+//! hotkeys and the log configuration in a form `state.restore` accepts as-is, so a restarted
+//! sidecar can be brought back in one round trip. Restored sessions get new
+//! ids; the result maps each old id to its replacement.
+
+use crate::comfort_noise::ComfortNoiseOptions;
+use crate::dereverb::DereverbOptions;
+use crate::dispatch::RequestContext;
+use crate::dsp::{AutoGainControlMode, ChannelMode, StageDescriptor};
+use crate::hotkeys::{self, SetHotkeysParams, SetHotkeysResult};
+use crate::limiter::LimiterOptions;
+use crate::logging::{self, LogSettings};
+use crate::transmit::TransmitOptions;
+use crate::{
+    lock_state, result_value, set_push_keybinds, start_audio_capture, start_voice_filter,
+    start_voice_filter_with_capture, CaptureTarget, MicCaptureSource, SetPushKeybindsParams,
+    SetPushKeybindsResult, SidecarContext, SidecarState, StartAudioCaptureParams,
+    StartAudioCaptureResult, StartVoiceFilterParams, StartVoiceFilterResult,
+    StartVoiceFilterWithCaptureParams, VoiceFilterStrength, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StateSnapshot {
+    protocol_version: u32,
+    #[serde(default)]
+    audio_capture: Option<AudioCaptureSnapshot>,
+    #[serde(default)]
+    voice_filter: Option<VoiceFilterSnapshot>,
+    #[serde(default)]
+    push_keybinds: SetPushKeybindsParams,
+    #[serde(default)]
+    hotkeys: SetHotkeysParams,
+    #[serde(default)]
+    log: Option<LogSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AudioCaptureSnapshot {
+    session_id: String,
+    target_id: String,
+    #[serde(default)]
+    exclude_own_playback: bool,
+    #[serde(default)]
+    limiter: Option<LimiterOptions>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct VoiceFilterSnapshot {
+    session_id: String,
+    sample_rate: usize,
+    channels: usize,
+    suppression_level: VoiceFilterStrength,
+    #[serde(default)]
+    channel_mode: ChannelMode,
+    #[serde(default)]
+    pre_filter: bool,
+    noise_suppression: bool,
+    auto_gain_control: bool,
+    #[serde(default)]
+    auto_gain_control_mode: AutoGainControlMode,
+    #[serde(default)]
+    dereverb: Option<DereverbOptions>,
+    #[serde(default)]
+    comfort_noise: Option<ComfortNoiseOptions>,
+    echo_cancellation: bool,
+    /// Set when the sidecar captures the microphone itself
+    /// (`voice_filter.start_with_capture`).
+    #[serde(default)]
+    mic_capture: Option<MicCaptureSource>,
+    #[serde(default)]
+    transmit: Option<TransmitOptions>,
+    #[serde(default)]
+    chain: Option<Vec<StageDescriptor>>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RestoreStateResult {
+    /// Old session id to the id of the session that replaces it.
+    session_ids: BTreeMap<String, String>,
+    audio_capture: Option<StartAudioCaptureResult>,
+    voice_filter: Option<StartVoiceFilterResult>,
+    push_keybinds: SetPushKeybindsResult,
+    hotkeys: SetHotkeysResult,
+    /// Parts of the snapshot that could not be restored; the rest still is.
+    errors: Vec<String>,
+    protocol_version: u32,
+}
+
+pub(crate) fn snapshot_state(state: &SidecarState) -> StateSnapshot {
+    let audio_capture = state
+        .capture_session
+        .as_ref()
+        .filter(|session| !session.handle.is_finished())
+        .map(|session| AudioCaptureSnapshot {
+            session_id: session.session_id.clone(),
+            target_id: session.target_id.clone(),
+            exclude_own_playback: matches!(
+                session.target,
+                CaptureTarget::System {
+                    exclude_own_playback: true
+                }
+            ),
+            limiter: session.limiter,
+        });
+
+    let voice_filter = state
+        .voice_filter_session
+        .as_ref()
+        .map(|session| VoiceFilterSnapshot {
+            session_id: session.session_id.clone(),
+            sample_rate: session.options.sample_rate,
+            channels: session.options.channels,
+            suppression_level: session.options.suppression_level,
+            channel_mode: session.options.channel_mode,
+            pre_filter: session.options.pre_filter,
+            noise_suppression: session.options.noise_suppression,
+            auto_gain_control: session.options.auto_gain_control,
+            auto_gain_control_mode: session.options.auto_gain_control_mode,
+            dereverb: session.options.dereverb,
+            comfort_noise: session.options.comfort_noise,
+            echo_cancellation: session.options.echo_cancellation,
+            mic_capture: session.mic_capture.clone(),
+            transmit: session.transmit_gate.as_ref().map(|gate| gate.options()),
+            chain: Some(session.chain.descriptors()),
+        });
+
+    StateSnapshot {
+        protocol_version: PROTOCOL_VERSION,
+        audio_capture,
+        voice_filter,
+        push_keybinds: state.push_keybinds.clone(),
+        hotkeys: state.hotkeys.clone(),
+        log: Some(logging::current_settings()),
+    }
+}
+
+pub(crate) fn handle_state_snapshot(state: &SidecarState) -> Result<Value, String> {
+    result_value(snapshot_state(state))
+}
+
+fn restore_voice_filter(
+    context: &SidecarContext,
+    snapshot: VoiceFilterSnapshot,
+    request: &RequestContext,
+) -> Result<StartVoiceFilterResult, String> {
+    match snapshot.mic_capture {
+        Some(mic_capture) => start_voice_filter_with_capture(
+            &context.state,
+            Arc::clone(&context.frame_queue),
+            StartVoiceFilterWithCaptureParams {
+                sample_rate: snapshot.sample_rate,
+                channels: snapshot.channels,
+                suppression_level: snapshot.suppression_level,
+                channel_mode: Some(snapshot.channel_mode),
+                pre_filter: Some(snapshot.pre_filter),
+                noise_suppression: Some(snapshot.noise_suppression),
+                auto_gain_control: Some(snapshot.auto_gain_control),
+                auto_gain_control_mode: Some(snapshot.auto_gain_control_mode),
+                dereverb: snapshot.dereverb,
+                comfort_noise: snapshot.comfort_noise,
+                echo_cancellation: Some(snapshot.echo_cancellation),
+                device_id: mic_capture.device_id,
+                transmit: snapshot.transmit,
+                chain: snapshot.chain.clone(),
+            },
+            request,
+        ),
+        None => start_voice_filter(
+            Arc::clone(&context.frame_queue),
+            &context.state,
+            StartVoiceFilterParams {
+                sample_rate: snapshot.sample_rate,
+                channels: snapshot.channels,
+                suppression_level: snapshot.suppression_level,
+                channel_mode: Some(snapshot.channel_mode),
+                pre_filter: Some(snapshot.pre_filter),
+                noise_suppression: Some(snapshot.noise_suppression),
+                auto_gain_control: Some(snapshot.auto_gain_control),
+                auto_gain_control_mode: Some(snapshot.auto_gain_control_mode),
+                dereverb: snapshot.dereverb,
+                comfort_noise: snapshot.comfort_noise,
+                echo_cancellation: Some(snapshot.echo_cancellation),
+                transmit: snapshot.transmit,
+                chain: snapshot.chain.clone(),
+            },
+            request,
+        ),
+    }
+}
+
+/// `state.restore`: recreates everything in a `state.snapshot` result,
+/// replacing whatever is currently running.
+pub(crate) fn handle_state_restore(
+    context: &SidecarContext,
+    params: Value,
+    request: &RequestContext,
+) -> Result<Value, String> {
+    let snapshot: StateSnapshot =
+        serde_json::from_value(params).map_err(|error| format!("invalid params: {error}"))?;
+
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&snapshot.protocol_version) {
+        return Err(format!(
+            "Unsupported snapshot protocol version {}",
+            snapshot.protocol_version
+        ));
+    }
+
+    if let Some(settings) = snapshot.log {
+        logging::apply_settings(settings);
+    }
+
+    let mut session_ids = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    let push_keybinds = set_push_keybinds(
+        Arc::clone(&context.frame_queue),
+        &mut *lock_state(&context.state)?,
+        snapshot.push_keybinds,
+    );
+    let hotkeys = hotkeys::set_hotkeys(
+        Arc::clone(&context.frame_queue),
+        &mut *lock_state(&context.state)?,
+        snapshot.hotkeys,
+    );
+
+    let audio_capture = match snapshot.audio_capture {
+        Some(capture) => {
+            let started = start_audio_capture(
+                Arc::clone(&context.stdout),
+                Arc::clone(&context.frame_queue),
+                context.app_audio_binary_stream.clone(),
+                &mut *lock_state(&context.state)?,
+                StartAudioCaptureParams {
+                    source_id: None,
+                    app_audio_target_id: Some(capture.target_id),
+                    exclude_own_playback: capture.exclude_own_playback,
+                    limiter: capture.limiter,
+                },
+            );
+
+            match started {
+                Ok(started) => {
+                    session_ids.insert(capture.session_id, started.session_id.clone());
+                    Some(started)
+                }
+                Err(error) => {
+                    errors.push(format!("audio_capture: {error}"));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    let voice_filter = match snapshot.voice_filter {
+        Some(voice_filter) => {
+            let old_session_id = voice_filter.session_id.clone();
+            match restore_voice_filter(context, voice_filter, request) {
+                Ok(started) => {
+                    session_ids.insert(old_session_id, started.session_id.clone());
+                    Some(started)
+                }
+                Err(error) => {
+                    errors.push(format!("voice_filter: {error}"));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    log_info!(
+        "sidecar",
+        "state restored",
+        "sessions" => session_ids.len(),
+        "errors" => &errors,
+    );
+
+    result_value(RestoreStateResult {
+        session_ids,
+        audio_capture,
+        voice_filter,
+        push_keybinds,
+        hotkeys,
+        errors,
+        protocol_version: PROTOCOL_VERSION,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{snapshot_state, StateSnapshot};
+    use crate::{SetPushKeybindsParams, SidecarState, PROTOCOL_VERSION};
+
+    #[test]
+    fn snapshot_round_trips_through_restore_params() {
+        let state = SidecarState {
+            push_keybinds: SetPushKeybindsParams {
+                push_to_talk_keybind: Some("Ctrl+KeyT".to_string()),
+                push_to_mute_keybind: None,
+            },
+            ..SidecarState::default()
+        };
+
+        let serialized = serde_json::to_value(snapshot_state(&state)).unwrap();
+        assert_eq!(serialized["protocolVersion"], PROTOCOL_VERSION);
+        assert_eq!(serialized["pushKeybinds"]["pushToTalkKeybind"], "Ctrl+KeyT");
+        assert!(serialized["voiceFilter"].is_null());
+
+        let restored: StateSnapshot = serde_json::from_value(serialized).unwrap();
+        assert_eq!(
+            restored.push_keybinds.push_to_talk_keybind.as_deref(),
+            Some("Ctrl+KeyT")
+        );
+
+        // Every section is optional.
+        let minimal: StateSnapshot =
+            serde_json::from_value(serde_json::json!({ "protocolVersion": 1 })).unwrap();
+        assert!(minimal.audio_capture.is_none());
+        assert!(minimal.log.is_none());
+    }
+}
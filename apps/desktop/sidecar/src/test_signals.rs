@@ -0,0 +1,39 @@
+//! Signals and helpers shared by the DSP stage tests.
+
+use serde::de::DeserializeOwned;
+use std::f32::consts::PI;
+
+/// Deterministic white noise in -1..1.
+pub(crate) fn noise(length: usize, mut seed: u32) -> Vec<f32> {
+    (0..length)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 23) as f32 - 1.0
+        })
+        .collect()
+}
+
+pub(crate) fn sine(frequency: f32, amplitude: f32, sample_rate: usize, frames: usize) -> Vec<f32> {
+    (0..frames)
+        .map(|index| amplitude * (2.0 * PI * frequency * index as f32 / sample_rate as f32).sin())
+        .collect()
+}
+
+pub(crate) fn peak(samples: &[f32]) -> f32 {
+    samples
+        .iter()
+        .fold(0.0_f32, |peak, sample| peak.max(sample.abs()))
+}
+
+/// Parses stage options from their JSON params, the way `voice_filter.start`
+/// receives them, and checks they pass `validate`.
+pub(crate) fn options<T: DeserializeOwned>(
+    params: serde_json::Value,
+    validate: impl Fn(&T) -> Result<(), String>,
+) -> T {
+    let options = serde_json::from_value(params).unwrap();
+    if let Err(error) = validate(&options) {
+        panic!("invalid test options: {error}");
+    }
+    options
+}
@@ -0,0 +1,436 @@
+//! Keyboard and mouse click suppression for the voice filter chain.
+//!
+//! DeepFilterNet removes stationary noise but lets mechanical clicks through,
+//! and they are loud enough to open the gate. A click is an impulsive onset
+//! that is over within a few milliseconds, has much of its energy up high and
+//! no pitch; speech onsets fail at least one of those. The stage delays the
+//! signal long enough to judge each onset before it is output and ducks the
+//! ones that pass.
+//!
+//! Key and button presses seen by the keybind watcher make onsets shortly
+//! after them count at a lower level. The watcher only runs while push
+//! keybinds or hotkeys are registered, so without them there are no hints.
+//! On Windows, where it has to poll every key for them, it only does so while
+//! a running suppressor uses hints.
+
+use crate::filters::{Biquad, BiquadFilter};
+use crate::params::check_range;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+const DEFAULT_ATTENUATION_DB: f32 = 24.0;
+const ATTENUATION_RANGE_DB: RangeInclusive<f32> = 0.0..=60.0;
+
+/// How far ahead the stage looks; an onset is judged on this much of it.
+const LOOKAHEAD_MS: f32 = 12.0;
+/// Gain ramp ahead of a click, taken out of the lookahead.
+const RAMP_MS: f32 = 1.0;
+/// Suppression kept up after the part of the click that was judged.
+const HOLD_MS: f32 = 4.0;
+const RELEASE_MS: f32 = 10.0;
+
+/// Envelopes the onset detector compares: the click itself and the
+/// background it stands out from.
+const FAST_ENVELOPE_MS: f32 = 1.0;
+const SLOW_ENVELOPE_MS: f32 = 100.0;
+const ONSET_DB: f32 = 15.0;
+/// Onset level within `KEY_HINT_MS` of a key or button press.
+const HINTED_ONSET_DB: f32 = 9.0;
+const KEY_HINT_MS: u64 = 250;
+/// Onsets below -60 dBFS are too quiet to matter.
+const MIN_ONSET_POWER: f32 = 1e-6;
+
+/// A click has decayed this far by the end of the lookahead...
+const DECAY_DB: f32 = 10.0;
+const ONSET_WINDOW_MS: f32 = 2.0;
+const TAIL_WINDOW_MS: f32 = 3.0;
+/// ...has at least this share of its energy above `HIGH_BAND_HZ`...
+const HIGH_BAND_HZ: f32 = 3_000.0;
+const MIN_HIGH_BAND_SHARE: f32 = 0.25;
+/// ...and no periodicity in the pitch range of voices.
+const MIN_PITCH_HZ: f32 = 100.0;
+const MAX_PITCH_HZ: f32 = 400.0;
+const MAX_PERIODICITY: f32 = 0.6;
+
+/// Milliseconds since `INPUT_CLOCK` of the last press, plus one; zero before
+/// any.
+static LAST_INPUT_PRESS: AtomicU64 = AtomicU64::new(0);
+static INPUT_CLOCK: OnceLock<Instant> = OnceLock::new();
+
+fn input_clock_ms() -> u64 {
+    INPUT_CLOCK.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Suppressors alive with key hints on, see [`KeyHintListener`].
+static KEY_HINT_LISTENERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether any suppressor would use key and button presses right now.
+#[cfg(windows)]
+pub(crate) fn key_hints_wanted() -> bool {
+    KEY_HINT_LISTENERS.load(Ordering::Relaxed) > 0
+}
+
+/// Counts a suppressor in [`KEY_HINT_LISTENERS`] for as long as it lives.
+struct KeyHintListener;
+
+impl KeyHintListener {
+    fn register() -> Self {
+        KEY_HINT_LISTENERS.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for KeyHintListener {
+    fn drop(&mut self) {
+        KEY_HINT_LISTENERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records a key or mouse button press, from any keybind watcher.
+pub(crate) fn note_input_press() {
+    LAST_INPUT_PRESS.store(input_clock_ms() + 1, Ordering::Relaxed);
+}
+
+fn recent_input_press() -> bool {
+    match LAST_INPUT_PRESS.load(Ordering::Relaxed) {
+        0 => false,
+        pressed => input_clock_ms() + 1 - pressed <= KEY_HINT_MS,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TransientSuppressorOptions {
+    /// How far clicks are turned down.
+    #[serde(default = "default_attenuation_db")]
+    attenuation_db: f32,
+    /// Uses key and mouse button presses as hints.
+    #[serde(default = "default_key_hints")]
+    key_hints: bool,
+}
+
+fn default_attenuation_db() -> f32 {
+    DEFAULT_ATTENUATION_DB
+}
+
+fn default_key_hints() -> bool {
+    true
+}
+
+impl TransientSuppressorOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range(
+            "Transient attenuation",
+            self.attenuation_db,
+            ATTENUATION_RANGE_DB,
+            " dB",
+        )
+    }
+
+    /// The same options for a bypassed stage, which has no use for hints.
+    pub(crate) fn without_key_hints(self) -> Self {
+        Self {
+            key_hints: false,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TransientMetrics {
+    /// Clicks suppressed since the session started.
+    suppressed_events: u64,
+}
+
+fn frames(milliseconds: f32, sample_rate: usize) -> usize {
+    ((milliseconds * sample_rate as f32 / 1_000.0).round() as usize).max(1)
+}
+
+fn smoothing_coeff(milliseconds: f32, sample_rate: usize) -> f32 {
+    (-1_000.0 / (milliseconds * sample_rate as f32)).exp()
+}
+
+fn power(samples: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = samples.fold((0.0, 0), |(sum, count), sample| {
+        (sum + sample * sample, count + 1)
+    });
+    sum / count.max(1) as f32
+}
+
+pub(crate) struct TransientSuppressor {
+    channels: usize,
+    attenuation: f32,
+    key_hints: Option<KeyHintListener>,
+    lookahead_frames: usize,
+    /// Frames of an onset seen before it is judged; the rest of the
+    /// lookahead is left for the gain ramp.
+    judged_frames: usize,
+    onset_frames: usize,
+    tail_frames: usize,
+    min_lag: usize,
+    max_lag: usize,
+    hold_frames: usize,
+    high_pass: BiquadFilter,
+    fast_coeff: f32,
+    slow_coeff: f32,
+    fast_power: f32,
+    slow_power: f32,
+    /// Mono input and its high band, newest last, covering the judged part
+    /// of an onset and the pitch lags before it.
+    history: VecDeque<f32>,
+    high_history: VecDeque<f32>,
+    /// Frames since the onset being judged, once there is one.
+    pending_onset: Option<usize>,
+    /// Frames the output stays ducked for.
+    suppress_frames: usize,
+    gain: f32,
+    attack_coeff: f32,
+    release_coeff: f32,
+    delay: VecDeque<f32>,
+    suppressed_events: u64,
+}
+
+impl TransientSuppressor {
+    pub(crate) fn new(
+        options: TransientSuppressorOptions,
+        sample_rate: usize,
+        channels: usize,
+    ) -> Self {
+        let channels = channels.max(1);
+        let lookahead_frames = frames(LOOKAHEAD_MS, sample_rate);
+        let max_lag = frames(1_000.0 / MIN_PITCH_HZ, sample_rate);
+        let history_frames = lookahead_frames + max_lag;
+
+        Self {
+            channels,
+            attenuation: 10.0_f32.powf(-options.attenuation_db / 20.0),
+            key_hints: options.key_hints.then(KeyHintListener::register),
+            lookahead_frames,
+            judged_frames: lookahead_frames.saturating_sub(frames(RAMP_MS, sample_rate)),
+            onset_frames: frames(ONSET_WINDOW_MS, sample_rate),
+            tail_frames: frames(TAIL_WINDOW_MS, sample_rate),
+            min_lag: frames(1_000.0 / MAX_PITCH_HZ, sample_rate),
+            max_lag,
+            hold_frames: lookahead_frames + frames(HOLD_MS, sample_rate),
+            high_pass: BiquadFilter::new(
+                Biquad::high_pass(sample_rate as f32, HIGH_BAND_HZ, FRAC_1_SQRT_2),
+                1,
+            ),
+            fast_coeff: smoothing_coeff(FAST_ENVELOPE_MS, sample_rate),
+            slow_coeff: smoothing_coeff(SLOW_ENVELOPE_MS, sample_rate),
+            fast_power: 0.0,
+            slow_power: 0.0,
+            history: VecDeque::from(vec![0.0; history_frames]),
+            high_history: VecDeque::from(vec![0.0; history_frames]),
+            pending_onset: None,
+            suppress_frames: 0,
+            gain: 1.0,
+            attack_coeff: smoothing_coeff(RAMP_MS / 4.0, sample_rate),
+            release_coeff: smoothing_coeff(RELEASE_MS, sample_rate),
+            delay: VecDeque::from(vec![0.0; lookahead_frames * channels]),
+            suppressed_events: 0,
+        }
+    }
+
+    /// Frames by which the output lags the input.
+    pub(crate) fn latency_frames(&self) -> usize {
+        self.lookahead_frames
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let onset_power_ratio = if self.key_hints.is_some() && recent_input_press() {
+            10.0_f32.powf(HINTED_ONSET_DB / 10.0)
+        } else {
+            10.0_f32.powf(ONSET_DB / 10.0)
+        };
+        let judged_frames = self.judged_frames;
+
+        for frame in samples.chunks_mut(self.channels) {
+            let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+            let mut high = [mono];
+            self.high_pass.process(&mut high);
+            self.history.pop_front();
+            self.history.push_back(mono);
+            self.high_history.pop_front();
+            self.high_history.push_back(high[0]);
+
+            let power = mono * mono;
+            self.fast_power = self.fast_power * self.fast_coeff + power * (1.0 - self.fast_coeff);
+            match &mut self.pending_onset {
+                Some(elapsed) => {
+                    *elapsed += 1;
+                    if *elapsed >= judged_frames {
+                        self.pending_onset = None;
+                        if self.is_click(judged_frames) {
+                            self.suppress_frames = self.hold_frames;
+                            self.suppressed_events += 1;
+                        }
+                    }
+                }
+                None if self.fast_power > self.slow_power * onset_power_ratio
+                    && self.fast_power > MIN_ONSET_POWER =>
+                {
+                    self.pending_onset = Some(0);
+                }
+                None => {}
+            }
+            // The background stands still while a click is ducked, so clicks
+            // in quick succession are caught as well.
+            if self.suppress_frames == 0 {
+                self.slow_power =
+                    self.slow_power * self.slow_coeff + power * (1.0 - self.slow_coeff);
+            }
+
+            let (target, coeff) = if self.suppress_frames > 0 {
+                self.suppress_frames -= 1;
+                (self.attenuation, self.attack_coeff)
+            } else {
+                (1.0, self.release_coeff)
+            };
+            self.gain = self.gain * coeff + target * (1.0 - coeff);
+
+            for sample in frame.iter_mut() {
+                self.delay.push_back(*sample);
+                *sample = self.delay.pop_front().unwrap_or(0.0) * self.gain;
+            }
+        }
+    }
+
+    /// Judges the onset `judged_frames` frames back from the newest one.
+    fn is_click(&self, judged_frames: usize) -> bool {
+        let start = self.history.len() - judged_frames;
+        let onset = self.history.range(start..);
+
+        let onset_power = power(onset.clone().take(self.onset_frames).copied());
+        let tail_power = power(
+            self.history
+                .range(self.history.len() - self.tail_frames..)
+                .copied(),
+        );
+        if tail_power > onset_power * 10.0_f32.powf(-DECAY_DB / 10.0) {
+            return false;
+        }
+
+        let total_power = power(onset.clone().copied());
+        let high_power = power(self.high_history.range(start..).copied());
+        if high_power < total_power * MIN_HIGH_BAND_SHARE {
+            return false;
+        }
+
+        let periodicity = (self.min_lag..=self.max_lag)
+            .map(|lag| {
+                let (mut cross, mut current, mut lagged) = (0.0_f32, 0.0_f32, 0.0_f32);
+                for index in start..self.history.len() {
+                    let (x, y) = (self.history[index], self.history[index - lag]);
+                    cross += x * y;
+                    current += x * x;
+                    lagged += y * y;
+                }
+                cross / (current * lagged).sqrt().max(f32::MIN_POSITIVE)
+            })
+            .fold(0.0_f32, f32::max);
+
+        periodicity < MAX_PERIODICITY
+    }
+
+    pub(crate) fn metrics(&self) -> TransientMetrics {
+        TransientMetrics {
+            suppressed_events: self.suppressed_events,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{note_input_press, TransientSuppressor, TransientSuppressorOptions};
+    use crate::test_signals::{noise, options, peak};
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: usize = 48_000;
+    const CLICK: usize = 9_600;
+
+    fn build(key_hints: bool) -> TransientSuppressor {
+        let options = options(
+            serde_json::json!({ "keyHints": key_hints }),
+            TransientSuppressorOptions::validate,
+        );
+        TransientSuppressor::new(options, SAMPLE_RATE, 1)
+    }
+
+    /// 200 ms of `background` noise with a click of `amplitude` halfway
+    /// through, decaying within a few milliseconds.
+    fn click(background: f32, amplitude: f32) -> Vec<f32> {
+        let mut samples: Vec<f32> = noise(19_200, 1)
+            .iter()
+            .map(|sample| sample * background)
+            .collect();
+        for (index, sample) in noise(480, 2).iter().enumerate() {
+            samples[CLICK + index] += amplitude * sample * (-(index as f32) / 48.0).exp();
+        }
+        samples
+    }
+
+    #[test]
+    fn ducks_clicks_before_they_are_output() {
+        let mut suppressor = build(false);
+        let latency = suppressor.latency_frames();
+        let input = click(1e-3, 0.5);
+        let mut samples = input.clone();
+        // Blocks of 10 ms, like the capture path delivers.
+        for block in samples.chunks_mut(480) {
+            suppressor.process(block);
+        }
+
+        assert_eq!(suppressor.metrics().suppressed_events, 1);
+        let onset = CLICK + latency;
+        assert!(peak(&samples[onset..onset + 480]) < peak(&input[CLICK..CLICK + 480]) * 0.1);
+        // The background before and after the click is left alone.
+        assert_eq!(samples[latency..CLICK], input[..CLICK - latency]);
+        let after = CLICK + 3_200;
+        assert!(
+            (peak(&samples[after + latency..]) - peak(&input[after..19_200 - latency])).abs()
+                < 1e-4
+        );
+
+        // A click 12 dB above the background only counts after a key press.
+        let quiet = click(0.02, 0.2);
+        let mut unhinted = build(true);
+        unhinted.process(&mut quiet.clone());
+        assert_eq!(unhinted.metrics().suppressed_events, 0);
+        let mut hinted = build(true);
+        note_input_press();
+        hinted.process(&mut quiet.clone());
+        assert_eq!(hinted.metrics().suppressed_events, 1);
+    }
+
+    #[test]
+    fn leaves_voiced_onsets_alone() {
+        let mut suppressor = build(false);
+        // A 150 Hz voice with bright harmonics starting abruptly.
+        let mut samples = vec![0.0_f32; 4_800];
+        samples.extend((0..9_600).map(|index| {
+            (1..20)
+                .map(|harmonic| {
+                    let phase = 2.0 * PI * 150.0 * harmonic as f32 * index as f32;
+                    0.05 * (phase / SAMPLE_RATE as f32).sin()
+                })
+                .sum::<f32>()
+        }));
+        let input = samples.clone();
+        suppressor.process(&mut samples);
+
+        assert_eq!(suppressor.metrics().suppressed_events, 0);
+        let latency = suppressor.latency_frames();
+        assert!(samples[latency..]
+            .iter()
+            .zip(&input)
+            .all(|(output, input)| (output - input).abs() < 1e-5));
+    }
+}
@@ -0,0 +1,339 @@
+//! Transmit gating for voice filter sessions.
+//!
+//! By default the renderer mutes its track itself on `push_keybind.state`
+//! events, which cuts the last syllable by however long the round trip takes.
+//! A session started with `transmit` options decides instead: frames it should
+//! not send are faded out and zeroed before `voice_filter.frame` is emitted,
+//! and every change is reported as `voice_filter.transmit_state`.
+
+use crate::params::check_range;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const DEFAULT_RELEASE_DELAY_MS: u32 = 200;
+const DEFAULT_VOICE_ACTIVATION_THRESHOLD_DB: f32 = -45.0;
+const RELEASE_DELAY_RANGE_MS: RangeInclusive<u32> = 0..=5_000;
+const VOICE_ACTIVATION_THRESHOLD_RANGE_DB: RangeInclusive<f32> = -100.0..=0.0;
+/// Length of the ramp when transmission starts or stops, so the edges do not
+/// click.
+const FADE_MS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TransmitMode {
+    /// Always transmit unless push-to-mute is held.
+    #[default]
+    Continuous,
+    /// Transmit while the global push-to-talk keybind is held.
+    PushToTalk,
+    /// Transmit while the processed signal is above the threshold.
+    VoiceActivation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TransmitOptions {
+    #[serde(default)]
+    mode: TransmitMode,
+    /// How long transmission continues after push-to-talk is released or the
+    /// voice drops below the threshold.
+    #[serde(default = "default_release_delay_ms")]
+    release_delay_ms: u32,
+    /// Frame level in dBFS that counts as voice in `voiceActivation` mode.
+    #[serde(default = "default_voice_activation_threshold_db")]
+    voice_activation_threshold_db: f32,
+}
+
+impl TransmitOptions {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        check_range(
+            "Transmit release delay",
+            self.release_delay_ms,
+            RELEASE_DELAY_RANGE_MS,
+            " ms",
+        )?;
+        check_range(
+            "Voice activation threshold",
+            self.voice_activation_threshold_db,
+            VOICE_ACTIVATION_THRESHOLD_RANGE_DB,
+            " dBFS",
+        )
+    }
+}
+
+fn default_release_delay_ms() -> u32 {
+    DEFAULT_RELEASE_DELAY_MS
+}
+
+fn default_voice_activation_threshold_db() -> f32 {
+    DEFAULT_VOICE_ACTIVATION_THRESHOLD_DB
+}
+
+/// Held state of the global push keybinds, written by the keybind watcher and
+/// read for every voice filter frame.
+#[derive(Debug, Default)]
+pub(crate) struct PushKeybindStates {
+    pub(crate) talk: AtomicBool,
+    pub(crate) mute: AtomicBool,
+}
+
+impl PushKeybindStates {
+    pub(crate) fn load(&self) -> PushKeys {
+        PushKeys {
+            talk: self.talk.load(Ordering::Relaxed),
+            mute: self.mute.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.talk.store(false, Ordering::Relaxed);
+        self.mute.store(false, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct PushKeys {
+    pub(crate) talk: bool,
+    pub(crate) mute: bool,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TransmitStateEvent<'a> {
+    pub(crate) session_id: &'a str,
+    pub(crate) transmitting: bool,
+}
+
+pub(crate) struct TransmitGate {
+    options: TransmitOptions,
+    sample_rate: usize,
+    transmitting: bool,
+    /// Frames left before a released push-to-talk key or a pause in speech
+    /// stops transmission.
+    hold_frames_remaining: usize,
+    gain: f32,
+}
+
+impl TransmitGate {
+    pub(crate) fn new(options: TransmitOptions, sample_rate: usize) -> Self {
+        Self {
+            options,
+            sample_rate,
+            transmitting: false,
+            hold_frames_remaining: 0,
+            gain: 0.0,
+        }
+    }
+
+    pub(crate) fn options(&self) -> TransmitOptions {
+        self.options
+    }
+
+    /// Switches to new options without resetting the current state, so a
+    /// change mid-sentence fades like any other transition.
+    pub(crate) fn set_options(&mut self, options: TransmitOptions) {
+        self.options = options;
+        self.hold_frames_remaining = self.hold_frames_remaining.min(self.release_delay_frames());
+    }
+
+    pub(crate) fn is_transmitting(&self) -> bool {
+        self.transmitting
+    }
+
+    fn release_delay_frames(&self) -> usize {
+        self.options.release_delay_ms as usize * self.sample_rate / 1_000
+    }
+
+    /// Decides whether `samples` is sent and fades or zeroes it accordingly.
+    /// Returns the new state when it changed. Push-to-mute wins over every
+    /// mode.
+    pub(crate) fn process(
+        &mut self,
+        samples: &mut [f32],
+        channels: usize,
+        keys: PushKeys,
+    ) -> Option<bool> {
+        if channels == 0 {
+            return None;
+        }
+
+        let frame_count = samples.len() / channels;
+        let wants_transmit = match self.options.mode {
+            TransmitMode::Continuous => true,
+            TransmitMode::PushToTalk => keys.talk,
+            TransmitMode::VoiceActivation => {
+                level_dbfs(samples) >= self.options.voice_activation_threshold_db
+            }
+        };
+
+        // Frames at the start of the block that are sent before fading out.
+        let sent_frames = if keys.mute {
+            self.hold_frames_remaining = 0;
+            0
+        } else if wants_transmit {
+            self.hold_frames_remaining = self.release_delay_frames();
+            frame_count
+        } else if self.transmitting {
+            let held_frames = self.hold_frames_remaining.min(frame_count);
+            self.hold_frames_remaining -= held_frames;
+            held_frames
+        } else {
+            0
+        };
+        let transmitting = sent_frames > 0;
+
+        let fade_step = 1.0 / (FADE_MS * self.sample_rate / 1_000).max(1) as f32;
+        for (index, frame) in samples.chunks_mut(channels).enumerate() {
+            let target_gain = if index < sent_frames { 1.0 } else { 0.0 };
+            self.gain = if target_gain > self.gain {
+                (self.gain + fade_step).min(target_gain)
+            } else {
+                (self.gain - fade_step).max(target_gain)
+            };
+
+            for sample in frame {
+                *sample *= self.gain;
+            }
+        }
+
+        if transmitting == self.transmitting {
+            return None;
+        }
+
+        self.transmitting = transmitting;
+        Some(transmitting)
+    }
+}
+
+fn level_dbfs(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+
+    let mean_square =
+        samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32;
+    10.0 * mean_square.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PushKeys, TransmitGate, TransmitMode, TransmitOptions};
+
+    const SAMPLE_RATE: usize = 48_000;
+    /// 10 ms at 48 kHz.
+    const FRAME: usize = 480;
+
+    fn gate(mode: TransmitMode, release_delay_ms: u32) -> TransmitGate {
+        TransmitGate::new(
+            TransmitOptions {
+                mode,
+                release_delay_ms,
+                voice_activation_threshold_db: -40.0,
+            },
+            SAMPLE_RATE,
+        )
+    }
+
+    fn keys(talk: bool, mute: bool) -> PushKeys {
+        PushKeys { talk, mute }
+    }
+
+    #[test]
+    fn rejects_out_of_range_options() {
+        let options = |release_delay_ms, voice_activation_threshold_db| TransmitOptions {
+            mode: TransmitMode::VoiceActivation,
+            release_delay_ms,
+            voice_activation_threshold_db,
+        };
+
+        assert!(options(200, -45.0).validate().is_ok());
+        assert!(options(60_000, -45.0).validate().is_err());
+        assert!(options(200, 20.0).validate().is_err());
+        assert!(options(200, f32::NAN).validate().is_err());
+    }
+
+    #[test]
+    fn holds_push_to_talk_for_the_release_delay() {
+        let mut gate = gate(TransmitMode::PushToTalk, 30);
+
+        let mut frame = vec![0.5; FRAME];
+        assert_eq!(gate.process(&mut frame, 1, keys(false, false)), None);
+        assert!(frame.iter().all(|sample| *sample == 0.0));
+
+        let mut frame = vec![0.5; FRAME];
+        assert_eq!(gate.process(&mut frame, 1, keys(true, false)), Some(true));
+        // Faded in over the first 5 ms rather than starting at full level.
+        assert!(frame[0] < 0.01);
+        assert_eq!(frame[FRAME - 1], 0.5);
+
+        // All 30 ms after the release go out at full level.
+        for _ in 0..3 {
+            let mut frame = vec![0.5; FRAME];
+            assert_eq!(gate.process(&mut frame, 1, keys(false, false)), None);
+            assert!(frame.iter().all(|sample| *sample == 0.5));
+        }
+
+        let mut frame = vec![0.5; FRAME];
+        assert_eq!(gate.process(&mut frame, 1, keys(false, false)), Some(false));
+        assert!(frame[0] > 0.49);
+        assert_eq!(frame[FRAME - 1], 0.0);
+    }
+
+    #[test]
+    fn fades_out_where_the_hold_ends_inside_a_block() {
+        let mut gate = gate(TransmitMode::PushToTalk, 25);
+        gate.process(&mut vec![0.5; FRAME], 1, keys(true, false));
+
+        for _ in 0..2 {
+            let mut frame = vec![0.5; FRAME];
+            assert_eq!(gate.process(&mut frame, 1, keys(false, false)), None);
+            assert!(frame.iter().all(|sample| *sample == 0.5));
+        }
+
+        // The last 5 ms of the hold, then the fade.
+        let mut frame = vec![0.5; FRAME];
+        assert_eq!(gate.process(&mut frame, 1, keys(false, false)), None);
+        assert!(frame[..FRAME / 2].iter().all(|sample| *sample == 0.5));
+        assert!(frame[FRAME - 1] < 0.01);
+
+        let mut frame = vec![0.5; FRAME];
+        assert_eq!(gate.process(&mut frame, 1, keys(false, false)), Some(false));
+        assert!(frame.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn push_to_mute_overrides_every_mode() {
+        let mut gate = gate(TransmitMode::Continuous, 200);
+
+        let mut frame = vec![0.5; FRAME * 2];
+        assert_eq!(gate.process(&mut frame, 2, keys(false, false)), Some(true));
+        assert_eq!(gate.process(&mut frame, 2, keys(true, true)), Some(false));
+        assert_eq!(gate.process(&mut frame, 2, keys(false, false)), Some(true));
+    }
+
+    #[test]
+    fn opens_on_voice_above_the_threshold() {
+        let mut gate = gate(TransmitMode::VoiceActivation, 10);
+
+        assert_eq!(
+            gate.process(&mut vec![0.001; FRAME], 1, keys(false, false)),
+            None
+        );
+        assert_eq!(
+            gate.process(&mut vec![0.1; FRAME], 1, keys(false, false)),
+            Some(true)
+        );
+        // Held for the 10 ms release delay, then closed.
+        assert_eq!(
+            gate.process(&mut vec![0.001; FRAME], 1, keys(false, false)),
+            None
+        );
+        assert_eq!(
+            gate.process(&mut vec![0.001; FRAME], 1, keys(false, false)),
+            Some(false)
+        );
+        assert!(!gate.is_transmitting());
+    }
+}
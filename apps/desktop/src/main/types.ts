@@ -202,7 +202,13 @@ export type TGlobalPushKeybindRegistrationResult = {
   errors: string[];
 };
 
-export type TMicDevice = { id: string; label: string };
+export type TMicDevice = {
+  id: string;
+  label: string;
+  isDefault: boolean;
+  channels?: number;
+  sampleRate?: number;
+};
 export type TMicDevicesResult = { devices: TMicDevice[] };
 export type TStartVoiceFilterWithCaptureInput = TStartVoiceFilterInput & {
   deviceId?: string;